@@ -17,8 +17,7 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     const balance = await program.provider.connection.getBalance(program.provider.publicKey);
     console.log(`Wallet balance: ${balance / web3.LAMPORTS_PER_SOL} SOL`);
 
-    // Generate keypairs for the AMM account and mint authority
-    const ammAccount = new web3.Keypair();
+    // Generate a keypair for the mint authority
     const mintAuthority = new web3.Keypair();
 
     // Airdrop SOL to the mint authority if needed
@@ -27,8 +26,13 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     await program.provider.connection.confirmTransaction(airdropSignature);
 
     // Create mint tokens for token A and token B
-    const mintA = await createMint(mintAuthority.publicKey);
-    const mintB = await createMint(mintAuthority.publicKey);
+    let mintA = await createMint(mintAuthority.publicKey);
+    let mintB = await createMint(mintAuthority.publicKey);
+
+    // Pools are keyed by the canonically ordered mint pair
+    if (Buffer.compare(mintA.toBuffer(), mintB.toBuffer()) > 0) {
+      [mintA, mintB] = [mintB, mintA];
+    }
 
     // Create token accounts for the user to hold token A and token B
     const user = pg.wallet; // Using the default wallet in the Playground
@@ -40,19 +44,61 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     await mintTokens(mintA, userAAccount, mintAuthority, 1000);
     await mintTokens(mintB, userBAccount, mintAuthority, 1000);
 
-    // Initialize the AMM with a fee of 30 (representing 0.3%)
+    // Create the global fee-tier table if it does not exist yet
+    const [feeTiers] = web3.PublicKey.findProgramAddressSync([Buffer.from("fee_tiers")], program.programId);
+    if (!(await program.provider.connection.getAccountInfo(feeTiers))) {
+      console.log("Initializing fee-tier table...");
+      await program.methods
+        .initializeFeeTiers()
+        .accounts({
+          feeTiers,
+          authority: user.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    // Initialize the AMM at fee tier 2 (30 bps, representing 0.3%)
     console.log("Initializing AMM...");
-    const fee = new anchor.BN(30); // 0.3% fee
+    const feeTier = 2;
+    const [ammAccount] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("amm"), mintA.toBuffer(), mintB.toBuffer(), Buffer.from([feeTier])],
+      program.programId
+    );
+    const [tokenAVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), ammAccount.toBuffer(), mintA.toBuffer()],
+      program.programId
+    );
+    const [tokenBVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), ammAccount.toBuffer(), mintB.toBuffer()],
+      program.programId
+    );
+    const [lpMint] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("lp_mint"), ammAccount.toBuffer()],
+      program.programId
+    );
     await program.methods
-      .initialize(fee)
+      .initialize(feeTier)
       .accounts({
-        amm: ammAccount.publicKey,
+        feeTiers,
+        amm: ammAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAVault,
+        tokenBVault,
+        lpMint,
         user: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
         systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
       })
-      .signers([ammAccount])
       .rpc();
-    console.log("AMM initialized with fee:", fee.toString());
+    console.log("AMM initialized at fee tier:", feeTier);
+
+    // LP tokens representing pool shares are minted to this account
+    const userLpAccount = await createTokenAccount(lpMint, user.publicKey);
 
     // Add liquidity: Adding 500 units of token A and 500 units of token B
     console.log("Adding liquidity...");
@@ -61,11 +107,26 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     await program.methods
       .addLiquidity(amountA, amountB)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
+        oracle: null,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAReserveAccount: tokenAVault,
+        tokenBReserveAccount: tokenBVault,
         user: user.publicKey,
         userA: userAAccount,
         userB: userBAccount,
+        lpMint,
+        userLp: userLpAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        denylistProgram: null,
+        denylistAccount: null,
+        wsolAccount: null,
+        systemProgram: null,
       })
       .rpc();
     console.log("Liquidity added: 500 Token A and 500 Token B");
@@ -73,28 +134,45 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     // Perform a swap: Swap 100 units of token A for token B
     console.log("Performing swap...");
     const amountIn = new anchor.BN(100);
-    const minimumOutput = new anchor.BN(90); // Example slippage protection: Require at least 90 units out
+    const minimumOutput = new anchor.BN(80); // Example slippage protection: Require at least 80 units out
     const fromAtoB = true; // Swapping from A to B
 
     await program.methods
-      .swap(amountIn, fromAtoB, minimumOutput)
+      .swap(amountIn, fromAtoB, minimumOutput, null, null)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
+        oracle: null,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAVault,
+        tokenBVault,
         user: user.publicKey,
         fromAccount: userAAccount,
         toAccount: userBAccount,
-        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        denylistProgram: null,
+        denylistAccount: null,
+        swapHookProgram: null,
+        referrerAccount: null,
+        referrerStats: null,
+        traderStats: null,
+        governanceStake: null,
+        wsolAccount: null,
+        systemProgram: null,
       })
       .rpc();
 
-    console.log(`Swapped 100 units of Token A for Token B (min output: 90)`);
+    console.log(`Swapped 100 units of Token A for Token B (min output: 80)`);
 
     // Pause the contract (Admin action)
     console.log("Pausing the contract...");
     await program.methods
       .pauseContract(true)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
         user: user.publicKey,
       })
       .rpc();
@@ -107,11 +185,22 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
       await program.methods
         .removeLiquidity(shares)
         .accounts({
-          amm: ammAccount.publicKey,
+          amm: ammAccount,
+          oracle: null,
+          twamm: null,
+          allowlistEntry: null,
+          tokenAReserveAccount: tokenAVault,
+          tokenBReserveAccount: tokenBVault,
           user: user.publicKey,
           userA: userAAccount,
           userB: userBAccount,
+          lpMint,
+          userLp: userLpAccount,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
           tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
         })
         .rpc();
     } catch (err) {
@@ -123,7 +212,7 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     await program.methods
       .pauseContract(false)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
         user: user.publicKey,
       })
       .rpc();
@@ -135,17 +224,28 @@ const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
     await program.methods
       .removeLiquidity(shares)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
+        oracle: null,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAReserveAccount: tokenAVault,
+        tokenBReserveAccount: tokenBVault,
         user: user.publicKey,
         userA: userAAccount,
         userB: userBAccount,
+        lpMint,
+        userLp: userLpAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
       })
       .rpc();
     console.log(`Liquidity removed: 500 shares`);
 
     // Fetch and log the final state of the AMM account
-    const ammState = await program.account.amm.fetch(ammAccount.publicKey);
+    const ammState = await program.account.amm.fetch(ammAccount);
     console.log("Final AMM state:", ammState);
 
   } catch (error) {
@@ -1,24 +1,211 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, TokenAccount, Transfer, Mint, Token};
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::keccak;
+use anchor_lang::solana_program::program::{get_return_data, invoke};
+use anchor_lang::solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked};
+use anchor_lang::system_program::{self, Allocate, Assign, CreateAccount};
+use anchor_lang::Discriminator;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::metadata::{
+    self, mpl_token_metadata::types::DataV2, CreateMasterEditionV3, CreateMetadataAccountsV3, Metadata,
+};
+use anchor_spl::token::{spl_token, Token};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+};
+use anchor_spl::token_interface::{
+    self, Burn, CloseAccount, InitializeAccount3, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked,
+};
+use swap_hook_interface::{PreSwapResult, SwapHookArgs};
 //use anchor_lang::solana_program::program_error::ProgramError;
 
 // Program ID
 declare_id!("HYtYWSrCFTumBJDfzJmBqCuBCPx5brMmtnV4b3qYzQyr");
 
+// PDA seeds
+pub const FEE_TIERS_SEED: &[u8] = b"fee_tiers";
+pub const AMM_SEED: &[u8] = b"amm";
+pub const VAULT_SEED: &[u8] = b"vault";
+pub const ORACLE_SEED: &[u8] = b"oracle";
+pub const LIMIT_ORDER_SEED: &[u8] = b"limit_order";
+pub const LIMIT_ORDER_ESCROW_SEED: &[u8] = b"limit_order_escrow";
+pub const TWAMM_SEED: &[u8] = b"twamm";
+pub const LONG_TERM_ORDER_SEED: &[u8] = b"long_term_order";
+pub const DCA_SEED: &[u8] = b"dca";
+pub const DCA_ESCROW_SEED: &[u8] = b"dca_escrow";
+pub const WSOL_SEED: &[u8] = b"wsol";
+pub const LP_MINT_SEED: &[u8] = b"lp_mint";
+pub const FARM_SEED: &[u8] = b"farm";
+pub const FARM_VAULT_SEED: &[u8] = b"farm_vault";
+pub const FARM_REWARD_VAULT_SEED: &[u8] = b"farm_reward_vault";
+pub const STAKE_SEED: &[u8] = b"stake";
+pub const LOCK_SEED: &[u8] = b"lock";
+pub const LOCK_ESCROW_SEED: &[u8] = b"lock_escrow";
+pub const POSITION_SEED: &[u8] = b"position";
+pub const ALLOWLIST_SEED: &[u8] = b"allowlist";
+pub const REFERRER_SEED: &[u8] = b"referrer";
+pub const TRADER_STATS_SEED: &[u8] = b"trader_stats";
+pub const GOVERNANCE_STAKING_SEED: &[u8] = b"governance_staking";
+pub const GOVERNANCE_VAULT_SEED: &[u8] = b"governance_vault";
+pub const GOVERNANCE_STAKE_SEED: &[u8] = b"governance_stake";
+
+// Fees are expressed in basis points (e.g., 30 for 0.3%). The original swap math divided by 1000
+// (per mille), so a stored fee of 30 used to charge 3%; pools now take their fee from the fee-tier
+// table in basis points, and pools created before the fee tiers must be recreated.
+pub const FEE_DENOMINATOR: u64 = 10_000;
+
+// Maximum number of fee tiers the global table can hold
+pub const MAX_FEE_TIERS: usize = 16;
+
+// Fee tiers seeded when the table is created: 0.01%, 0.05%, 0.3% and 1%
+pub const DEFAULT_FEE_TIERS: [u64; 4] = [1, 5, 30, 100];
+
+// Upper bound on the number of observations an oracle ring buffer can hold
+pub const MAX_ORACLE_CARDINALITY: u16 = 1024;
+
+// Routing limits: each hop is passed as [amm, token_a_vault, token_b_vault, token_a_mint, token_b_mint,
+// token_a_program, token_b_program, oracle] in remaining accounts, with this program's ID in place of the
+// oracle for pools without one
+pub const MAX_ROUTE_HOPS: usize = 4;
+pub const ROUTE_ACCOUNTS_PER_HOP: usize = 8;
+
+// Split swaps: up to MAX_SPLIT_LEGS pools, allocations in basis points of the input
+pub const MAX_SPLIT_LEGS: usize = 4;
+pub const ALLOCATION_DENOMINATOR: u64 = 10_000;
+
+// Limit order crank: each order is passed as [order, escrow, destination, owner] in remaining accounts,
+// and the keeper earns a bounty (basis points of the output) for every fill
+pub const LIMIT_ORDER_ACCOUNTS_PER_ORDER: usize = 4;
+pub const LIMIT_ORDER_KEEPER_BOUNTY_BPS: u64 = 10;
+
+// Long-term orders expire on interval boundaries (in slots) so the expiry schedule stays small
+pub const LONG_TERM_ORDER_INTERVAL: u64 = 150;
+pub const MAX_LONG_TERM_ORDER_INTERVALS: u64 = 10_000;
+pub const MAX_TWAMM_EXPIRIES: usize = 32;
+pub const MAX_TWAMM_SETTLEMENT_INTERVALS: u64 = 32; // Active order intervals executed per settlement
+
+// Pool shares are represented one-to-one by LP tokens
+pub const LP_MINT_DECIMALS: u8 = 6;
+
+// Farms: up to MAX_FARM_REWARDS concurrent reward streams; harvest takes each stream as
+// [reward_vault, reward_mint, destination] in remaining accounts
+pub const MAX_FARM_REWARDS: usize = 4;
+pub const FARM_ACCOUNTS_PER_REWARD: usize = 3;
+
+// Locked shares earn trading fees directly, so nothing in a liquidity lock may vest sooner than
+// this many seconds after it is created
+pub const MIN_LIQUIDITY_LOCK_DURATION: i64 = 7 * 86_400;
+
+// Fee discounts: up to MAX_FEE_DISCOUNT_TIERS tiers, earned by volume over a rolling window
+// of VOLUME_WINDOW_DAYS daily buckets or by governance tokens staked with the program
+pub const MAX_FEE_DISCOUNT_TIERS: usize = 4;
+pub const VOLUME_WINDOW_DAYS: usize = 30;
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+// Metadata of position NFTs
+pub const POSITION_NFT_NAME: &str = "AMM LP Position";
+pub const POSITION_NFT_SYMBOL: &str = "AMM-LP";
+
+// Sighash preimage of the instruction flash_swap invokes on the receiver program
+pub const FLASH_SWAP_CALLBACK_PREIMAGE: &[u8] = b"global:flash_swap_callback";
+
+// Sighash preimage of the instruction swaps and deposits invoke on a pool's denylist program
+pub const DENYLIST_CHECK_PREIMAGE: &[u8] = b"global:check_denylist";
+
 #[program]
 mod amm_contract {
     use super::*;
 
-    // Initialize the AMM
-    pub fn initialize(ctx: Context<Initialize>, fee: u64) -> Result<()> {
+    // Create the global fee-tier table; the signer becomes the governance authority
+    pub fn initialize_fee_tiers(ctx: Context<InitializeFeeTiers>) -> Result<()> {
+        let fee_tiers = &mut ctx.accounts.fee_tiers;
+        fee_tiers.authority = ctx.accounts.authority.key();
+        fee_tiers.fees = DEFAULT_FEE_TIERS.to_vec();
+        fee_tiers.bump = ctx.bumps.fee_tiers;
+        Ok(())
+    }
+
+    // Append a new fee tier (governance only). Existing tiers are immutable so pool addresses stay stable.
+    pub fn add_fee_tier(ctx: Context<ManageFeeTiers>, fee: u64) -> Result<()> {
+        let fee_tiers = &mut ctx.accounts.fee_tiers;
+
+        // Input validation
+        if fee >= FEE_DENOMINATOR {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        if fee_tiers.fees.contains(&fee) {
+            return Err(ErrorCode::FeeTierAlreadyExists.into());
+        }
+        if fee_tiers.fees.len() >= MAX_FEE_TIERS {
+            return Err(ErrorCode::FeeTierTableFull.into());
+        }
+
+        fee_tiers.fees.push(fee);
+
+        emit!(FeeTierAddedEvent {
+            fee_tier: (fee_tiers.fees.len() - 1) as u8,
+            fee,
+        });
+
+        Ok(())
+    }
+
+    // Hand the fee-tier table over to a new governance authority
+    pub fn set_fee_tier_authority(ctx: Context<ManageFeeTiers>, new_authority: Pubkey) -> Result<()> {
+        ctx.accounts.fee_tiers.authority = new_authority;
+        Ok(())
+    }
+
+    // Initialize the AMM for a (mint pair, fee tier)
+    pub fn initialize(ctx: Context<Initialize>, fee_tier: u8) -> Result<()> {
+        // Mints must be passed in canonical order so each pair has a single pool per tier
+        if ctx.accounts.token_a_mint.key() >= ctx.accounts.token_b_mint.key() {
+            return Err(ErrorCode::InvalidMintOrder.into());
+        }
+
+        // Token-2022 mints may only carry extensions the pool accounts for
+        check_mint_extensions(&ctx.accounts.token_a_mint)?;
+        check_mint_extensions(&ctx.accounts.token_b_mint)?;
+
+        let fee = *ctx
+            .accounts
+            .fee_tiers
+            .fees
+            .get(fee_tier as usize)
+            .ok_or(ErrorCode::InvalidFeeTier)?;
+
         let amm = &mut ctx.accounts.amm;
         amm.token_a_reserve = 0;
         amm.token_b_reserve = 0;
-        amm.fee = fee; // Set the fee (e.g., 30 for 0.3%)
+        amm.fee = fee; // Fee comes from the selected tier (e.g., 30 for 0.3%)
         amm.total_shares = 0;
         amm.accumulated_fees_a = 0;
         amm.accumulated_fees_b = 0;
         amm.paused = false; // Start in unpaused state
+        amm.admin = ctx.accounts.user.key();
+        amm.token_a_mint = ctx.accounts.token_a_mint.key();
+        amm.token_b_mint = ctx.accounts.token_b_mint.key();
+        amm.token_a_vault = ctx.accounts.token_a_vault.key();
+        amm.token_b_vault = ctx.accounts.token_b_vault.key();
+        amm.fee_tier = fee_tier;
+        amm.bump = ctx.bumps.amm;
+        amm.price_a_cumulative = 0;
+        amm.price_b_cumulative = 0;
+        amm.log_price_cumulative = 0;
+        amm.last_oracle_timestamp = Clock::get()?.unix_timestamp;
+        amm.token_a_decimals = ctx.accounts.token_a_mint.decimals;
+        amm.token_b_decimals = ctx.accounts.token_b_mint.decimals;
+        amm.lp_mint = ctx.accounts.lp_mint.key();
+
+        emit!(PoolCreatedEvent {
+            amm: amm.key(),
+            token_a_mint: amm.token_a_mint,
+            token_b_mint: amm.token_b_mint,
+            fee_tier,
+            fee,
+        });
+
         Ok(())
     }
 
@@ -26,48 +213,92 @@ mod amm_contract {
     pub fn add_liquidity(ctx: Context<AddLiquidity>, amount_a: u64, amount_b: u64) -> Result<()> {
         let amm = &mut ctx.accounts.amm;
 
+        // Permissioned pools only admit allowlisted wallets, and screened pools consult their denylist
+        check_allowlist(amm, ctx.accounts.allowlist_entry.as_ref())?;
+        check_denylist(
+            amm,
+            &ctx.accounts.user,
+            ctx.accounts.denylist_program.as_ref(),
+            ctx.accounts.denylist_account.as_ref(),
+        )?;
+
+        // Reentrancy guard: no deposits while a flash loan is outstanding
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
         // Input validation
         if amount_a == 0 || amount_b == 0 {
             return Err(ErrorCode::InvalidInput.into());
         }
 
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this deposit
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // Credit only what the vaults actually receive from transfer-fee mints
+        let received_a = amount_a - transfer_fee(&ctx.accounts.token_a_mint, amount_a)?;
+        let received_b = amount_b - transfer_fee(&ctx.accounts.token_b_mint, amount_b)?;
+        if received_a == 0 || received_b == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
         // Calculate shares to mint
-        let shares = if amm.total_shares == 0 {
-            amount_a + amount_b // Initial liquidity, 1:1 ratio
-        } else {
-            let share_a = (amount_a * amm.total_shares) / amm.token_a_reserve;
-            let share_b = (amount_b * amm.total_shares) / amm.token_b_reserve;
-            share_a.min(share_b)
-        };
+        let shares = calculate_shares(received_a, received_b, amm)?;
 
         // Update reserves and total shares
-        amm.token_a_reserve += amount_a;
-        amm.token_b_reserve += amount_b;
+        amm.token_a_reserve += received_a;
+        amm.token_b_reserve += received_b;
         amm.total_shares += shares;
 
-        // Transfer tokens to AMM
-        let cpi_accounts_a = Transfer {
-            from: ctx.accounts.user_a.to_account_info(),
-            to: ctx.accounts.token_a_reserve_account.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx_a = CpiContext::new(cpi_program.clone(), cpi_accounts_a);
-        token::transfer(cpi_ctx_a, amount_a)?;
+        // Transfer tokens to AMM; a side without a token account is paid in native SOL
+        let sides = [
+            (&ctx.accounts.user_a, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_reserve_account, &ctx.accounts.token_a_program, amount_a),
+            (&ctx.accounts.user_b, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_reserve_account, &ctx.accounts.token_b_program, amount_b),
+        ];
+        for (user_token, mint, vault, token_program, amount) in sides {
+            match user_token {
+                Some(user_token) => {
+                    let cpi_accounts = TransferChecked {
+                        from: user_token.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: vault.to_account_info(),
+                        authority: ctx.accounts.user.to_account_info(),
+                    };
+                    token_interface::transfer_checked(CpiContext::new(token_program.to_account_info(), cpi_accounts), amount, mint.decimals)?;
+                }
+                None => {
+                    let native_sol = NativeSol::load(
+                        &ctx.accounts.user,
+                        ctx.accounts.wsol_account.as_ref(),
+                        ctx.accounts.system_program.as_ref(),
+                        token_program,
+                        ctx.bumps.wsol_account,
+                    )?;
+                    native_sol.pay(mint, vault, amount)?;
+                }
+            }
+        }
 
-        let cpi_accounts_b = Transfer {
-            from: ctx.accounts.user_b.to_account_info(),
-            to: ctx.accounts.token_b_reserve_account.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
+        // Mint LP tokens for the new shares, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_lp = MintTo {
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            to: ctx.accounts.user_lp.to_account_info(),
+            authority: amm.to_account_info(),
         };
-        let cpi_ctx_b = CpiContext::new(cpi_program, cpi_accounts_b);
-        token::transfer(cpi_ctx_b, amount_b)?;
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::mint_to(CpiContext::new_with_signer(cpi_program, cpi_accounts_lp, signer), shares)?;
 
         // Emit an event for adding liquidity
         emit!(AddLiquidityEvent {
             user: ctx.accounts.user.key(),
-            amount_a,
-            amount_b,
+            amount_a: received_a,
+            amount_b: received_b,
             shares,
         });
 
@@ -75,7 +306,14 @@ mod amm_contract {
     }
 
     // Swap function using the constant product formula
-    pub fn swap(ctx: Context<Swap>, amount_in: u64, from_a_to_b: bool, minimum_output: u64) -> Result<()> {
+    pub fn swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+        amount_in: u64,
+        from_a_to_b: bool,
+        minimum_output: u64,
+        deadline: Option<SwapDeadline>,
+        max_price_impact_bps: Option<u16>,
+    ) -> Result<()> {
         let amm = &mut ctx.accounts.amm;
 
         // Check if the contract is paused
@@ -83,52 +321,232 @@ mod amm_contract {
             return Err(ErrorCode::ContractPaused.into());
         }
 
+        // Permissioned pools only admit allowlisted wallets, and screened pools consult their denylist
+        check_allowlist(amm, ctx.accounts.allowlist_entry.as_ref())?;
+        check_denylist(
+            amm,
+            &ctx.accounts.user,
+            ctx.accounts.denylist_program.as_ref(),
+            ctx.accounts.denylist_account.as_ref(),
+        )?;
+
+        // Reject stale transactions
+        if let Some(deadline) = deadline {
+            let clock = Clock::get()?;
+            let expired = match deadline {
+                SwapDeadline::Slot(slot) => clock.slot > slot,
+                SwapDeadline::UnixTimestamp(timestamp) => clock.unix_timestamp > timestamp,
+            };
+            if expired {
+                return Err(ErrorCode::Expired.into());
+            }
+        }
+
+        // Reentrancy guard: no trading while a flash loan is outstanding
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
         // Input validation
         if amount_in == 0 {
             return Err(ErrorCode::InvalidInput.into());
         }
 
-        let (token_in_reserve, token_out_reserve, accumulated_fees) = if from_a_to_b {
-            let token_in_reserve = amm.token_a_reserve;
-            let token_out_reserve = amm.token_b_reserve;
-            let accumulated_fees = amm.accumulated_fees_b;
-            (token_in_reserve, token_out_reserve, accumulated_fees)
+        // The pool trades only what the vault actually receives from transfer-fee mints
+        let (mint_in, mint_out) = if from_a_to_b {
+            (&ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
         } else {
-            let token_in_reserve = amm.token_b_reserve;
-            let token_out_reserve = amm.token_a_reserve;
-            let accumulated_fees = amm.accumulated_fees_a;
-            (token_in_reserve, token_out_reserve, accumulated_fees)
+            (&ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
         };
+        let amount_received = amount_in - transfer_fee(mint_in, amount_in)?;
+        if amount_received == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
 
-        // Calculate the amount out using the constant product formula
-        let amount_out = calculate_amount_out(amount_in, token_in_reserve, token_out_reserve, amm.fee)?;
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
 
-        // Slippage protection: ensure the amount out is greater than or equal to the minimum output
-        if amount_out < minimum_output {
+        // Accrue the price oracle with the reserves in effect before this trade
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // Traders with enough rolling volume or governance stake pay a discounted fee
+        if let Some(trader_stats) = ctx.accounts.trader_stats.as_mut() {
+            trader_stats.roll(now);
+        }
+        let mut fee = discounted_fee(amm, ctx.accounts.trader_stats.as_deref(), ctx.accounts.governance_stake.as_deref());
+
+        // The pool's swap hook may reject the trade or override the fee for it
+        if amm.swap_hook_program != Pubkey::default() {
+            let (reserve_in, reserve_out) = if from_a_to_b {
+                (amm.token_a_reserve, amm.token_b_reserve)
+            } else {
+                (amm.token_b_reserve, amm.token_a_reserve)
+            };
+            let args = SwapHookArgs {
+                amm: amm.key(),
+                user: ctx.accounts.user.key(),
+                amount_in: amount_received,
+                amount_out: calculate_amount_out(amount_received, reserve_in, reserve_out, fee)?,
+                from_a_to_b,
+            };
+            let hook_program = ctx.accounts.swap_hook_program.as_ref().ok_or(ErrorCode::SwapHookRequired)?;
+            let ix = swap_hook_interface::pre_swap_instruction(hook_program.key(), &args, hook_account_metas(ctx.remaining_accounts))?;
+            invoke_swap_hook(&ix, hook_program, amm, &ctx.accounts.user, ctx.remaining_accounts)?;
+            if let Some((program_id, data)) = get_return_data() {
+                if program_id == hook_program.key() {
+                    let result = PreSwapResult::try_from_slice(&data)?;
+                    if let Some(fee_override) = result.fee_override {
+                        if fee_override >= FEE_DENOMINATOR {
+                            return Err(ErrorCode::InvalidInput.into());
+                        }
+                        fee = fee_override;
+                    }
+                }
+            }
+        }
+
+        // Calculate the amount out using the constant product formula and collect fees. The circuit
+        // breaker checks the trade at the fee actually charged.
+        let reserves_before = (amm.token_a_reserve, amm.token_b_reserve);
+        let protocol_fees_before = if from_a_to_b { amm.accumulated_fees_a } else { amm.accumulated_fees_b };
+        let amount_out = amm.apply_swap_with_fee(amount_received, from_a_to_b, fee)?;
+        let protocol_fee = if from_a_to_b { amm.accumulated_fees_a } else { amm.accumulated_fees_b } - protocol_fees_before;
+
+        // Slippage protection: ensure the amount the user receives is at least the minimum output
+        if amount_out - transfer_fee(mint_out, amount_out)? < minimum_output {
             return Err(ErrorCode::SlippageExceeded.into());
         }
 
-        // Collect fees
-        let fee_amount = amount_in * amm.fee / 1000;
-        if from_a_to_b {
-            amm.accumulated_fees_b += fee_amount;
-            amm.token_a_reserve += amount_in - fee_amount;
-            amm.token_b_reserve -= amount_out;
+        // Price impact protection: limit how far the trade moves the marginal price
+        if let Some(max_price_impact_bps) = max_price_impact_bps {
+            let impact = calculate_price_impact_bps(reserves_before, (amm.token_a_reserve, amm.token_b_reserve));
+            if impact > max_price_impact_bps as u64 {
+                return Err(ErrorCode::PriceImpactTooHigh.into());
+            }
+        }
+
+        let (vault_in, vault_out, program_in, program_out) = if from_a_to_b {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_b_vault, &ctx.accounts.token_a_program, &ctx.accounts.token_b_program)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_a_vault, &ctx.accounts.token_b_program, &ctx.accounts.token_a_program)
+        };
+
+        // Without a token account on either side, the user trades native SOL through a temporary wSOL account
+        let native_sol = if ctx.accounts.from_account.is_none() || ctx.accounts.to_account.is_none() {
+            Some(NativeSol::load(
+                &ctx.accounts.user,
+                ctx.accounts.wsol_account.as_ref(),
+                ctx.accounts.system_program.as_ref(),
+                if ctx.accounts.from_account.is_none() { program_in } else { program_out },
+                ctx.bumps.wsol_account,
+            )?)
         } else {
-            amm.accumulated_fees_a += fee_amount;
-            amm.token_b_reserve += amount_in - fee_amount;
-            amm.token_a_reserve -= amount_out;
+            None
+        };
+
+        // Transfer the input tokens from the user into the pool
+        match (ctx.accounts.from_account.as_ref(), native_sol.as_ref()) {
+            (Some(from_account), _) => {
+                let cpi_accounts_in = TransferChecked {
+                    from: from_account.to_account_info(),
+                    mint: mint_in.to_account_info(),
+                    to: vault_in.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                };
+                let cpi_ctx_in = CpiContext::new(program_in.to_account_info(), cpi_accounts_in);
+                token_interface::transfer_checked(cpi_ctx_in, amount_in, mint_in.decimals)?;
+            }
+            (None, Some(native_sol)) => native_sol.pay(mint_in, vault_in, amount_in)?,
+            (None, None) => return Err(ErrorCode::InvalidNativeSol.into()),
         }
 
-        // Perform the token transfer
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.from_account.to_account_info(),
-            to: ctx.accounts.to_account.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
+        // Transfer the output tokens from the pool to the user, signed by the AMM
+        let to = match (ctx.accounts.to_account.as_ref(), native_sol.as_ref()) {
+            (Some(to_account), _) => to_account.to_account_info(),
+            (None, Some(native_sol)) => {
+                native_sol.open(mint_out, 0)?;
+                native_sol.wsol_account.to_account_info()
+            }
+            (None, None) => return Err(ErrorCode::InvalidNativeSol.into()),
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount_out)?;
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_out = TransferChecked {
+            from: vault_out.to_account_info(),
+            mint: mint_out.to_account_info(),
+            to,
+            authority: amm.to_account_info(),
+        };
+        let cpi_ctx_out = CpiContext::new_with_signer(program_out.to_account_info(), cpi_accounts_out, signer);
+        token_interface::transfer_checked(cpi_ctx_out, amount_out, mint_out.decimals)?;
+
+        // Unwrap the output back to lamports
+        if let (None, Some(native_sol)) = (ctx.accounts.to_account.as_ref(), native_sol.as_ref()) {
+            native_sol.close()?;
+        }
+
+        // Pay the referrer its share of this trade's protocol fee, in the input token
+        let mut referrer = None;
+        let mut referral_fee = 0;
+        if let Some(referrer_account) = ctx.accounts.referrer_account.as_ref() {
+            let stats = ctx.accounts.referrer_stats.as_mut().ok_or(ErrorCode::InvalidReferrer)?;
+            if referrer_account.owner != stats.referrer || referrer_account.mint != mint_in.key() {
+                return Err(ErrorCode::InvalidReferrer.into());
+            }
+            // Gross up the transfer so the referrer nets its share from transfer-fee mints, without
+            // paying out more than the protocol fee
+            let fee_bps = stats.fee_bps.min(amm.max_referral_fee_bps);
+            let share = (protocol_fee as u128 * fee_bps as u128 / FEE_DENOMINATOR as u128) as u64;
+            let referral_transfer = (share + transfer_fee_inverse(mint_in, share)?).min(protocol_fee);
+            if referral_transfer > 0 {
+                let cpi_accounts_referral = TransferChecked {
+                    from: vault_in.to_account_info(),
+                    mint: mint_in.to_account_info(),
+                    to: referrer_account.to_account_info(),
+                    authority: amm.to_account_info(),
+                };
+                let cpi_ctx_referral = CpiContext::new_with_signer(program_in.to_account_info(), cpi_accounts_referral, signer);
+                token_interface::transfer_checked(cpi_ctx_referral, referral_transfer, mint_in.decimals)?;
+                if from_a_to_b {
+                    amm.accumulated_fees_a -= referral_transfer;
+                } else {
+                    amm.accumulated_fees_b -= referral_transfer;
+                }
+                referral_fee = referral_transfer - transfer_fee(mint_in, referral_transfer)?;
+            }
+
+            // Cumulative referral stats
+            stats.swaps += 1;
+            if from_a_to_b {
+                stats.volume_a = stats.volume_a.saturating_add(amount_received);
+                stats.fees_earned_a += referral_fee;
+            } else {
+                stats.volume_b = stats.volume_b.saturating_add(amount_received);
+                stats.fees_earned_b += referral_fee;
+            }
+            referrer = Some(stats.referrer);
+        }
+
+        // Count the trade toward the trader's rolling volume, measured in token B
+        if let Some(trader_stats) = ctx.accounts.trader_stats.as_mut() {
+            trader_stats.record(now, if from_a_to_b { amount_out } else { amount_received });
+        }
+
+        // Let the swap hook observe the completed trade, with the post-trade pool state written back
+        if amm.swap_hook_program != Pubkey::default() {
+            let hook_program = ctx.accounts.swap_hook_program.as_ref().ok_or(ErrorCode::SwapHookRequired)?;
+            amm.exit(&crate::ID)?;
+            let args = SwapHookArgs {
+                amm: amm.key(),
+                user: ctx.accounts.user.key(),
+                amount_in: amount_received,
+                amount_out,
+                from_a_to_b,
+            };
+            let ix = swap_hook_interface::post_swap_instruction(hook_program.key(), &args, hook_account_metas(ctx.remaining_accounts))?;
+            invoke_swap_hook(&ix, hook_program, amm, &ctx.accounts.user, ctx.remaining_accounts)?;
+        }
 
         // Emit an event for the swap
         emit!(SwapEvent {
@@ -136,6 +554,11 @@ mod amm_contract {
             amount_in,
             amount_out,
             from_a_to_b,
+            execution_price: amm.execution_price(amount_received, amount_out, from_a_to_b)?,
+            reserve_a: amm.token_a_reserve,
+            reserve_b: amm.token_b_reserve,
+            referrer,
+            referral_fee,
         });
 
         Ok(())
@@ -145,32 +568,63 @@ mod amm_contract {
     pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, shares: u64) -> Result<()> {
         let amm = &mut ctx.accounts.amm;
 
+        // Permissioned pools only admit allowlisted wallets
+        check_allowlist(amm, ctx.accounts.allowlist_entry.as_ref())?;
+
+        // Reentrancy guard: no withdrawals while a flash loan is outstanding
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if shares == 0 || shares > amm.total_shares {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this withdrawal
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
         // Calculate the amount of tokens to return
-        let amount_a = shares * amm.token_a_reserve / amm.total_shares;
-        let amount_b = shares * amm.token_b_reserve / amm.total_shares;
+        let (amount_a, amount_b) = calculate_withdrawal(shares, amm)?;
 
         // Update reserves and total shares
         amm.token_a_reserve -= amount_a;
         amm.token_b_reserve -= amount_b;
         amm.total_shares -= shares;
 
-        // Transfer tokens back to user
-        let cpi_accounts_a = Transfer {
+        // Burn the user's LP tokens for the withdrawn shares
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_accounts_lp = Burn {
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            from: ctx.accounts.user_lp.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        token_interface::burn(CpiContext::new(cpi_program, cpi_accounts_lp), shares)?;
+
+        // Transfer tokens back to user, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_a = TransferChecked {
             from: ctx.accounts.token_a_reserve_account.to_account_info(),
+            mint: ctx.accounts.token_a_mint.to_account_info(),
             to: ctx.accounts.user_a.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
+            authority: amm.to_account_info(),
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx_a = CpiContext::new(cpi_program.clone(), cpi_accounts_a);
-        token::transfer(cpi_ctx_a, amount_a)?;
+        let cpi_ctx_a = CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer);
+        token_interface::transfer_checked(cpi_ctx_a, amount_a, ctx.accounts.token_a_mint.decimals)?;
 
-        let cpi_accounts_b = Transfer {
+        let cpi_accounts_b = TransferChecked {
             from: ctx.accounts.token_b_reserve_account.to_account_info(),
+            mint: ctx.accounts.token_b_mint.to_account_info(),
             to: ctx.accounts.user_b.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
+            authority: amm.to_account_info(),
         };
-        let cpi_ctx_b = CpiContext::new(cpi_program, cpi_accounts_b);
-        token::transfer(cpi_ctx_b, amount_b)?;
+        let cpi_ctx_b = CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer);
+        token_interface::transfer_checked(cpi_ctx_b, amount_b, ctx.accounts.token_b_mint.decimals)?;
 
         // Emit an event for removing liquidity
         emit!(RemoveLiquidityEvent {
@@ -186,34 +640,39 @@ mod amm_contract {
     // Distribute accumulated fees to liquidity providers
     pub fn distribute_fees(ctx: Context<DistributeFees>) -> Result<()> {
         let amm = &mut ctx.accounts.amm;
-
-        // Transfer accumulated fees to fee_receiver
-        let cpi_accounts_a = Transfer {
-            from: ctx.accounts.fee_reserve_a.to_account_info(),
-            to: ctx.accounts.fee_receiver.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx_a = CpiContext::new(cpi_program.clone(), cpi_accounts_a);
-        token::transfer(cpi_ctx_a, amm.accumulated_fees_a)?;
-
-        let cpi_accounts_b = Transfer {
-            from: ctx.accounts.fee_reserve_b.to_account_info(),
-            to: ctx.accounts.fee_receiver.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_ctx_b = CpiContext::new(cpi_program, cpi_accounts_b);
-        token::transfer(cpi_ctx_b, amm.accumulated_fees_b)?;
+        let amount_a = amm.accumulated_fees_a;
+        let amount_b = amm.accumulated_fees_b;
 
         // Reset accumulated fees
         amm.accumulated_fees_a = 0;
         amm.accumulated_fees_b = 0;
 
+        // Transfer accumulated fees out of the vaults, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_a = TransferChecked {
+            from: ctx.accounts.token_a_vault.to_account_info(),
+            mint: ctx.accounts.token_a_mint.to_account_info(),
+            to: ctx.accounts.fee_receiver_a.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        let cpi_ctx_a = CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer);
+        token_interface::transfer_checked(cpi_ctx_a, amount_a, ctx.accounts.token_a_mint.decimals)?;
+
+        let cpi_accounts_b = TransferChecked {
+            from: ctx.accounts.token_b_vault.to_account_info(),
+            mint: ctx.accounts.token_b_mint.to_account_info(),
+            to: ctx.accounts.fee_receiver_b.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        let cpi_ctx_b = CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer);
+        token_interface::transfer_checked(cpi_ctx_b, amount_b, ctx.accounts.token_b_mint.decimals)?;
+
         // Emit fee distribution event
         emit!(FeeDistributedEvent {
             user: ctx.accounts.user.key(),
-            amount_a: amm.accumulated_fees_a,
-            amount_b: amm.accumulated_fees_b,
+            amount_a,
+            amount_b,
         });
 
         Ok(())
@@ -223,143 +682,5409 @@ mod amm_contract {
     pub fn pause_contract(ctx: Context<AdminAction>, paused: bool) -> Result<()> {
         let amm = &mut ctx.accounts.amm;
         amm.paused = paused;
+
+        // Unpausing clears any circuit breaker trip
+        if !paused {
+            amm.breaches = 0;
+        }
         Ok(())
     }
-}
 
-// Helper function to calculate the output amount based on the constant product formula
-fn calculate_amount_out(amount_in: u64, reserve_in: u64, reserve_out: u64, fee: u64) -> Result<u64> {
-    let amount_in_with_fee = amount_in * (1000 - fee);
-    let numerator = amount_in_with_fee * reserve_out;
-    let denominator = reserve_in * 1000 + amount_in_with_fee;
-    Ok(numerator / denominator)
-}
+    // Configure the per-slot price-movement circuit breaker (admin only); zero disables it
+    pub fn set_circuit_breaker(ctx: Context<AdminAction>, max_price_move_bps: u16, max_breaches: u8) -> Result<()> {
+        if max_price_move_bps as u64 > FEE_DENOMINATOR {
+            return Err(ErrorCode::InvalidInput.into());
+        }
 
-// AMM struct
-#[account]
-pub struct Amm {
-    pub token_a_reserve: u64,
-    pub token_b_reserve: u64,
-    pub fee: u64,
-    pub total_shares: u64,
-    pub accumulated_fees_a: u64,
-    pub accumulated_fees_b: u64,
-    pub paused: bool, // Contract paused state
-}
+        let amm = &mut ctx.accounts.amm;
+        amm.max_price_move_bps = max_price_move_bps;
+        amm.max_breaches = max_breaches;
+        amm.breaches = 0;
+        Ok(())
+    }
 
-// Context for Initialize function
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(init, payer = user, space = 8 + 64)]
-    pub amm: Account<'info, Amm>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+    // Make the pool permissioned (admin only). `authority` manages allowlist entries and
+    // Pubkey::default() makes the pool permissionless again; a non-zero `merkle_root` lets wallets
+    // claim their own entry with a proof.
+    pub fn set_allowlist(ctx: Context<AdminAction>, authority: Pubkey, merkle_root: [u8; 32]) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        amm.allowlist_authority = authority;
+        amm.allowlist_root = merkle_root;
+        check_restrictable(amm)
+    }
 
-// Context for Swap function
-#[derive(Accounts)]
-pub struct Swap<'info> {
-    #[account(mut)]
-    pub amm: Account<'info, Amm>,
-    #[account(mut)]
-    pub from_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub to_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+    // Register the screening program consulted before every swap and deposit (admin only);
+    // Pubkey::default() removes it
+    pub fn set_denylist_program(ctx: Context<AdminAction>, denylist_program: Pubkey) -> Result<()> {
+        ctx.accounts.amm.denylist_program = denylist_program;
+        check_restrictable(&ctx.accounts.amm)
+    }
 
-// Context for AddLiquidity function
-#[derive(Accounts)]
-pub struct AddLiquidity<'info> {
-    #[account(mut)]
-    pub amm: Account<'info, Amm>,
-    #[account(mut)]
-    pub token_a_reserve_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub token_b_reserve_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_a: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_b: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+    // Register the hook program invoked before and after every swap (admin only);
+    // Pubkey::default() removes it
+    pub fn set_swap_hook(ctx: Context<AdminAction>, swap_hook_program: Pubkey) -> Result<()> {
+        ctx.accounts.amm.swap_hook_program = swap_hook_program;
+        check_restrictable(&ctx.accounts.amm)
+    }
 
-// Context for RemoveLiquidity function
-#[derive(Accounts)]
-pub struct RemoveLiquidity<'info> {
-    #[account(mut)]
-    pub amm: Account<'info, Amm>,
-    #[account(mut)]
-    pub token_a_reserve_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub token_b_reserve_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_a: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user_b: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+    // Replace the pool's fee discount schedule (admin only). A trader gets the largest discount
+    // (basis points of the fee) among the tiers whose volume or stake threshold they meet.
+    pub fn set_fee_discounts(ctx: Context<AdminAction>, tiers: Vec<FeeDiscountTier>) -> Result<()> {
+        if tiers.len() > MAX_FEE_DISCOUNT_TIERS {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        if tiers.iter().any(|tier| tier.discount_bps as u64 > FEE_DENOMINATOR) {
+            return Err(ErrorCode::InvalidInput.into());
+        }
 
-// Context for DistributeFees function
-#[derive(Accounts)]
-pub struct DistributeFees<'info> {
-    #[account(mut)]
-    pub amm: Account<'info, Amm>,
-    #[account(mut)]
-    pub fee_reserve_a: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub fee_reserve_b: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub fee_receiver: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+        let amm = &mut ctx.accounts.amm;
+        amm.fee_discounts = [FeeDiscountTier::default(); MAX_FEE_DISCOUNT_TIERS];
+        amm.fee_discounts[..tiers.len()].copy_from_slice(&tiers);
+        Ok(())
+    }
 
-// Context for Admin actions (pausing contract)
-#[derive(Accounts)]
-pub struct AdminAction<'info> {
-    #[account(mut)]
-    pub amm: Account<'info, Amm>,
-    pub user: Signer<'info>,
-}
+    // Cap the share of the protocol fee paid to referrers, in basis points (admin only; 0 disables referrals)
+    pub fn set_referral_fee_cap(ctx: Context<AdminAction>, max_referral_fee_bps: u16) -> Result<()> {
+        if max_referral_fee_bps as u64 > FEE_DENOMINATOR {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        ctx.accounts.amm.max_referral_fee_bps = max_referral_fee_bps;
+        Ok(())
+    }
 
-// Custom error codes for better error handling
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Invalid input.")]
-    InvalidInput,
-    #[msg("Slippage exceeded the allowed limit.")]
-    SlippageExceeded,
-    #[msg("The contract is paused.")]
-    ContractPaused,
-}
+    // Allowlist a wallet (allowlist authority only)
+    pub fn add_allowlist_entry(ctx: Context<AddAllowlistEntry>, wallet: Pubkey) -> Result<()> {
+        let entry = &mut ctx.accounts.allowlist_entry;
+        entry.amm = ctx.accounts.amm.key();
+        entry.wallet = wallet;
+        entry.merkle_root = [0; 32];
+        entry.bump = ctx.bumps.allowlist_entry;
+        Ok(())
+    }
 
-// Events
-#[event]
-pub struct SwapEvent {
-    pub user: Pubkey,
-    pub amount_in: u64,
-    pub amount_out: u64,
-    pub from_a_to_b: bool,
-}
+    // Remove a wallet from the allowlist (allowlist authority only)
+    pub fn remove_allowlist_entry(_ctx: Context<RemoveAllowlistEntry>) -> Result<()> {
+        Ok(())
+    }
 
-#[event]
-pub struct AddLiquidityEvent {
-    pub user: Pubkey,
-    pub amount_a: u64,
-    pub amount_b: u64,
-    pub shares: u64,
-}
+    // Claim an allowlist entry with a Merkle proof of the signer's wallet against the pool's root.
+    // Entries claimed this way lapse when the root changes and can be claimed again.
+    pub fn claim_allowlist_entry(ctx: Context<ClaimAllowlistEntry>, proof: Vec<[u8; 32]>) -> Result<()> {
+        let amm = &ctx.accounts.amm;
+        let wallet = ctx.accounts.wallet.key();
+        if amm.allowlist_root == [0; 32] || !verify_merkle_proof(&proof, amm.allowlist_root, keccak::hash(wallet.as_ref()).0) {
+            return Err(ErrorCode::NotAllowlisted.into());
+        }
 
-#[event]
+        // Entries added by the authority do not lapse; keep them as they are
+        let entry = &mut ctx.accounts.allowlist_entry;
+        if entry.wallet == wallet && entry.merkle_root == [0; 32] {
+            return Ok(());
+        }
+        entry.amm = amm.key();
+        entry.wallet = wallet;
+        entry.merkle_root = amm.allowlist_root;
+        entry.bump = ctx.bumps.allowlist_entry;
+        Ok(())
+    }
+
+    // Quote a swap against live reserves, with the trader's fee discount when their stats or governance
+    // stake are passed and net of transfer fees; the result is returned via set_return_data
+    pub fn quote_swap(ctx: Context<QuoteSwap>, amount_in: u64, from_a_to_b: bool) -> Result<SwapQuote> {
+        let amm = &settled_view(&ctx.accounts.amm, ctx.accounts.twamm.as_deref())?;
+
+        // Mirror the checks performed by swap
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+        if amount_in == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let (mint_in, mint_out) = if from_a_to_b {
+            (&ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+        } else {
+            (&ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+        };
+        let amount_received = amount_in - transfer_fee(mint_in, amount_in)?;
+        if amount_received == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // The stats and the stake must describe the same trader
+        let governance_stake = ctx.accounts.governance_stake.as_deref();
+        let mut trader_stats = ctx.accounts.trader_stats.as_deref().cloned();
+        if let (Some(stats), Some(stake)) = (trader_stats.as_ref(), governance_stake) {
+            if stats.trader != stake.owner {
+                return Err(ErrorCode::InvalidInput.into());
+            }
+        }
+        if let Some(stats) = trader_stats.as_mut() {
+            stats.roll(Clock::get()?.unix_timestamp);
+        }
+        let fee = discounted_fee(amm, trader_stats.as_ref(), governance_stake);
+
+        let (token_in_reserve, token_out_reserve) = if from_a_to_b {
+            (amm.token_a_reserve, amm.token_b_reserve)
+        } else {
+            (amm.token_b_reserve, amm.token_a_reserve)
+        };
+        let amount_out = calculate_amount_out(amount_received, token_in_reserve, token_out_reserve, fee)?;
+
+        Ok(SwapQuote {
+            amount_out: amount_out - transfer_fee(mint_out, amount_out)?,
+            fee_amount: (amount_received as u128 * fee as u128 / FEE_DENOMINATOR as u128) as u64,
+        })
+    }
+
+    // Spot price of the pool normalized by mint decimals; the result is returned via set_return_data
+    pub fn quote_ui_price(ctx: Context<Quote>) -> Result<UiPrice> {
+        let amm = &settled_view(&ctx.accounts.amm, ctx.accounts.twamm.as_deref())?;
+
+        Ok(UiPrice {
+            price_a: amm.ui_price(amm.token_a_reserve, amm.token_b_reserve)?,
+            price_b: calculate_decimal_price(
+                amm.token_b_reserve,
+                amm.token_b_decimals,
+                amm.token_a_reserve,
+                amm.token_a_decimals,
+            )?,
+            token_a_decimals: amm.token_a_decimals,
+            token_b_decimals: amm.token_b_decimals,
+        })
+    }
+
+    // Quote the shares minted for a deposit; the result is returned via set_return_data
+    pub fn quote_add_liquidity(ctx: Context<Quote>, amount_a: u64, amount_b: u64) -> Result<LiquidityQuote> {
+        if amount_a == 0 || amount_b == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        Ok(LiquidityQuote {
+            amount_a,
+            amount_b,
+            shares: calculate_shares(amount_a, amount_b, &settled_view(&ctx.accounts.amm, ctx.accounts.twamm.as_deref())?)?,
+        })
+    }
+
+    // Quote the tokens returned for burning shares; the result is returned via set_return_data
+    pub fn quote_remove_liquidity(ctx: Context<Quote>, shares: u64) -> Result<LiquidityQuote> {
+        let amm = &settled_view(&ctx.accounts.amm, ctx.accounts.twamm.as_deref())?;
+        if shares == 0 || shares > amm.total_shares {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let (amount_a, amount_b) = calculate_withdrawal(shares, amm)?;
+        Ok(LiquidityQuote {
+            amount_a,
+            amount_b,
+            shares,
+        })
+    }
+
+    // Create the observation ring buffer for a pool's TWAP oracle (admin only)
+    pub fn initialize_oracle(ctx: Context<InitializeOracle>, cardinality: u16) -> Result<()> {
+        if cardinality == 0 || cardinality > MAX_ORACLE_CARDINALITY {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let amm = &mut ctx.accounts.amm;
+        let now = Clock::get()?.unix_timestamp;
+        amm.update_price_accumulators(now);
+
+        let oracle = &mut ctx.accounts.oracle;
+        oracle.amm = amm.key();
+        oracle.index = 0;
+        oracle.cardinality = cardinality;
+        oracle.observations = vec![Observation::from_amm(amm, now)];
+        oracle.bump = ctx.bumps.oracle;
+
+        // From now on every reserve change must record an observation
+        amm.oracle_enabled = true;
+        Ok(())
+    }
+
+    // Grow the oracle ring buffer; new slots are used once the write index wraps around
+    pub fn grow_oracle(ctx: Context<GrowOracle>, cardinality: u16) -> Result<()> {
+        let oracle = &mut ctx.accounts.oracle;
+        if cardinality <= oracle.cardinality || cardinality > MAX_ORACLE_CARDINALITY {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        oracle.cardinality = cardinality;
+        Ok(())
+    }
+
+    // View: time-weighted average prices between two points in time, given as seconds ago
+    pub fn observe_twap(ctx: Context<ObserveTwap>, start_seconds_ago: u32, end_seconds_ago: u32) -> Result<TwapResult> {
+        if start_seconds_ago <= end_seconds_ago {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        let start_timestamp = now - start_seconds_ago as i64;
+        let end_timestamp = now - end_seconds_ago as i64;
+
+        let amm = &ctx.accounts.amm;
+        let oracle = &ctx.accounts.oracle;
+        let start = oracle.observe(amm, now, start_timestamp)?;
+        let end = oracle.observe(amm, now, end_timestamp)?;
+
+        let elapsed = (end_timestamp - start_timestamp) as u128;
+        Ok(TwapResult {
+            start_timestamp,
+            end_timestamp,
+            price_a_twap: end.price_a_cumulative.wrapping_sub(start.price_a_cumulative) / elapsed,
+            price_b_twap: end.price_b_cumulative.wrapping_sub(start.price_b_cumulative) / elapsed,
+            log_price_twap: (end.log_price_cumulative.wrapping_sub(start.log_price_cumulative) / elapsed as i128) as i64,
+        })
+    }
+
+    // Lend tokens from a vault; a matching flash_repay must follow later in the same transaction
+    pub fn flash_borrow(ctx: Context<FlashBorrow>, amount: u64, token_a: bool) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard: one outstanding loan per pool
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        let reserve = if token_a { amm.token_a_reserve } else { amm.token_b_reserve };
+        if amount == 0 || amount > reserve {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Instruction introspection: this must be a top-level call followed by a flash_repay for this pool
+        let instructions = ctx.accounts.instructions.to_account_info();
+        let current_index = load_current_index_checked(&instructions)? as usize;
+        let current = load_instruction_at_checked(current_index, &instructions)?;
+        if current.program_id != crate::ID {
+            return Err(ErrorCode::FlashLoanNotRepaid.into());
+        }
+        let mut index = current_index + 1;
+        loop {
+            let ix = load_instruction_at_checked(index, &instructions)
+                .map_err(|_| ErrorCode::FlashLoanNotRepaid)?;
+            if ix.program_id == crate::ID
+                && ix.data.len() >= 8
+                && ix.data[..8] == instruction::FlashRepay::DISCRIMINATOR
+                && ix.accounts.first().map(|meta| meta.pubkey) == Some(amm.key())
+            {
+                break;
+            }
+            index += 1;
+        }
+
+        // Lock the pool until the loan is repaid
+        amm.locked = true;
+        amm.flash_loan_amount = amount;
+        amm.flash_loan_token_a = token_a;
+
+        // Transfer the borrowed tokens to the borrower, signed by the AMM
+        let (vault, mint, token_program) = if token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_program)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_program)
+        };
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts = TransferChecked {
+            from: vault.to_account_info(),
+            mint: mint.to_account_info(),
+            to: ctx.accounts.receiver.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        let cpi_program = token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+
+        Ok(())
+    }
+
+    // Repay an outstanding flash loan plus fee; the fee is credited to liquidity providers
+    pub fn flash_repay(ctx: Context<FlashRepay>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Only a flash_borrow can be repaid; flash_swap holds the lock without an outstanding loan
+        if !amm.locked || amm.flash_loan_amount == 0 {
+            return Err(ErrorCode::NoFlashLoanOutstanding.into());
+        }
+
+        let amount = amm.flash_loan_amount;
+        let token_a = amm.flash_loan_token_a;
+        let fee = calculate_flash_loan_fee(amount, amm.fee)?;
+
+        // Credit the fee to the reserves so LP shares appreciate
+        if token_a {
+            amm.token_a_reserve = amm.token_a_reserve.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
+        } else {
+            amm.token_b_reserve = amm.token_b_reserve.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        // Release the lock
+        amm.locked = false;
+        amm.flash_loan_amount = 0;
+
+        // Transfer principal plus fee back into the vault, grossed up so the vault receives it in full
+        let (vault, mint, token_program) = if token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_program)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_program)
+        };
+        let repayment = amount + fee + transfer_fee_inverse(mint, amount + fee)?;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.repayer.to_account_info(),
+            mint: mint.to_account_info(),
+            to: vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, repayment, mint.decimals)?;
+
+        emit!(FlashLoanEvent {
+            user: ctx.accounts.user.key(),
+            amount,
+            fee,
+            token_a,
+        });
+
+        Ok(())
+    }
+
+    // Uniswap-V2-style flash swap: send output first, call back into the receiver program,
+    // then require the constant product (after fees) to hold on the repaid balances
+    pub fn flash_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashSwap<'info>>,
+        amount_a_out: u64,
+        amount_b_out: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if (amount_a_out == 0 && amount_b_out == 0)
+            || amount_a_out >= amm.token_a_reserve
+            || amount_b_out >= amm.token_b_reserve
+        {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // The receiver only gets the pool read-only; passing it again as a remaining account would
+        // hand the callback a writable pool to re-enter with
+        if ctx.remaining_accounts.iter().any(|account| account.key() == amm.key()) {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this trade
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // The circuit breaker measures the flash swap against the slot's opening reserves
+        amm.roll_price_reference(Clock::get()?.slot);
+
+        // Persist the lock before handing control to the receiver so re-entrant calls see it
+        amm.locked = true;
+        amm.exit(&crate::ID)?;
+
+        // Optimistically transfer the requested output, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        if amount_a_out > 0 {
+            let cpi_accounts_a = TransferChecked {
+                from: ctx.accounts.token_a_vault.to_account_info(),
+                mint: ctx.accounts.token_a_mint.to_account_info(),
+                to: ctx.accounts.receiver_a.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer), amount_a_out, ctx.accounts.token_a_mint.decimals)?;
+        }
+        if amount_b_out > 0 {
+            let cpi_accounts_b = TransferChecked {
+                from: ctx.accounts.token_b_vault.to_account_info(),
+                mint: ctx.accounts.token_b_mint.to_account_info(),
+                to: ctx.accounts.receiver_b.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer), amount_b_out, ctx.accounts.token_b_mint.decimals)?;
+        }
+
+        // Call back into the receiver program using the published layout
+        let callback = FlashSwapCallback {
+            amm: amm.key(),
+            sender: ctx.accounts.user.key(),
+            amount_a_out,
+            amount_b_out,
+            data,
+        };
+        let mut ix_data = flash_swap_callback_discriminator().to_vec();
+        callback.serialize(&mut ix_data)?;
+
+        let mut metas = vec![
+            AccountMeta::new_readonly(amm.key(), false),
+            AccountMeta::new(ctx.accounts.token_a_vault.key(), false),
+            AccountMeta::new(ctx.accounts.token_b_vault.key(), false),
+            AccountMeta::new_readonly(ctx.accounts.user.key(), true),
+        ];
+        let mut infos = vec![
+            amm.to_account_info(),
+            ctx.accounts.token_a_vault.to_account_info(),
+            ctx.accounts.token_b_vault.to_account_info(),
+            ctx.accounts.user.to_account_info(),
+        ];
+        for account in ctx.remaining_accounts.iter() {
+            metas.push(if account.is_writable {
+                AccountMeta::new(account.key(), account.is_signer)
+            } else {
+                AccountMeta::new_readonly(account.key(), account.is_signer)
+            });
+            infos.push(account.clone());
+        }
+        let ix = Instruction {
+            program_id: ctx.accounts.callback_program.key(),
+            accounts: metas,
+            data: ix_data,
+        };
+        invoke(&ix, &infos)?;
+
+        // Read the pool as persisted and the repaid vault balances; the lock must still be held
+        ctx.accounts.amm.reload()?;
+        ctx.accounts.token_a_vault.reload()?;
+        ctx.accounts.token_b_vault.reload()?;
+        let amm = &mut ctx.accounts.amm;
+        if !amm.locked {
+            return Err(ErrorCode::FlashSwapReentered.into());
+        }
+        let balance_a = ctx.accounts.token_a_vault.amount - amm.accumulated_fees_a - amm.locked_fees_a - amm.position_fees_a - amm.long_term_balance_a;
+        let balance_b = ctx.accounts.token_b_vault.amount - amm.accumulated_fees_b - amm.locked_fees_b - amm.position_fees_b - amm.long_term_balance_b;
+
+        // Whatever exceeds the post-transfer reserves was paid in
+        let amount_a_in = balance_a.saturating_sub(amm.token_a_reserve - amount_a_out);
+        let amount_b_in = balance_b.saturating_sub(amm.token_b_reserve - amount_b_out);
+        if amount_a_in == 0 && amount_b_in == 0 {
+            return Err(ErrorCode::InsufficientInputAmount.into());
+        }
+
+        // Fees on the input are set aside exactly as in swap
+        let fee_a = (amount_a_in as u128 * amm.fee as u128 / FEE_DENOMINATOR as u128) as u64;
+        let fee_b = (amount_b_in as u128 * amm.fee as u128 / FEE_DENOMINATOR as u128) as u64;
+        let new_reserve_a = balance_a - fee_a;
+        let new_reserve_b = balance_b - fee_b;
+
+        // Invariant: k after fees must not decrease
+        if (new_reserve_a as u128) * (new_reserve_b as u128) < (amm.token_a_reserve as u128) * (amm.token_b_reserve as u128) {
+            return Err(ErrorCode::InvariantViolated.into());
+        }
+
+        amm.token_a_reserve = new_reserve_a;
+        amm.token_b_reserve = new_reserve_b;
+        amm.collect_fees(fee_a, fee_b);
+        amm.check_price_move()?;
+        amm.locked = false;
+
+        emit!(FlashSwapEvent {
+            user: ctx.accounts.user.key(),
+            callback_program: ctx.accounts.callback_program.key(),
+            amount_a_in,
+            amount_b_in,
+            amount_a_out,
+            amount_b_out,
+        });
+
+        Ok(())
+    }
+
+    // Multi-hop swap through an ordered list of pools passed as remaining accounts.
+    // Intermediate amounts move vault-to-vault; only the final output is checked against minimum_output.
+    pub fn swap_route<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapRoute<'info>>,
+        amount_in: u64,
+        minimum_output: u64,
+    ) -> Result<()> {
+        let accounts = ctx.remaining_accounts;
+
+        // Input validation
+        if amount_in == 0
+            || accounts.is_empty()
+            || !accounts.len().is_multiple_of(ROUTE_ACCOUNTS_PER_HOP)
+            || accounts.len() / ROUTE_ACCOUNTS_PER_HOP > MAX_ROUTE_HOPS
+        {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Load each hop and work out its direction from the token flowing into it
+        let mut hops = load_route(accounts, ctx.accounts.source.mint)?;
+        let last_mint = hops.last().map(|hop| hop.output_mint()).unwrap_or_default();
+        if last_mint != ctx.accounts.destination.mint {
+            return Err(ErrorCode::InvalidRoute.into());
+        }
+
+        // Transfer the input tokens from the user into the first pool
+        let cpi_accounts_in = TransferChecked {
+            from: ctx.accounts.source.to_account_info(),
+            mint: hops[0].mint_in.to_account_info(),
+            to: hops[0].vault_in.clone(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new(hops[0].program_in.clone(), cpi_accounts_in), amount_in, hops[0].mint_in.decimals)?;
+
+        // Each pool trades only what its vault actually receives from transfer-fee mints
+        let now = Clock::get()?.unix_timestamp;
+        let mut amount = amount_in - transfer_fee(&hops[0].mint_in, amount_in)?;
+        for i in 0..hops.len() {
+            let next_vault_in = hops.get(i + 1).map(|hop| hop.vault_in.clone());
+            let hop = &mut hops[i];
+            let amm = &mut hop.amm;
+
+            // Check if the pool is paused or locked
+            if amm.paused {
+                return Err(ErrorCode::ContractPaused.into());
+            }
+            if amm.locked {
+                return Err(ErrorCode::PoolLocked.into());
+            }
+
+            // Accrue the price oracle, then trade against this pool
+            write_oracle(amm, hop.oracle.as_mut(), now)?;
+            let amount_out = amm.apply_swap(amount, hop.from_a_to_b)?;
+
+            // Send the output to the next pool's vault, or to the user on the last hop
+            let to = next_vault_in.unwrap_or_else(|| ctx.accounts.destination.to_account_info());
+            let seeds = amm.signer_seeds();
+            let signer = &[&seeds[..]];
+            let cpi_accounts_out = TransferChecked {
+                from: hop.vault_out.clone(),
+                mint: hop.mint_out.to_account_info(),
+                to,
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(hop.program_out.clone(), cpi_accounts_out, signer), amount_out, hop.mint_out.decimals)?;
+
+            emit!(SwapEvent {
+                user: ctx.accounts.user.key(),
+                amount_in: amount,
+                amount_out,
+                from_a_to_b: hop.from_a_to_b,
+                execution_price: amm.execution_price(amount, amount_out, hop.from_a_to_b)?,
+                reserve_a: amm.token_a_reserve,
+                reserve_b: amm.token_b_reserve,
+                referrer: None,
+                referral_fee: 0,
+            });
+
+            amount = amount_out - transfer_fee(&hop.mint_out, amount_out)?;
+        }
+
+        // Slippage protection on the end-to-end output
+        if amount < minimum_output {
+            return Err(ErrorCode::SlippageExceeded.into());
+        }
+
+        // Persist the updated pools and their oracles
+        for hop in hops.iter() {
+            hop.amm.exit(&crate::ID)?;
+            if let Some(oracle) = hop.oracle.as_ref() {
+                oracle.exit(&crate::ID)?;
+            }
+        }
+
+        emit!(RouteSwapEvent {
+            user: ctx.accounts.user.key(),
+            amount_in,
+            amount_out: amount,
+            hops: hops.len() as u8,
+        });
+
+        Ok(())
+    }
+
+    // Split one order across several pools of the same pair (e.g., different fee tiers).
+    // Pools are passed as remaining accounts like swap_route; allocations are in basis points.
+    pub fn swap_split<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapRoute<'info>>,
+        amount_in: u64,
+        allocations: Vec<u16>,
+        minimum_output: u64,
+    ) -> Result<()> {
+        let accounts = ctx.remaining_accounts;
+
+        // Input validation
+        if amount_in == 0
+            || allocations.is_empty()
+            || allocations.len() > MAX_SPLIT_LEGS
+            || accounts.len() != allocations.len() * ROUTE_ACCOUNTS_PER_HOP
+            || allocations.iter().map(|bps| *bps as u64).sum::<u64>() != ALLOCATION_DENOMINATOR
+        {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Every leg trades the source token for the destination token
+        let input_mint = ctx.accounts.source.mint;
+        let mut legs: Vec<RouteHop<'info>> = Vec::with_capacity(allocations.len());
+        for chunk in accounts.chunks(ROUTE_ACCOUNTS_PER_HOP) {
+            let leg = load_hop(chunk, input_mint, &legs)?;
+            if leg.output_mint() != ctx.accounts.destination.mint {
+                return Err(ErrorCode::InvalidRoute.into());
+            }
+            legs.push(leg);
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        let mut remaining = amount_in;
+        let mut total_out: u64 = 0;
+        for (i, leg) in legs.iter_mut().enumerate() {
+            // The last leg takes any rounding remainder
+            let leg_in = if i + 1 == allocations.len() {
+                remaining
+            } else {
+                amount_in * allocations[i] as u64 / ALLOCATION_DENOMINATOR
+            };
+            remaining -= leg_in;
+            if leg_in == 0 {
+                continue;
+            }
+
+            let amm = &mut leg.amm;
+
+            // Check if the pool is paused or locked
+            if amm.paused {
+                return Err(ErrorCode::ContractPaused.into());
+            }
+            if amm.locked {
+                return Err(ErrorCode::PoolLocked.into());
+            }
+
+            // Accrue the price oracle, then trade what the vault actually receives against this pool
+            write_oracle(amm, leg.oracle.as_mut(), now)?;
+            let leg_received = leg_in - transfer_fee(&leg.mint_in, leg_in)?;
+            let amount_out = amm.apply_swap(leg_received, leg.from_a_to_b)?;
+
+            // Transfer this leg's input from the user into the pool
+            let cpi_accounts_in = TransferChecked {
+                from: ctx.accounts.source.to_account_info(),
+                mint: leg.mint_in.to_account_info(),
+                to: leg.vault_in.clone(),
+                authority: ctx.accounts.user.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new(leg.program_in.clone(), cpi_accounts_in), leg_in, leg.mint_in.decimals)?;
+
+            // Transfer this leg's output from the pool to the user, signed by the AMM
+            let seeds = amm.signer_seeds();
+            let signer = &[&seeds[..]];
+            let cpi_accounts_out = TransferChecked {
+                from: leg.vault_out.clone(),
+                mint: leg.mint_out.to_account_info(),
+                to: ctx.accounts.destination.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(leg.program_out.clone(), cpi_accounts_out, signer), amount_out, leg.mint_out.decimals)?;
+
+            emit!(SwapEvent {
+                user: ctx.accounts.user.key(),
+                amount_in: leg_in,
+                amount_out,
+                from_a_to_b: leg.from_a_to_b,
+                execution_price: amm.execution_price(leg_received, amount_out, leg.from_a_to_b)?,
+                reserve_a: amm.token_a_reserve,
+                reserve_b: amm.token_b_reserve,
+                referrer: None,
+                referral_fee: 0,
+            });
+
+            let amount_received = amount_out - transfer_fee(&leg.mint_out, amount_out)?;
+            total_out = total_out.checked_add(amount_received).ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        // Slippage protection on the aggregated output
+        if total_out < minimum_output {
+            return Err(ErrorCode::SlippageExceeded.into());
+        }
+
+        // Persist the updated pools and their oracles
+        for leg in legs.iter() {
+            leg.amm.exit(&crate::ID)?;
+            if let Some(oracle) = leg.oracle.as_ref() {
+                oracle.exit(&crate::ID)?;
+            }
+        }
+
+        emit!(SplitSwapEvent {
+            user: ctx.accounts.user.key(),
+            amount_in,
+            amount_out: total_out,
+            legs: legs.len() as u8,
+        });
+
+        Ok(())
+    }
+
+    // Single-sided deposit (zap in): swap the optimal part of the input internally, then add liquidity
+    pub fn deposit_single(ctx: Context<DepositSingle>, amount_in: u64, token_a: bool, min_shares_out: u64) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if amount_in == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        if amm.total_shares == 0 {
+            return Err(ErrorCode::InsufficientLiquidity.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this deposit
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // Deposit only what the vault actually receives from transfer-fee mints
+        let (vault_in, mint_in, program_in) = if token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_program)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_program)
+        };
+        let amount_received = amount_in - transfer_fee(mint_in, amount_in)?;
+
+        // Swap the optimal fraction through the curve (fees are charged as in swap)
+        let swap_amount = calculate_zap_swap_amount(amount_received, token_a, amm)?;
+        let amount_out = if swap_amount > 0 {
+            amm.apply_swap(swap_amount, token_a)?
+        } else {
+            0
+        };
+
+        // Deposit the remaining input together with the swap output
+        let (amount_a, amount_b) = if token_a {
+            (amount_received - swap_amount, amount_out)
+        } else {
+            (amount_out, amount_received - swap_amount)
+        };
+        if amount_a == 0 || amount_b == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        let shares = calculate_shares(amount_a, amount_b, amm)?;
+        if shares < min_shares_out {
+            return Err(ErrorCode::SlippageExceeded.into());
+        }
+
+        // Update reserves and total shares
+        amm.token_a_reserve += amount_a;
+        amm.token_b_reserve += amount_b;
+        amm.total_shares += shares;
+
+        // Transfer the full input into the pool; the swapped output never leaves the vault
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_token.to_account_info(),
+            mint: mint_in.to_account_info(),
+            to: vault_in.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new(program_in.to_account_info(), cpi_accounts), amount_in, mint_in.decimals)?;
+
+        // Mint LP tokens for the new shares, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_lp = MintTo {
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            to: ctx.accounts.user_lp.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::mint_to(CpiContext::new_with_signer(cpi_program, cpi_accounts_lp, signer), shares)?;
+
+        emit!(DepositSingleEvent {
+            user: ctx.accounts.user.key(),
+            amount_in,
+            token_a,
+            swap_amount,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    // Single-token withdrawal (zap out): burn shares, then swap the unwanted side back through the curve
+    pub fn withdraw_single(ctx: Context<WithdrawSingle>, shares: u64, token_a: bool, min_out: u64) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if shares == 0 || shares >= amm.total_shares {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this withdrawal
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // Burn shares for the proportional amounts
+        let (amount_a, amount_b) = calculate_withdrawal(shares, amm)?;
+        amm.token_a_reserve -= amount_a;
+        amm.token_b_reserve -= amount_b;
+        amm.total_shares -= shares;
+
+        // Swap the unwanted side back into the pool for the requested token (fees are charged as in swap).
+        // Those tokens never leave the vault, so only the reserve accounting changes.
+        let (kept, unwanted) = if token_a { (amount_a, amount_b) } else { (amount_b, amount_a) };
+        let swapped = if unwanted > 0 {
+            amm.apply_swap(unwanted, !token_a)?
+        } else {
+            0
+        };
+
+        // Slippage protection on the amount the user receives
+        let (vault_out, mint_out, program_out) = if token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_program)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_program)
+        };
+        let amount_out = kept + swapped;
+        if amount_out - transfer_fee(mint_out, amount_out)? < min_out {
+            return Err(ErrorCode::SlippageExceeded.into());
+        }
+
+        // Burn the user's LP tokens for the withdrawn shares
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_accounts_lp = Burn {
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            from: ctx.accounts.user_lp.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        token_interface::burn(CpiContext::new(cpi_program, cpi_accounts_lp), shares)?;
+
+        // Transfer the requested token to the user, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts = TransferChecked {
+            from: vault_out.to_account_info(),
+            mint: mint_out.to_account_info(),
+            to: ctx.accounts.user_token.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(program_out.to_account_info(), cpi_accounts, signer), amount_out, mint_out.decimals)?;
+
+        emit!(WithdrawSingleEvent {
+            user: ctx.accounts.user.key(),
+            shares,
+            token_a,
+            amount_out,
+        });
+
+        Ok(())
+    }
+
+    // Imbalanced withdrawal: take exact amounts of each token, burning shares by the drop in sqrt(k).
+    // The swap fee is charged on the portion that deviates from a proportional withdrawal.
+    pub fn withdraw_imbalanced(ctx: Context<WithdrawImbalanced>, amount_a: u64, amount_b: u64, max_shares_burned: u64) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if (amount_a == 0 && amount_b == 0) || amount_a >= amm.token_a_reserve || amount_b >= amm.token_b_reserve {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this withdrawal
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // An imbalanced withdrawal moves the price like a swap, so the circuit breaker applies
+        amm.roll_price_reference(Clock::get()?.slot);
+
+        let (shares, fee_a, fee_b) = calculate_imbalanced_withdrawal(amount_a, amount_b, amm)?;
+        if shares > max_shares_burned {
+            return Err(ErrorCode::SlippageExceeded.into());
+        }
+        if shares >= amm.total_shares {
+            return Err(ErrorCode::InsufficientLiquidity.into());
+        }
+
+        // Update reserves and total shares; fees are set aside like swap fees
+        amm.token_a_reserve -= amount_a + fee_a;
+        amm.token_b_reserve -= amount_b + fee_b;
+        amm.collect_fees(fee_a, fee_b);
+        amm.total_shares -= shares;
+        amm.check_price_move()?;
+
+        // Burn the user's LP tokens for the shares consumed
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_accounts_lp = Burn {
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            from: ctx.accounts.user_lp.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        token_interface::burn(CpiContext::new(cpi_program, cpi_accounts_lp), shares)?;
+
+        // Transfer tokens back to user, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        if amount_a > 0 {
+            let cpi_accounts_a = TransferChecked {
+                from: ctx.accounts.token_a_vault.to_account_info(),
+                mint: ctx.accounts.token_a_mint.to_account_info(),
+                to: ctx.accounts.user_a.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer), amount_a, ctx.accounts.token_a_mint.decimals)?;
+        }
+        if amount_b > 0 {
+            let cpi_accounts_b = TransferChecked {
+                from: ctx.accounts.token_b_vault.to_account_info(),
+                mint: ctx.accounts.token_b_mint.to_account_info(),
+                to: ctx.accounts.user_b.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer), amount_b, ctx.accounts.token_b_mint.decimals)?;
+        }
+
+        // Emit an event for removing liquidity
+        emit!(RemoveLiquidityEvent {
+            user: ctx.accounts.user.key(),
+            amount_a,
+            amount_b,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    // Place a limit order: escrow `amount` of one token, to be sold for at least `min_amount_out`
+    pub fn place_limit_order(
+        ctx: Context<PlaceLimitOrder>,
+        order_id: u64,
+        sell_token_a: bool,
+        amount: u64,
+        min_amount_out: u64,
+    ) -> Result<()> {
+        // Input validation
+        if amount == 0 || min_amount_out == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let amm = &ctx.accounts.amm;
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+        let (sell_mint, buy_mint) = if sell_token_a {
+            (amm.token_a_mint, amm.token_b_mint)
+        } else {
+            (amm.token_b_mint, amm.token_a_mint)
+        };
+        if ctx.accounts.sell_mint.key() != sell_mint || ctx.accounts.destination.mint != buy_mint {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let order = &mut ctx.accounts.order;
+        order.owner = ctx.accounts.owner.key();
+        order.amm = amm.key();
+        order.escrow = ctx.accounts.escrow.key();
+        order.destination = ctx.accounts.destination.key();
+        order.order_id = order_id;
+        order.sell_token_a = sell_token_a;
+        order.amount = amount;
+        order.min_amount_out = min_amount_out;
+        order.bump = ctx.bumps.order;
+
+        // Move the tokens being sold into the order's escrow, grossed up so the escrow holds `amount`
+        let deposit = amount + transfer_fee_inverse(&ctx.accounts.sell_mint, amount)?;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_token.to_account_info(),
+            mint: ctx.accounts.sell_mint.to_account_info(),
+            to: ctx.accounts.escrow.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), deposit, ctx.accounts.sell_mint.decimals)?;
+
+        Ok(())
+    }
+
+    // Cancel a resting limit order and refund the escrow to the owner
+    pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>) -> Result<()> {
+        let order = &ctx.accounts.order;
+        let order_id = order.order_id.to_le_bytes();
+        let seeds = [
+            LIMIT_ORDER_SEED,
+            order.amm.as_ref(),
+            order.owner.as_ref(),
+            order_id.as_ref(),
+            std::slice::from_ref(&order.bump),
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        // Refund the whole escrow balance: tokens sent to the escrow on top of the order would block its close
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.escrow.to_account_info(),
+            mint: ctx.accounts.sell_mint.to_account_info(),
+            to: ctx.accounts.owner_token.to_account_info(),
+            authority: order.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer), ctx.accounts.escrow.amount, ctx.accounts.sell_mint.decimals)?;
+
+        let cpi_accounts_close = CloseAccount {
+            account: ctx.accounts.escrow.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
+            authority: order.to_account_info(),
+        };
+        token_interface::close_account(CpiContext::new_with_signer(cpi_program, cpi_accounts_close, signer))?;
+
+        Ok(())
+    }
+
+    // Permissionless crank: fill every passed order whose price the pool can now meet.
+    // Orders that do not cross are skipped; the keeper earns a bounty on each fill.
+    pub fn crank_limit_orders<'info>(ctx: Context<'_, '_, 'info, 'info, CrankLimitOrders<'info>>) -> Result<()> {
+        let accounts = ctx.remaining_accounts;
+        if accounts.is_empty() || !accounts.len().is_multiple_of(LIMIT_ORDER_ACCOUNTS_PER_ORDER) {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before any fills
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        for chunk in accounts.chunks(LIMIT_ORDER_ACCOUNTS_PER_ORDER) {
+            let order: Account<'info, LimitOrder> = Account::try_from(&chunk[0])?;
+            let (escrow, destination, owner) = (&chunk[1], &chunk[2], &chunk[3]);
+            if order.amm != amm.key()
+                || escrow.key() != order.escrow
+                || destination.key() != order.destination
+                || owner.key() != order.owner
+            {
+                return Err(ErrorCode::InvalidInput.into());
+            }
+
+            // Skip orders the pool cannot fill at their limit price (after the keeper bounty).
+            // The pool trades only what its vault actually receives from transfer-fee mints.
+            let (reserve_in, reserve_out, mint_in, mint_out) = if order.sell_token_a {
+                (amm.token_a_reserve, amm.token_b_reserve, &ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+            } else {
+                (amm.token_b_reserve, amm.token_a_reserve, &ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+            };
+            let amount_received = order.amount - transfer_fee(mint_in, order.amount)?;
+            let quote = calculate_amount_out(amount_received, reserve_in, reserve_out, amm.fee)?;
+            let bounty = (quote as u128 * LIMIT_ORDER_KEEPER_BOUNTY_BPS as u128 / FEE_DENOMINATOR as u128) as u64;
+            if quote - bounty < order.min_amount_out {
+                continue;
+            }
+
+            // Execute the fill against the pool
+            let amount_out = amm.apply_swap(amount_received, order.sell_token_a)?;
+            let (vault_in, vault_out, keeper_token) = if order.sell_token_a {
+                (&ctx.accounts.token_a_vault, &ctx.accounts.token_b_vault, &ctx.accounts.keeper_token_b)
+            } else {
+                (&ctx.accounts.token_b_vault, &ctx.accounts.token_a_vault, &ctx.accounts.keeper_token_a)
+            };
+            let (program_in, program_out) = if order.sell_token_a {
+                (ctx.accounts.token_a_program.to_account_info(), ctx.accounts.token_b_program.to_account_info())
+            } else {
+                (ctx.accounts.token_b_program.to_account_info(), ctx.accounts.token_a_program.to_account_info())
+            };
+
+            // Move the escrowed tokens into the pool and close the escrow, signed by the order. The whole
+            // balance goes in so tokens sent to the escrow on top of the order cannot block the close;
+            // the pool only books the order amount.
+            let escrow_balance = InterfaceAccount::<TokenAccount>::try_from(escrow)?.amount;
+            let order_id = order.order_id.to_le_bytes();
+            let order_seeds = [
+                LIMIT_ORDER_SEED,
+                order.amm.as_ref(),
+                order.owner.as_ref(),
+                order_id.as_ref(),
+                std::slice::from_ref(&order.bump),
+            ];
+            let order_signer = &[&order_seeds[..]];
+            let cpi_accounts_in = TransferChecked {
+                from: escrow.clone(),
+                mint: mint_in.to_account_info(),
+                to: vault_in.to_account_info(),
+                authority: order.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(program_in.clone(), cpi_accounts_in, order_signer), escrow_balance, mint_in.decimals)?;
+            let cpi_accounts_close = CloseAccount {
+                account: escrow.clone(),
+                destination: owner.clone(),
+                authority: order.to_account_info(),
+            };
+            token_interface::close_account(CpiContext::new_with_signer(program_in, cpi_accounts_close, order_signer))?;
+
+            // Pay the owner and the keeper from the pool, signed by the AMM
+            let seeds = amm.signer_seeds();
+            let signer = &[&seeds[..]];
+            let cpi_accounts_out = TransferChecked {
+                from: vault_out.to_account_info(),
+                mint: mint_out.to_account_info(),
+                to: destination.clone(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(program_out.clone(), cpi_accounts_out, signer), amount_out - bounty, mint_out.decimals)?;
+            if bounty > 0 {
+                let cpi_accounts_bounty = TransferChecked {
+                    from: vault_out.to_account_info(),
+                    mint: mint_out.to_account_info(),
+                    to: keeper_token.to_account_info(),
+                    authority: amm.to_account_info(),
+                };
+                token_interface::transfer_checked(CpiContext::new_with_signer(program_out, cpi_accounts_bounty, signer), bounty, mint_out.decimals)?;
+            }
+
+            emit!(LimitOrderFilledEvent {
+                order: order.key(),
+                owner: order.owner,
+                keeper: ctx.accounts.keeper.key(),
+                sell_token_a: order.sell_token_a,
+                amount_in: order.amount,
+                amount_out: amount_out - bounty,
+                keeper_bounty: bounty,
+            });
+
+            // The order is complete; return its rent to the owner
+            order.close(owner.clone())?;
+        }
+
+        Ok(())
+    }
+
+    // Create a DCA schedule: swap `amount_per_cycle` every `interval` seconds for `total_cycles` cycles.
+    // The full amount is escrowed up front; the first cycle can execute immediately.
+    pub fn create_dca_schedule(
+        ctx: Context<CreateDcaSchedule>,
+        schedule_id: u64,
+        sell_token_a: bool,
+        amount_per_cycle: u64,
+        interval: i64,
+        total_cycles: u64,
+        min_amount_out_per_cycle: u64,
+    ) -> Result<()> {
+        // Input validation
+        if amount_per_cycle == 0 || interval <= 0 || total_cycles == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        let total_amount = amount_per_cycle.checked_mul(total_cycles).ok_or(ErrorCode::MathOverflow)?;
+
+        let amm = &ctx.accounts.amm;
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+        let (sell_mint, buy_mint) = if sell_token_a {
+            (amm.token_a_mint, amm.token_b_mint)
+        } else {
+            (amm.token_b_mint, amm.token_a_mint)
+        };
+        if ctx.accounts.sell_mint.key() != sell_mint || ctx.accounts.destination.mint != buy_mint {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let dca = &mut ctx.accounts.dca;
+        dca.owner = ctx.accounts.owner.key();
+        dca.amm = amm.key();
+        dca.escrow = ctx.accounts.escrow.key();
+        dca.destination = ctx.accounts.destination.key();
+        dca.schedule_id = schedule_id;
+        dca.sell_token_a = sell_token_a;
+        dca.amount_per_cycle = amount_per_cycle;
+        dca.min_amount_out_per_cycle = min_amount_out_per_cycle;
+        dca.interval = interval;
+        dca.cycles_remaining = total_cycles;
+        dca.next_cycle_timestamp = Clock::get()?.unix_timestamp;
+        dca.bump = ctx.bumps.dca;
+
+        // Pre-fund the escrow with every cycle's input, grossed up for transfer-fee mints
+        let total_amount = total_amount + transfer_fee_inverse(&ctx.accounts.sell_mint, total_amount)?;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_token.to_account_info(),
+            mint: ctx.accounts.sell_mint.to_account_info(),
+            to: ctx.accounts.escrow.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), total_amount, ctx.accounts.sell_mint.decimals)?;
+
+        Ok(())
+    }
+
+    // Permissionless crank: execute the next cycle of a DCA schedule once its interval has elapsed.
+    // The schedule and its escrow are closed after the final cycle.
+    pub fn execute_dca_cycle(ctx: Context<ExecuteDcaCycle>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let dca = &mut ctx.accounts.dca;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // The cycle must be due
+        let now = Clock::get()?.unix_timestamp;
+        if now < dca.next_cycle_timestamp {
+            return Err(ErrorCode::DcaCycleNotDue.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this trade
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        let (vault_in, vault_out, mint_in, mint_out) = if dca.sell_token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_b_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_a_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+        };
+        let (program_in, program_out) = if dca.sell_token_a {
+            (ctx.accounts.token_a_program.to_account_info(), ctx.accounts.token_b_program.to_account_info())
+        } else {
+            (ctx.accounts.token_b_program.to_account_info(), ctx.accounts.token_a_program.to_account_info())
+        };
+
+        // Execute the cycle through the regular swap path, trading what the vault actually receives
+        let amount_in = dca.amount_per_cycle;
+        let amount_out = amm.apply_swap(amount_in - transfer_fee(mint_in, amount_in)?, dca.sell_token_a)?;
+
+        // Slippage protection per cycle, on the amount the owner receives
+        if amount_out - transfer_fee(mint_out, amount_out)? < dca.min_amount_out_per_cycle {
+            return Err(ErrorCode::SlippageExceeded.into());
+        }
+
+        // Move this cycle's input from the escrow into the pool, signed by the schedule. The final cycle
+        // sweeps the whole balance so tokens sent to the escrow on top of the schedule cannot block its
+        // close; the pool only books the cycle amount.
+        let transfer_amount = if dca.cycles_remaining == 1 { ctx.accounts.escrow.amount } else { amount_in };
+        let (dca_amm, dca_owner) = (dca.amm, dca.owner);
+        let schedule_id = dca.schedule_id.to_le_bytes();
+        let dca_bump = dca.bump;
+        let dca_seeds = [
+            DCA_SEED,
+            dca_amm.as_ref(),
+            dca_owner.as_ref(),
+            schedule_id.as_ref(),
+            std::slice::from_ref(&dca_bump),
+        ];
+        let dca_signer = &[&dca_seeds[..]];
+        let cpi_accounts_in = TransferChecked {
+            from: ctx.accounts.escrow.to_account_info(),
+            mint: mint_in.to_account_info(),
+            to: vault_in.to_account_info(),
+            authority: dca.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(program_in.clone(), cpi_accounts_in, dca_signer), transfer_amount, mint_in.decimals)?;
+
+        // Pay the owner from the pool, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_out = TransferChecked {
+            from: vault_out.to_account_info(),
+            mint: mint_out.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(program_out, cpi_accounts_out, signer), amount_out, mint_out.decimals)?;
+
+        // Schedule the next cycle
+        dca.cycles_remaining -= 1;
+        dca.next_cycle_timestamp = dca.next_cycle_timestamp.saturating_add(dca.interval);
+
+        emit!(DcaCycleExecutedEvent {
+            schedule: dca.key(),
+            owner: dca.owner,
+            keeper: ctx.accounts.keeper.key(),
+            sell_token_a: dca.sell_token_a,
+            amount_in,
+            amount_out,
+            cycles_remaining: dca.cycles_remaining,
+        });
+
+        // The schedule is complete; close the escrow and return the rent to the owner
+        if dca.cycles_remaining == 0 {
+            let cpi_accounts_close = CloseAccount {
+                account: ctx.accounts.escrow.to_account_info(),
+                destination: ctx.accounts.owner.to_account_info(),
+                authority: dca.to_account_info(),
+            };
+            token_interface::close_account(CpiContext::new_with_signer(program_in, cpi_accounts_close, dca_signer))?;
+            dca.close(ctx.accounts.owner.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    // Cancel a DCA schedule and refund the remaining escrow to the owner
+    pub fn cancel_dca_schedule(ctx: Context<CancelDcaSchedule>) -> Result<()> {
+        let dca = &ctx.accounts.dca;
+        let schedule_id = dca.schedule_id.to_le_bytes();
+        let seeds = [
+            DCA_SEED,
+            dca.amm.as_ref(),
+            dca.owner.as_ref(),
+            schedule_id.as_ref(),
+            std::slice::from_ref(&dca.bump),
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.escrow.to_account_info(),
+            mint: ctx.accounts.sell_mint.to_account_info(),
+            to: ctx.accounts.owner_token.to_account_info(),
+            authority: dca.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer), ctx.accounts.escrow.amount, ctx.accounts.sell_mint.decimals)?;
+
+        let cpi_accounts_close = CloseAccount {
+            account: ctx.accounts.escrow.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
+            authority: dca.to_account_info(),
+        };
+        token_interface::close_account(CpiContext::new_with_signer(cpi_program, cpi_accounts_close, signer))?;
+
+        Ok(())
+    }
+
+    // Enable long-term (TWAMM) orders on a pool (admin only). The minimum sell rates (tokens per slot)
+    // keep dust orders from filling the expiry schedule.
+    pub fn initialize_twamm(ctx: Context<InitializeTwamm>, min_sell_rate_a: u64, min_sell_rate_b: u64) -> Result<()> {
+        if ctx.accounts.amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        let twamm = &mut ctx.accounts.twamm;
+        twamm.amm = ctx.accounts.amm.key();
+        twamm.sell_rate_a = 0;
+        twamm.sell_rate_b = 0;
+        twamm.earnings_per_rate_a = 0;
+        twamm.earnings_per_rate_b = 0;
+        twamm.last_virtual_order_slot = Clock::get()?.slot;
+        twamm.min_sell_rate_a = min_sell_rate_a;
+        twamm.min_sell_rate_b = min_sell_rate_b;
+        twamm.expiries = Vec::new();
+        twamm.bump = ctx.bumps.twamm;
+
+        ctx.accounts.amm.twamm_enabled = true;
+        Ok(())
+    }
+
+    // Place a long-term order selling `amount` evenly over `intervals` order intervals
+    pub fn place_long_term_order(
+        ctx: Context<PlaceLongTermOrder>,
+        order_id: u64,
+        sell_token_a: bool,
+        amount: u64,
+        intervals: u64,
+    ) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let twamm = &mut ctx.accounts.twamm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if amount == 0 || intervals == 0 || intervals > MAX_LONG_TERM_ORDER_INTERVALS {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Settle virtual execution up to now before changing the sell rates. A new rate would also
+        // sell through any stretch still awaiting settlement, so the pool must be caught up first.
+        let slot = Clock::get()?.slot;
+        twamm.execute_virtual_orders(amm, slot)?;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), Clock::get()?.unix_timestamp)?;
+        if twamm.last_virtual_order_slot < slot {
+            return Err(ErrorCode::TwammSettlementPending.into());
+        }
+
+        // Orders expire on an interval boundary; the deposit is rounded down to a whole rate per slot
+        let expiry_slot = (slot / LONG_TERM_ORDER_INTERVAL + intervals) * LONG_TERM_ORDER_INTERVAL;
+        let sell_rate = amount / (expiry_slot - slot);
+        let min_sell_rate = if sell_token_a { twamm.min_sell_rate_a } else { twamm.min_sell_rate_b };
+        if sell_rate == 0 || sell_rate < min_sell_rate {
+            return Err(ErrorCode::LongTermOrderTooSmall.into());
+        }
+        let deposit = sell_rate * (expiry_slot - slot);
+
+        // Register the rate and when it stops
+        let entry = twamm.expiry_entry(expiry_slot)?;
+        if sell_token_a {
+            entry.rate_a += sell_rate;
+        } else {
+            entry.rate_b += sell_rate;
+        }
+        entry.orders += 1;
+        let earnings_checkpoint = if sell_token_a {
+            twamm.sell_rate_a += sell_rate;
+            twamm.earnings_per_rate_a
+        } else {
+            twamm.sell_rate_b += sell_rate;
+            twamm.earnings_per_rate_b
+        };
+
+        let order = &mut ctx.accounts.order;
+        order.owner = ctx.accounts.owner.key();
+        order.amm = amm.key();
+        order.order_id = order_id;
+        order.sell_token_a = sell_token_a;
+        order.sell_rate = sell_rate;
+        order.expiry_slot = expiry_slot;
+        order.earnings_checkpoint = earnings_checkpoint;
+        order.expiry_released = false;
+        order.pending_proceeds = 0;
+        order.bump = ctx.bumps.order;
+
+        // Tokens being sold sit in the pool's vault but outside the reserves
+        let (vault_in, mint_in, program_in) = if sell_token_a {
+            amm.long_term_balance_a += deposit;
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_program)
+        } else {
+            amm.long_term_balance_b += deposit;
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_program)
+        };
+
+        // Gross up the transfer so the vault receives the full deposit from transfer-fee mints
+        let gross_deposit = deposit + transfer_fee_inverse(mint_in, deposit)?;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_token.to_account_info(),
+            mint: mint_in.to_account_info(),
+            to: vault_in.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = program_in.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), gross_deposit, mint_in.decimals)?;
+
+        emit!(LongTermOrderPlacedEvent {
+            order: order.key(),
+            owner: order.owner,
+            sell_token_a,
+            sell_rate,
+            expiry_slot,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw the proceeds of a long-term order; once expired, the order is closed
+    pub fn withdraw_long_term_proceeds(ctx: Context<LongTermOrderAction>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let twamm = &mut ctx.accounts.twamm;
+        let order = &mut ctx.accounts.order;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Settle virtual execution up to now
+        let slot = Clock::get()?.slot;
+        twamm.execute_virtual_orders(amm, slot)?;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), Clock::get()?.unix_timestamp)?;
+
+        // An order has expired once settlement has run past its expiry slot
+        let expired = twamm.last_virtual_order_slot >= order.expiry_slot;
+        if expired && !order.expiry_released {
+            twamm.release_order(order)?;
+        }
+        let proceeds = if order.expiry_released {
+            std::mem::take(&mut order.pending_proceeds)
+        } else {
+            twamm.claim_proceeds(order, false)?
+        };
+
+        // Pay out the proceeds, signed by the AMM
+        let (vault_out, mint_out, program_out) = if order.sell_token_a {
+            amm.long_term_balance_b -= proceeds;
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_b_program)
+        } else {
+            amm.long_term_balance_a -= proceeds;
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_a_program)
+        };
+        if proceeds > 0 {
+            let seeds = amm.signer_seeds();
+            let signer = &[&seeds[..]];
+            let cpi_accounts = TransferChecked {
+                from: vault_out.to_account_info(),
+                mint: mint_out.to_account_info(),
+                to: ctx.accounts.owner_buy_token.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            let cpi_program = program_out.to_account_info();
+            token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer), proceeds, mint_out.decimals)?;
+        }
+
+        emit!(LongTermOrderWithdrawnEvent {
+            order: order.key(),
+            owner: order.owner,
+            proceeds,
+            refund: 0,
+        });
+
+        // A fully executed order has nothing left to claim
+        if expired {
+            order.close(ctx.accounts.owner.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    // Permissionless crank: move an expired order's final proceeds into the order and free its expiry
+    // entry, so orders left unclaimed cannot hold the schedule full. The owner withdraws them later.
+    pub fn release_long_term_order(ctx: Context<ReleaseLongTermOrder>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let twamm = &mut ctx.accounts.twamm;
+        let order = &mut ctx.accounts.order;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Settle virtual execution up to now
+        twamm.execute_virtual_orders(amm, Clock::get()?.slot)?;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), Clock::get()?.unix_timestamp)?;
+
+        if twamm.last_virtual_order_slot < order.expiry_slot {
+            return Err(ErrorCode::LongTermOrderNotExpired.into());
+        }
+        if order.expiry_released {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        twamm.release_order(order)?;
+
+        Ok(())
+    }
+
+    // Permissionless crank: settle long-term orders on a pool that fell more than a settlement's
+    // worth of intervals behind
+    pub fn execute_long_term_orders(ctx: Context<ExecuteLongTermOrders>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        ctx.accounts.twamm.execute_virtual_orders(amm, Clock::get()?.slot)?;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), Clock::get()?.unix_timestamp)?;
+        Ok(())
+    }
+
+    // Cancel a long-term order: refund the unsold tokens, pay out proceeds and close the order
+    pub fn cancel_long_term_order(ctx: Context<LongTermOrderAction>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let twamm = &mut ctx.accounts.twamm;
+        let order = &mut ctx.accounts.order;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Settle virtual execution up to now
+        twamm.execute_virtual_orders(amm, Clock::get()?.slot)?;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), Clock::get()?.unix_timestamp)?;
+
+        // Expired orders have nothing left to cancel
+        let settled_slot = twamm.last_virtual_order_slot;
+        if settled_slot >= order.expiry_slot {
+            return Err(ErrorCode::LongTermOrderExpired.into());
+        }
+
+        // The order has sold up to the last settled slot
+        let proceeds = twamm.claim_proceeds(order, false)?;
+        let refund = order.sell_rate * (order.expiry_slot - settled_slot);
+
+        // Stop selling
+        let entry = twamm.expiry_entry(order.expiry_slot)?;
+        if order.sell_token_a {
+            entry.rate_a -= order.sell_rate;
+            twamm.sell_rate_a -= order.sell_rate;
+        } else {
+            entry.rate_b -= order.sell_rate;
+            twamm.sell_rate_b -= order.sell_rate;
+        }
+        twamm.release_expiry(order.expiry_slot);
+
+        // Refund the unsold side and pay out the proceeds, signed by the AMM
+        let (vault_sell, vault_buy, mint_sell, mint_buy) = if order.sell_token_a {
+            amm.long_term_balance_a -= refund;
+            amm.long_term_balance_b -= proceeds;
+            (&ctx.accounts.token_a_vault, &ctx.accounts.token_b_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+        } else {
+            amm.long_term_balance_b -= refund;
+            amm.long_term_balance_a -= proceeds;
+            (&ctx.accounts.token_b_vault, &ctx.accounts.token_a_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+        };
+        let (program_sell, program_buy) = if order.sell_token_a {
+            (ctx.accounts.token_a_program.to_account_info(), ctx.accounts.token_b_program.to_account_info())
+        } else {
+            (ctx.accounts.token_b_program.to_account_info(), ctx.accounts.token_a_program.to_account_info())
+        };
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        if refund > 0 {
+            let cpi_accounts_refund = TransferChecked {
+                from: vault_sell.to_account_info(),
+                mint: mint_sell.to_account_info(),
+                to: ctx.accounts.owner_sell_token.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(program_sell, cpi_accounts_refund, signer), refund, mint_sell.decimals)?;
+        }
+        if proceeds > 0 {
+            let cpi_accounts_proceeds = TransferChecked {
+                from: vault_buy.to_account_info(),
+                mint: mint_buy.to_account_info(),
+                to: ctx.accounts.owner_buy_token.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(program_buy, cpi_accounts_proceeds, signer), proceeds, mint_buy.decimals)?;
+        }
+
+        emit!(LongTermOrderWithdrawnEvent {
+            order: order.key(),
+            owner: order.owner,
+            proceeds,
+            refund,
+        });
+
+        order.close(ctx.accounts.owner.to_account_info())?;
+        Ok(())
+    }
+
+    // Create the liquidity-mining farm of a pool (admin only); reward streams are added separately
+    pub fn initialize_farm(ctx: Context<InitializeFarm>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        farm.amm = ctx.accounts.amm.key();
+        farm.lp_mint = ctx.accounts.lp_mint.key();
+        farm.stake_vault = ctx.accounts.stake_vault.key();
+        farm.authority = ctx.accounts.user.key();
+        farm.total_staked = 0;
+        farm.rewards = Vec::new();
+        farm.bump = ctx.bumps.farm;
+        Ok(())
+    }
+
+    // Add a reward stream emitting `emission_rate` tokens per second until `end_time`.
+    // The whole emission is deposited up front, so stakers can always harvest what they earned.
+    pub fn add_farm_reward(ctx: Context<AddFarmReward>, emission_rate: u64, end_time: i64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+
+        // Input validation
+        if emission_rate == 0 || end_time <= now {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        let farm = &mut ctx.accounts.farm;
+        if farm.rewards.len() >= MAX_FARM_REWARDS {
+            return Err(ErrorCode::FarmRewardsFull.into());
+        }
+        check_mint_extensions(&ctx.accounts.reward_mint)?;
+        let total_amount = emission_rate.checked_mul((end_time - now) as u64).ok_or(ErrorCode::MathOverflow)?;
+
+        // Bring the existing streams up to date before the new one starts accruing
+        farm.update_rewards(now)?;
+        farm.rewards.push(FarmReward {
+            mint: ctx.accounts.reward_mint.key(),
+            vault: ctx.accounts.reward_vault.key(),
+            emission_rate,
+            end_time,
+            last_update_time: now,
+            reward_per_share: 0,
+        });
+
+        // Fund the full emission, grossed up for transfer-fee mints
+        let total_amount = total_amount + transfer_fee_inverse(&ctx.accounts.reward_mint, total_amount)?;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.funder_token.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), total_amount, ctx.accounts.reward_mint.decimals)?;
+
+        emit!(FarmRewardAddedEvent {
+            farm: farm.key(),
+            mint: ctx.accounts.reward_mint.key(),
+            emission_rate,
+            end_time,
+        });
+
+        Ok(())
+    }
+
+    // Stake LP tokens in the pool's farm; rewards accrue in proportion to stake and time
+    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let farm = &mut ctx.accounts.farm;
+        let position = &mut ctx.accounts.position;
+        if position.owner == Pubkey::default() {
+            position.owner = ctx.accounts.owner.key();
+            position.farm = farm.key();
+            position.bump = ctx.bumps.position;
+        }
+
+        // Settle what the position earned at its previous stake
+        farm.update_rewards(Clock::get()?.unix_timestamp)?;
+        position.settle(farm)?;
+        position.amount = position.amount.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        farm.total_staked = farm.total_staked.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_lp.to_account_info(),
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            to: ctx.accounts.stake_vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), amount, ctx.accounts.lp_mint.decimals)?;
+
+        emit!(StakeEvent {
+            farm: farm.key(),
+            owner: position.owner,
+            amount,
+            total_staked: farm.total_staked,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw staked LP tokens; earned rewards stay pending until harvested
+    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let position = &mut ctx.accounts.position;
+        if amount == 0 || amount > position.amount {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        farm.update_rewards(Clock::get()?.unix_timestamp)?;
+        position.settle(farm)?;
+        position.amount -= amount;
+        farm.total_staked -= amount;
+
+        // Return the LP tokens, signed by the farm
+        let seeds = farm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            to: ctx.accounts.owner_lp.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer), amount, ctx.accounts.lp_mint.decimals)?;
+
+        emit!(UnstakeEvent {
+            farm: farm.key(),
+            owner: position.owner,
+            amount,
+            total_staked: farm.total_staked,
+        });
+
+        Ok(())
+    }
+
+    // Pay out pending rewards for every reward stream passed in remaining accounts
+    pub fn harvest<'info>(ctx: Context<'_, '_, 'info, 'info, Harvest<'info>>) -> Result<()> {
+        let accounts = ctx.remaining_accounts;
+        if accounts.is_empty() || !accounts.len().is_multiple_of(FARM_ACCOUNTS_PER_REWARD) {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let farm = &mut ctx.accounts.farm;
+        let position = &mut ctx.accounts.position;
+        farm.update_rewards(Clock::get()?.unix_timestamp)?;
+        position.settle(farm)?;
+
+        let seeds = farm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        for chunk in accounts.chunks(FARM_ACCOUNTS_PER_REWARD) {
+            let (reward_vault, destination) = (&chunk[0], &chunk[2]);
+            let reward_mint: InterfaceAccount<'info, Mint> = InterfaceAccount::try_from(&chunk[1])?;
+            let index = farm
+                .rewards
+                .iter()
+                .position(|reward| reward.vault == reward_vault.key() && reward.mint == reward_mint.key())
+                .ok_or(ErrorCode::InvalidInput)?;
+
+            let amount = position.pending_rewards[index];
+            if amount == 0 {
+                continue;
+            }
+            position.pending_rewards[index] = 0;
+
+            let cpi_accounts = TransferChecked {
+                from: reward_vault.clone(),
+                mint: reward_mint.to_account_info(),
+                to: destination.clone(),
+                authority: farm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer), amount, reward_mint.decimals)?;
+
+            emit!(HarvestEvent {
+                farm: farm.key(),
+                owner: position.owner,
+                mint: reward_mint.key(),
+                amount,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Lock LP tokens until `unlock_start`, then release them linearly until `unlock_end`
+    // (pass equal timestamps for a single cliff). Locked shares keep earning trading fees, so
+    // `unlock_start` must be at least MIN_LIQUIDITY_LOCK_DURATION away.
+    pub fn lock_liquidity(ctx: Context<LockLiquidity>, lock_id: u64, shares: u64, unlock_start: i64, unlock_end: i64) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        // Reentrancy guard
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        let now = Clock::get()?.unix_timestamp;
+        if shares == 0 || unlock_end < unlock_start || unlock_start < now + MIN_LIQUIDITY_LOCK_DURATION {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        amm.locked_shares += shares;
+
+        let lock = &mut ctx.accounts.lock;
+        lock.owner = ctx.accounts.owner.key();
+        lock.amm = amm.key();
+        lock.escrow = ctx.accounts.escrow.key();
+        lock.lock_id = lock_id;
+        lock.initial_shares = shares;
+        lock.shares = shares;
+        lock.unlock_start = unlock_start;
+        lock.unlock_end = unlock_end;
+        lock.fee_checkpoint_a = amm.locked_fee_growth_a;
+        lock.fee_checkpoint_b = amm.locked_fee_growth_b;
+        lock.fees_owed_a = 0;
+        lock.fees_owed_b = 0;
+        lock.bump = ctx.bumps.lock;
+
+        // Move the LP tokens into the lock's escrow
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_lp.to_account_info(),
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            to: ctx.accounts.escrow.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), shares, ctx.accounts.lp_mint.decimals)?;
+
+        emit!(LiquidityLockedEvent {
+            lock: lock.key(),
+            owner: lock.owner,
+            amm: lock.amm,
+            shares,
+            unlock_start,
+            unlock_end,
+        });
+
+        Ok(())
+    }
+
+    // Release every share of a lock that has vested so far back to the owner
+    pub fn unlock_liquidity(ctx: Context<UnlockLiquidity>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let lock = &mut ctx.accounts.lock;
+
+        let shares = lock.unlocked_shares(Clock::get()?.unix_timestamp);
+        if shares == 0 {
+            return Err(ErrorCode::LiquidityLocked.into());
+        }
+
+        // Settle fees earned on the full locked balance before it shrinks
+        lock.settle_fees(amm);
+        lock.shares -= shares;
+        amm.locked_shares -= shares;
+
+        // Return the vested LP tokens, signed by the lock
+        let (lock_amm, lock_owner) = (lock.amm, lock.owner);
+        let lock_id = lock.lock_id.to_le_bytes();
+        let lock_bump = lock.bump;
+        let seeds = [
+            LOCK_SEED,
+            lock_amm.as_ref(),
+            lock_owner.as_ref(),
+            lock_id.as_ref(),
+            std::slice::from_ref(&lock_bump),
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.escrow.to_account_info(),
+            mint: ctx.accounts.lp_mint.to_account_info(),
+            to: ctx.accounts.owner_lp.to_account_info(),
+            authority: lock.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer), shares, ctx.accounts.lp_mint.decimals)?;
+
+        emit!(LiquidityUnlockedEvent {
+            lock: lock.key(),
+            owner: lock.owner,
+            shares,
+            shares_remaining: lock.shares,
+        });
+
+        Ok(())
+    }
+
+    // Pay a lock owner the trading fees earned by the locked shares
+    pub fn claim_locked_fees(ctx: Context<ClaimLockedFees>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let lock = &mut ctx.accounts.lock;
+
+        lock.settle_fees(amm);
+        let (amount_a, amount_b) = (lock.fees_owed_a, lock.fees_owed_b);
+        lock.fees_owed_a = 0;
+        lock.fees_owed_b = 0;
+        amm.locked_fees_a -= amount_a;
+        amm.locked_fees_b -= amount_b;
+
+        // Transfer the fees out of the vaults, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        if amount_a > 0 {
+            let cpi_accounts_a = TransferChecked {
+                from: ctx.accounts.token_a_vault.to_account_info(),
+                mint: ctx.accounts.token_a_mint.to_account_info(),
+                to: ctx.accounts.owner_a.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer), amount_a, ctx.accounts.token_a_mint.decimals)?;
+        }
+        if amount_b > 0 {
+            let cpi_accounts_b = TransferChecked {
+                from: ctx.accounts.token_b_vault.to_account_info(),
+                mint: ctx.accounts.token_b_mint.to_account_info(),
+                to: ctx.accounts.owner_b.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer), amount_b, ctx.accounts.token_b_mint.decimals)?;
+        }
+
+        emit!(LockedFeesClaimedEvent {
+            lock: lock.key(),
+            owner: lock.owner,
+            amount_a,
+            amount_b,
+        });
+
+        Ok(())
+    }
+
+    // Deposit liquidity into a new position represented by a Metaplex NFT instead of LP tokens.
+    // Whoever holds the NFT owns the position's shares and collects their pro-rata share of every
+    // trade's fee from the pool's position bucket, rather than through distribute_fees.
+    pub fn open_position(ctx: Context<OpenPosition>, amount_a: u64, amount_b: u64, uri: String) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+
+        // Check if the contract is paused
+        if amm.paused {
+            return Err(ErrorCode::ContractPaused.into());
+        }
+
+        if amm.is_restricted() {
+            return Err(ErrorCode::RestrictedPool.into());
+        }
+
+        // Reentrancy guard: no deposits while a flash loan is outstanding
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if amount_a == 0 || amount_b == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this deposit
+        let now = Clock::get()?.unix_timestamp;
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // Credit only what the vaults actually receive from transfer-fee mints
+        let received_a = amount_a - transfer_fee(&ctx.accounts.token_a_mint, amount_a)?;
+        let received_b = amount_b - transfer_fee(&ctx.accounts.token_b_mint, amount_b)?;
+        if received_a == 0 || received_b == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        let shares = calculate_shares(received_a, received_b, amm)?;
+
+        // Update reserves and total shares; position shares earn fees in their own bucket
+        amm.token_a_reserve += received_a;
+        amm.token_b_reserve += received_b;
+        amm.total_shares += shares;
+        amm.position_shares += shares;
+
+        let position = &mut ctx.accounts.position;
+        position.amm = amm.key();
+        position.position_mint = ctx.accounts.position_mint.key();
+        position.shares = shares;
+        position.fee_checkpoint_a = amm.position_fee_growth_a;
+        position.fee_checkpoint_b = amm.position_fee_growth_b;
+        position.fees_owed_a = 0;
+        position.fees_owed_b = 0;
+        position.locked_until = 0;
+        position.bump = ctx.bumps.position;
+
+        // Transfer tokens to AMM
+        let cpi_accounts_a = TransferChecked {
+            from: ctx.accounts.user_a.to_account_info(),
+            mint: ctx.accounts.token_a_mint.to_account_info(),
+            to: ctx.accounts.token_a_vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a), amount_a, ctx.accounts.token_a_mint.decimals)?;
+        let cpi_accounts_b = TransferChecked {
+            from: ctx.accounts.user_b.to_account_info(),
+            mint: ctx.accounts.token_b_mint.to_account_info(),
+            to: ctx.accounts.token_b_vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b), amount_b, ctx.accounts.token_b_mint.decimals)?;
+
+        // Mint the position NFT with its metadata and master edition, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let nft_program = ctx.accounts.nft_token_program.to_account_info();
+        let cpi_accounts_nft = MintTo {
+            mint: ctx.accounts.position_mint.to_account_info(),
+            to: ctx.accounts.position_token.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        token_interface::mint_to(CpiContext::new_with_signer(nft_program.clone(), cpi_accounts_nft, signer), 1)?;
+
+        let metadata_program = ctx.accounts.metadata_program.to_account_info();
+        let cpi_accounts_metadata = CreateMetadataAccountsV3 {
+            metadata: ctx.accounts.metadata.to_account_info(),
+            mint: ctx.accounts.position_mint.to_account_info(),
+            mint_authority: amm.to_account_info(),
+            payer: ctx.accounts.owner.to_account_info(),
+            update_authority: amm.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+        };
+        let data = DataV2 {
+            name: POSITION_NFT_NAME.to_string(),
+            symbol: POSITION_NFT_SYMBOL.to_string(),
+            uri,
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        };
+        metadata::create_metadata_accounts_v3(
+            CpiContext::new_with_signer(metadata_program.clone(), cpi_accounts_metadata, signer),
+            data,
+            true,
+            true,
+            None,
+        )?;
+
+        // The master edition takes over the mint authority, fixing the supply at one
+        let cpi_accounts_edition = CreateMasterEditionV3 {
+            edition: ctx.accounts.master_edition.to_account_info(),
+            mint: ctx.accounts.position_mint.to_account_info(),
+            update_authority: amm.to_account_info(),
+            mint_authority: amm.to_account_info(),
+            payer: ctx.accounts.owner.to_account_info(),
+            metadata: ctx.accounts.metadata.to_account_info(),
+            token_program: nft_program,
+            system_program: ctx.accounts.system_program.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+        };
+        metadata::create_master_edition_v3(CpiContext::new_with_signer(metadata_program, cpi_accounts_edition, signer), Some(0))?;
+
+        emit!(PositionOpenedEvent {
+            position: position.key(),
+            position_mint: position.position_mint,
+            owner: ctx.accounts.owner.key(),
+            amount_a: received_a,
+            amount_b: received_b,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw shares from a position; authorized by holding its NFT
+    pub fn remove_position_liquidity(ctx: Context<PositionAction>, shares: u64) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let position = &mut ctx.accounts.position;
+
+        // Reentrancy guard: no withdrawals while a flash loan is outstanding
+        if amm.locked {
+            return Err(ErrorCode::PoolLocked.into());
+        }
+
+        // Input validation
+        if shares == 0 || shares > position.shares {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        let now = Clock::get()?.unix_timestamp;
+        if now < position.locked_until {
+            return Err(ErrorCode::LiquidityLocked.into());
+        }
+
+        // Settle virtual long-term order execution before touching the reserves
+        settle_long_term_orders(amm, ctx.accounts.twamm.as_mut())?;
+
+        // Accrue the price oracle with the reserves in effect before this withdrawal
+        write_oracle(amm, ctx.accounts.oracle.as_mut(), now)?;
+
+        // Settle fees earned on the full position before it shrinks
+        position.settle_fees(amm);
+        let (amount_a, amount_b) = calculate_withdrawal(shares, amm)?;
+        amm.token_a_reserve -= amount_a;
+        amm.token_b_reserve -= amount_b;
+        amm.total_shares -= shares;
+        amm.position_shares -= shares;
+        position.shares -= shares;
+
+        // Transfer tokens back to the holder, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts_a = TransferChecked {
+            from: ctx.accounts.token_a_vault.to_account_info(),
+            mint: ctx.accounts.token_a_mint.to_account_info(),
+            to: ctx.accounts.owner_a.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer), amount_a, ctx.accounts.token_a_mint.decimals)?;
+        let cpi_accounts_b = TransferChecked {
+            from: ctx.accounts.token_b_vault.to_account_info(),
+            mint: ctx.accounts.token_b_mint.to_account_info(),
+            to: ctx.accounts.owner_b.to_account_info(),
+            authority: amm.to_account_info(),
+        };
+        token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer), amount_b, ctx.accounts.token_b_mint.decimals)?;
+
+        // Emit an event for removing liquidity
+        emit!(RemoveLiquidityEvent {
+            user: ctx.accounts.owner.key(),
+            amount_a,
+            amount_b,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    // Pay the position's NFT holder the trading fees it has earned
+    pub fn collect_position_fees(ctx: Context<PositionAction>) -> Result<()> {
+        let amm = &mut ctx.accounts.amm;
+        let position = &mut ctx.accounts.position;
+
+        position.settle_fees(amm);
+        let (amount_a, amount_b) = (position.fees_owed_a, position.fees_owed_b);
+        position.fees_owed_a = 0;
+        position.fees_owed_b = 0;
+        amm.position_fees_a -= amount_a;
+        amm.position_fees_b -= amount_b;
+
+        // Transfer the fees out of the vaults, signed by the AMM
+        let seeds = amm.signer_seeds();
+        let signer = &[&seeds[..]];
+        if amount_a > 0 {
+            let cpi_accounts_a = TransferChecked {
+                from: ctx.accounts.token_a_vault.to_account_info(),
+                mint: ctx.accounts.token_a_mint.to_account_info(),
+                to: ctx.accounts.owner_a.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_a_program.to_account_info(), cpi_accounts_a, signer), amount_a, ctx.accounts.token_a_mint.decimals)?;
+        }
+        if amount_b > 0 {
+            let cpi_accounts_b = TransferChecked {
+                from: ctx.accounts.token_b_vault.to_account_info(),
+                mint: ctx.accounts.token_b_mint.to_account_info(),
+                to: ctx.accounts.owner_b.to_account_info(),
+                authority: amm.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_b_program.to_account_info(), cpi_accounts_b, signer), amount_b, ctx.accounts.token_b_mint.decimals)?;
+        }
+
+        emit!(PositionFeesCollectedEvent {
+            position: position.key(),
+            owner: ctx.accounts.owner.key(),
+            amount_a,
+            amount_b,
+        });
+
+        Ok(())
+    }
+
+    // Lock a position until `locked_until`; a lock can only be extended. The lock travels with the NFT.
+    pub fn lock_position(ctx: Context<LockPosition>, locked_until: i64) -> Result<()> {
+        let position = &mut ctx.accounts.position;
+        if locked_until <= position.locked_until || locked_until <= Clock::get()?.unix_timestamp {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        position.locked_until = locked_until;
+        Ok(())
+    }
+
+    // Register as a referrer for a pool, or update the requested share of the protocol fee
+    // (basis points, capped by the pool). Swaps routed with one of the referrer's token accounts
+    // pay the referral and accumulate stats here.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>, fee_bps: u16) -> Result<()> {
+        if fee_bps as u64 > FEE_DENOMINATOR {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let stats = &mut ctx.accounts.referrer_stats;
+        if stats.referrer == Pubkey::default() {
+            stats.amm = ctx.accounts.amm.key();
+            stats.referrer = ctx.accounts.referrer.key();
+            stats.bump = ctx.bumps.referrer_stats;
+        }
+        stats.fee_bps = fee_bps;
+        Ok(())
+    }
+
+    // Start tracking a trader's rolling volume on a pool; swaps that pass the stats earn volume discounts
+    pub fn initialize_trader_stats(ctx: Context<InitializeTraderStats>) -> Result<()> {
+        let stats = &mut ctx.accounts.trader_stats;
+        stats.amm = ctx.accounts.amm.key();
+        stats.trader = ctx.accounts.trader.key();
+        stats.daily_volume = [0; VOLUME_WINDOW_DAYS];
+        stats.last_day = Clock::get()?.unix_timestamp / SECONDS_PER_DAY;
+        stats.bump = ctx.bumps.trader_stats;
+        Ok(())
+    }
+
+    // Open governance staking for fee discounts (governance only). Every stake is locked for `lockup`
+    // seconds, so a stake cannot be borrowed for a single discounted trade and withdrawn.
+    pub fn initialize_governance_staking(ctx: Context<InitializeGovernanceStaking>, lockup: i64) -> Result<()> {
+        if lockup <= 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let staking = &mut ctx.accounts.governance_staking;
+        staking.governance_mint = ctx.accounts.governance_mint.key();
+        staking.vault = ctx.accounts.vault.key();
+        staking.lockup = lockup;
+        staking.total_staked = 0;
+        staking.bump = ctx.bumps.governance_staking;
+        Ok(())
+    }
+
+    // Stake governance tokens; the whole stake is locked for the lockup period from now
+    pub fn stake_governance(ctx: Context<StakeGovernance>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+
+        let staking = &mut ctx.accounts.governance_staking;
+        let stake = &mut ctx.accounts.governance_stake;
+        if stake.owner == Pubkey::default() {
+            stake.owner = ctx.accounts.owner.key();
+            stake.bump = ctx.bumps.governance_stake;
+        }
+
+        // Only what reaches the vault counts toward the stake
+        let received = amount - transfer_fee(&ctx.accounts.governance_mint, amount)?;
+        stake.amount = stake.amount.checked_add(received).ok_or(ErrorCode::MathOverflow)?;
+        stake.unlock_time = Clock::get()?.unix_timestamp + staking.lockup;
+        staking.total_staked = staking.total_staked.checked_add(received).ok_or(ErrorCode::MathOverflow)?;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_token.to_account_info(),
+            mint: ctx.accounts.governance_mint.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts), amount, ctx.accounts.governance_mint.decimals)?;
+
+        emit!(GovernanceStakeEvent {
+            owner: stake.owner,
+            amount: stake.amount,
+            unlock_time: stake.unlock_time,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw staked governance tokens once the stake's lockup has ended
+    pub fn unstake_governance(ctx: Context<UnstakeGovernance>, amount: u64) -> Result<()> {
+        let staking = &mut ctx.accounts.governance_staking;
+        let stake = &mut ctx.accounts.governance_stake;
+        if amount == 0 || amount > stake.amount {
+            return Err(ErrorCode::InvalidInput.into());
+        }
+        if Clock::get()?.unix_timestamp < stake.unlock_time {
+            return Err(ErrorCode::StakeLocked.into());
+        }
+
+        stake.amount -= amount;
+        staking.total_staked -= amount;
+
+        // Return the tokens, signed by the staking config
+        let seeds = [GOVERNANCE_STAKING_SEED, std::slice::from_ref(&staking.bump)];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            mint: ctx.accounts.governance_mint.to_account_info(),
+            to: ctx.accounts.owner_token.to_account_info(),
+            authority: staking.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token_interface::transfer_checked(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer), amount, ctx.accounts.governance_mint.decimals)?;
+
+        emit!(GovernanceStakeEvent {
+            owner: stake.owner,
+            amount: stake.amount,
+            unlock_time: stake.unlock_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Permissioned pools require the user's allowlist entry, either added by the allowlist authority
+// or claimed against the current Merkle root
+fn check_allowlist(amm: &Amm, entry: Option<&Account<AllowlistEntry>>) -> Result<()> {
+    if amm.allowlist_authority == Pubkey::default() {
+        return Ok(());
+    }
+    match entry {
+        Some(entry) if entry.merkle_root == [0; 32] || entry.merkle_root == amm.allowlist_root => Ok(()),
+        _ => Err(ErrorCode::NotAllowlisted.into()),
+    }
+}
+
+// Screened pools call their denylist program with the published DenylistCheck layout;
+// the program rejects a user by failing the instruction
+fn check_denylist<'info>(
+    amm: &Account<'info, Amm>,
+    user: &Signer<'info>,
+    denylist_program: Option<&UncheckedAccount<'info>>,
+    denylist_account: Option<&UncheckedAccount<'info>>,
+) -> Result<()> {
+    if amm.denylist_program == Pubkey::default() {
+        return Ok(());
+    }
+    let program = denylist_program.ok_or(ErrorCode::DenylistProgramRequired)?;
+
+    let check = DenylistCheck {
+        amm: amm.key(),
+        user: user.key(),
+    };
+    let mut ix_data = denylist_check_discriminator().to_vec();
+    check.serialize(&mut ix_data)?;
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(amm.key(), false),
+        AccountMeta::new_readonly(user.key(), false),
+    ];
+    let mut infos = vec![amm.to_account_info(), user.to_account_info()];
+    if let Some(account) = denylist_account {
+        metas.push(AccountMeta::new_readonly(account.key(), false));
+        infos.push(account.to_account_info());
+    }
+    let ix = Instruction {
+        program_id: program.key(),
+        accounts: metas,
+        data: ix_data,
+    };
+    invoke(&ix, &infos)?;
+    Ok(())
+}
+
+// Account metas for the remaining accounts passed through to a swap hook
+fn hook_account_metas(remaining_accounts: &[AccountInfo]) -> Vec<AccountMeta> {
+    remaining_accounts
+        .iter()
+        .map(|account| {
+            if account.is_writable {
+                AccountMeta::new(account.key(), account.is_signer)
+            } else {
+                AccountMeta::new_readonly(account.key(), account.is_signer)
+            }
+        })
+        .collect()
+}
+
+// Invoke a swap hook instruction built with the swap_hook_interface crate
+fn invoke_swap_hook<'info>(
+    ix: &Instruction,
+    hook_program: &UncheckedAccount<'info>,
+    amm: &Account<'info, Amm>,
+    user: &Signer<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let mut infos = vec![amm.to_account_info(), user.to_account_info()];
+    infos.extend(remaining_accounts.iter().cloned());
+    infos.push(hook_program.to_account_info());
+    invoke(ix, &infos)?;
+    Ok(())
+}
+
+// Verify a Merkle proof for `leaf`; sibling pairs are hashed in sorted order
+fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).0
+        } else {
+            keccak::hashv(&[sibling, &node]).0
+        }
+    });
+    computed == root
+}
+
+// Helper function to calculate the output amount based on the constant product formula
+fn calculate_amount_out(amount_in: u64, reserve_in: u64, reserve_out: u64, fee: u64) -> Result<u64> {
+    let amount_in_with_fee = amount_in as u128 * (FEE_DENOMINATOR - fee) as u128;
+    let numerator = amount_in_with_fee * reserve_out as u128;
+    let denominator = reserve_in as u128 * FEE_DENOMINATOR as u128 + amount_in_with_fee;
+    if denominator == 0 {
+        return Err(ErrorCode::InsufficientLiquidity.into());
+    }
+    Ok((numerator / denominator) as u64)
+}
+
+// Helper function to calculate the shares minted for a deposit
+fn calculate_shares(amount_a: u64, amount_b: u64, amm: &Amm) -> Result<u64> {
+    if amm.total_shares == 0 {
+        // Initial liquidity: the geometric mean is independent of each mint's decimals
+        return u64::try_from(sqrt_u128(amount_a as u128 * amount_b as u128)).map_err(|_| ErrorCode::MathOverflow.into());
+    }
+    let share_a = amount_a as u128 * amm.total_shares as u128 / amm.token_a_reserve as u128;
+    let share_b = amount_b as u128 * amm.total_shares as u128 / amm.token_b_reserve as u128;
+    u64::try_from(share_a.min(share_b)).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+// Helper function to price `base` in `quote` as Q64.64 after normalizing both by their mint decimals
+fn calculate_decimal_price(base: u64, base_decimals: u8, quote: u64, quote_decimals: u8) -> Result<u128> {
+    if base == 0 {
+        return Err(ErrorCode::InsufficientLiquidity.into());
+    }
+    let raw = ((quote as u128) << 64) / base as u128;
+    let (base_decimals, quote_decimals) = (base_decimals as u32, quote_decimals as u32);
+    let price = if base_decimals >= quote_decimals {
+        10u128.checked_pow(base_decimals - quote_decimals).and_then(|scale| raw.checked_mul(scale))
+    } else {
+        10u128.checked_pow(quote_decimals - base_decimals).map(|scale| raw / scale)
+    };
+    price.ok_or_else(|| ErrorCode::MathOverflow.into())
+}
+
+// Helper function to calculate the tokens returned for burning shares
+fn calculate_withdrawal(shares: u64, amm: &Amm) -> Result<(u64, u64)> {
+    let amount_a = shares as u128 * amm.token_a_reserve as u128 / amm.total_shares as u128;
+    let amount_b = shares as u128 * amm.token_b_reserve as u128 / amm.total_shares as u128;
+    Ok((amount_a as u64, amount_b as u64))
+}
+
+// Helper function to find how much of a single-sided deposit to swap so that the remainder and the
+// swap output match the post-swap reserve ratio. Binary search over the exact on-chain swap math.
+fn calculate_zap_swap_amount(amount_in: u64, token_a: bool, amm: &Amm) -> Result<u64> {
+    let (reserve_in, reserve_out) = if token_a {
+        (amm.token_a_reserve, amm.token_b_reserve)
+    } else {
+        (amm.token_b_reserve, amm.token_a_reserve)
+    };
+
+    // Largest swap amount for which the leftover input still covers the post-swap ratio
+    let (mut low, mut high) = (0u64, amount_in);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        let out = calculate_amount_out(mid, reserve_in, reserve_out, amm.fee)? as u128;
+        let fee_amount = mid as u128 * amm.fee as u128 / FEE_DENOMINATOR as u128;
+        let new_reserve_in = reserve_in as u128 + mid as u128 - fee_amount;
+        let new_reserve_out = reserve_out as u128 - out;
+        if (amount_in - mid) as u128 * new_reserve_out >= out * new_reserve_in {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Ok(low)
+}
+
+// Helper function to price an imbalanced withdrawal against sqrt(k), Curve-style:
+// each side's deviation from the proportional ("ideal") balance pays half the swap fee,
+// since only one side of the imbalance is effectively swapped. Returns (shares, fee_a, fee_b).
+fn calculate_imbalanced_withdrawal(amount_a: u64, amount_b: u64, amm: &Amm) -> Result<(u64, u64, u64)> {
+    let reserve_a = amm.token_a_reserve as u128;
+    let reserve_b = amm.token_b_reserve as u128;
+    let new_a = reserve_a - amount_a as u128;
+    let new_b = reserve_b - amount_b as u128;
+
+    let d0 = sqrt_u128(reserve_a * reserve_b);
+    let d1 = sqrt_u128(new_a * new_b);
+    if d0 == 0 {
+        return Err(ErrorCode::InsufficientLiquidity.into());
+    }
+
+    // Fee on the deviation from a proportional withdrawal
+    let ideal_a = d1 * reserve_a / d0;
+    let ideal_b = d1 * reserve_b / d0;
+    let fee_a = ideal_a.abs_diff(new_a) * amm.fee as u128 / (2 * FEE_DENOMINATOR as u128);
+    let fee_b = ideal_b.abs_diff(new_b) * amm.fee as u128 / (2 * FEE_DENOMINATOR as u128);
+
+    // Shares burned in proportion to the drop in sqrt(k), rounded up in the pool's favour
+    let d2 = sqrt_u128((new_a - fee_a) * (new_b - fee_b));
+    let shares = (amm.total_shares as u128 * (d0 - d2)).div_ceil(d0);
+
+    Ok((
+        u64::try_from(shares).map_err(|_| ErrorCode::MathOverflow)?,
+        fee_a as u64,
+        fee_b as u64,
+    ))
+}
+
+// Integer square root (floor) using Newton's method
+fn sqrt_u128(x: u128) -> u128 {
+    if x < 2 {
+        return x;
+    }
+    let mut estimate = 1u128 << ((128 - x.leading_zeros()).div_ceil(2));
+    loop {
+        let next = (estimate + x / estimate) / 2;
+        if next >= estimate {
+            return estimate;
+        }
+        estimate = next;
+    }
+}
+
+// Helper function to settle long-term orders before a pool's reserves change
+fn settle_long_term_orders(amm: &mut Amm, twamm: Option<&mut Account<TwammState>>) -> Result<()> {
+    match twamm {
+        // Long-term orders only trade while the pool itself may
+        Some(_) if amm.paused => Err(ErrorCode::ContractPaused.into()),
+        Some(_) if amm.locked => Err(ErrorCode::PoolLocked.into()),
+        Some(twamm) => twamm.execute_virtual_orders(amm, Clock::get()?.slot),
+        None if amm.twamm_enabled => Err(ErrorCode::TwammStateRequired.into()),
+        None => Ok(()),
+    }
+}
+
+// Helper function to keep long-term orders off screened or hooked pools: their owners trade through
+// settlement in every entry point, which runs neither the screening nor the hook
+fn check_restrictable(amm: &Amm) -> Result<()> {
+    if amm.is_restricted() && amm.twamm_enabled {
+        return Err(ErrorCode::TwammPoolRestricted.into());
+    }
+    Ok(())
+}
+
+// Helper function returning a copy of the pool with long-term orders settled, for read-only views
+fn settled_view(amm: &Amm, twamm: Option<&TwammState>) -> Result<Amm> {
+    let mut amm = amm.clone();
+    match twamm {
+        Some(twamm) => twamm.clone().execute_virtual_orders(&mut amm, Clock::get()?.slot)?,
+        None if amm.twamm_enabled => return Err(ErrorCode::TwammStateRequired.into()),
+        None => {}
+    }
+    Ok(amm)
+}
+
+// Helper function to calculate a trader's fee after their volume and governance-stake discount; the
+// stats must be rolled to the current day first
+fn discounted_fee(amm: &Amm, trader_stats: Option<&TraderStats>, governance_stake: Option<&GovernanceStake>) -> u64 {
+    let volume = trader_stats.map_or(0, |stats| stats.rolling_volume());
+    let staked = governance_stake.map_or(0, |stake| stake.amount);
+    amm.fee - amm.fee * amm.fee_discount_bps(volume, staked) as u64 / FEE_DENOMINATOR
+}
+
+// Helper function to calculate the part of `fee` owed to `shares` of the pool and add it to that
+// bucket's per-share growth
+fn accrue_fee_share(fee: u64, shares: u64, total_shares: u64, growth: &mut u128) -> u64 {
+    if shares == 0 || total_shares == 0 {
+        return 0;
+    }
+    let owed = (fee as u128 * shares as u128 / total_shares as u128) as u64;
+    *growth = growth.wrapping_add(((owed as u128) << 64) / shares as u128);
+    owed
+}
+
+// Helper function to accrue the price accumulators and record an oracle observation before a pool's
+// reserves change; once a pool has an oracle, every such path must pass it
+fn write_oracle(amm: &mut Amm, oracle: Option<&mut Account<Oracle>>, now: i64) -> Result<()> {
+    amm.update_price_accumulators(now);
+    match oracle {
+        Some(oracle) => {
+            oracle.write(amm, now);
+            Ok(())
+        }
+        None if amm.oracle_enabled => Err(ErrorCode::OracleRequired.into()),
+        None => Ok(()),
+    }
+}
+
+// Helper function to calculate how far a trade moved the marginal price, in basis points.
+// The ratio of post- to pre-trade price (A in B) is (b'/b) * (a/a') or its inverse; both
+// factors are computed in Q64.64 and truncated to Q32 before multiplying to avoid overflow.
+fn calculate_price_impact_bps(before: (u64, u64), after: (u64, u64)) -> u64 {
+    let (a, b) = (before.0 as u128, before.1 as u128);
+    let (a_after, b_after) = (after.0 as u128, after.1 as u128);
+
+    // Express the price in the direction it fell so the ratio is at most one
+    let (num_1, den_1, num_2, den_2) = if b_after * a <= b * a_after {
+        (b_after, b, a, a_after)
+    } else {
+        (a_after, a, b, b_after)
+    };
+    if den_1 == 0 || den_2 == 0 {
+        return FEE_DENOMINATOR;
+    }
+    let ratio_1 = (num_1 << 64) / den_1;
+    let ratio_2 = (num_2 << 64) / den_2;
+    let ratio = (ratio_1 >> 32) * (ratio_2 >> 32);
+    FEE_DENOMINATOR - ((ratio * FEE_DENOMINATOR as u128) >> 64) as u64
+}
+
+// Helper function to read a Token-2022 mint's transfer-fee configuration, if it has one
+fn transfer_fee_config(mint: &InterfaceAccount<Mint>) -> Result<Option<TransferFeeConfig>> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != spl_token_2022::ID {
+        return Ok(None);
+    }
+    let data = mint_info.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    Ok(state.get_extension::<TransferFeeConfig>().ok().copied())
+}
+
+// Helper function to calculate the fee withheld when `amount` is sent; the recipient gets the rest
+fn transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> Result<u64> {
+    match transfer_fee_config(mint)? {
+        Some(config) => config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or_else(|| ErrorCode::MathOverflow.into()),
+        None => Ok(0),
+    }
+}
+
+// Helper function to calculate the extra amount to send so that the recipient gets exactly `amount`
+fn transfer_fee_inverse(mint: &InterfaceAccount<Mint>, amount: u64) -> Result<u64> {
+    match transfer_fee_config(mint)? {
+        Some(config) => config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or_else(|| ErrorCode::MathOverflow.into()),
+        None => Ok(0),
+    }
+}
+
+// Helper function to reject Token-2022 mints whose extensions the pool cannot account for
+// (transfer hooks, confidential transfers, permanent delegates, non-transferable tokens, ...)
+fn check_mint_extensions(mint: &InterfaceAccount<Mint>) -> Result<()> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+    let data = mint_info.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata
+            | ExtensionType::InterestBearingConfig => {}
+            _ => return Err(ErrorCode::UnsupportedTokenExtension.into()),
+        }
+    }
+    Ok(())
+}
+
+// Helper function to calculate the flash loan fee, rounded up so tiny loans are not free
+fn calculate_flash_loan_fee(amount: u64, fee: u64) -> Result<u64> {
+    let numerator = amount as u128 * fee as u128;
+    let fee_amount = numerator.div_ceil(FEE_DENOMINATOR as u128);
+    u64::try_from(fee_amount).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+// The user's temporary wSOL account, used to pay or receive native SOL. The account is its own
+// token authority so the program can sign for it, and it is closed back to the user within the instruction.
+pub struct NativeSol<'a, 'info> {
+    pub user: &'a Signer<'info>,
+    pub wsol_account: &'a UncheckedAccount<'info>,
+    pub system_program: &'a Program<'info, System>,
+    pub token_program: &'a Interface<'info, TokenInterface>,
+    pub bump: u8,
+}
+
+impl<'a, 'info> NativeSol<'a, 'info> {
+    pub fn load(
+        user: &'a Signer<'info>,
+        wsol_account: Option<&'a UncheckedAccount<'info>>,
+        system_program: Option<&'a Program<'info, System>>,
+        token_program: &'a Interface<'info, TokenInterface>,
+        bump: u8,
+    ) -> Result<Self> {
+        match (wsol_account, system_program) {
+            (Some(wsol_account), Some(system_program)) => Ok(NativeSol {
+                user,
+                wsol_account,
+                system_program,
+                token_program,
+                bump,
+            }),
+            _ => Err(ErrorCode::InvalidNativeSol.into()),
+        }
+    }
+
+    // Seeds used by the wSOL account PDA to sign as its own token authority
+    pub fn signer_seeds(&self) -> [&[u8]; 3] {
+        [WSOL_SEED, self.user.key.as_ref(), std::slice::from_ref(&self.bump)]
+    }
+
+    // Create the wSOL account holding `lamports` wrapped on top of its rent
+    pub fn open(&self, native_mint: &InterfaceAccount<'info, Mint>, lamports: u64) -> Result<()> {
+        if native_mint.key() != spl_token::native_mint::ID || self.token_program.key() != spl_token::ID {
+            return Err(ErrorCode::InvalidNativeSol.into());
+        }
+
+        let seeds = self.signer_seeds();
+        let signer = &[&seeds[..]];
+        let system = self.system_program.to_account_info();
+        let required = Rent::get()?.minimum_balance(anchor_spl::token::TokenAccount::LEN) + lamports;
+        let current = self.wsol_account.lamports();
+        if current == 0 {
+            let cpi_accounts = CreateAccount {
+                from: self.user.to_account_info(),
+                to: self.wsol_account.to_account_info(),
+            };
+            system_program::create_account(
+                CpiContext::new_with_signer(system.clone(), cpi_accounts, signer),
+                required,
+                anchor_spl::token::TokenAccount::LEN as u64,
+                &self.token_program.key(),
+            )?;
+        } else {
+            // Someone pre-funded the address: top it up, then allocate and assign it
+            if required > current {
+                let cpi_accounts = system_program::Transfer {
+                    from: self.user.to_account_info(),
+                    to: self.wsol_account.to_account_info(),
+                };
+                system_program::transfer(CpiContext::new(system.clone(), cpi_accounts), required - current)?;
+            }
+            let cpi_accounts_allocate = Allocate {
+                account_to_allocate: self.wsol_account.to_account_info(),
+            };
+            system_program::allocate(
+                CpiContext::new_with_signer(system.clone(), cpi_accounts_allocate, signer),
+                anchor_spl::token::TokenAccount::LEN as u64,
+            )?;
+            let cpi_accounts_assign = Assign {
+                account_to_assign: self.wsol_account.to_account_info(),
+            };
+            system_program::assign(
+                CpiContext::new_with_signer(system, cpi_accounts_assign, signer),
+                &self.token_program.key(),
+            )?;
+        }
+
+        // Initializing a native account wraps every lamport above rent
+        let cpi_accounts_init = InitializeAccount3 {
+            account: self.wsol_account.to_account_info(),
+            mint: native_mint.to_account_info(),
+            authority: self.wsol_account.to_account_info(),
+        };
+        token_interface::initialize_account3(CpiContext::new(self.token_program.to_account_info(), cpi_accounts_init))
+    }
+
+    // Wrap `amount` lamports from the user and send them to `to` as wSOL
+    pub fn pay(
+        &self,
+        native_mint: &InterfaceAccount<'info, Mint>,
+        to: &InterfaceAccount<'info, TokenAccount>,
+        amount: u64,
+    ) -> Result<()> {
+        self.open(native_mint, amount)?;
+
+        let seeds = self.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts = TransferChecked {
+            from: self.wsol_account.to_account_info(),
+            mint: native_mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: self.wsol_account.to_account_info(),
+        };
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(self.token_program.to_account_info(), cpi_accounts, signer),
+            amount,
+            native_mint.decimals,
+        )?;
+        self.close()
+    }
+
+    // Close the wSOL account, returning its rent and any wrapped balance to the user as lamports
+    pub fn close(&self) -> Result<()> {
+        let seeds = self.signer_seeds();
+        let signer = &[&seeds[..]];
+        let cpi_accounts = CloseAccount {
+            account: self.wsol_account.to_account_info(),
+            destination: self.user.to_account_info(),
+            authority: self.wsol_account.to_account_info(),
+        };
+        token_interface::close_account(CpiContext::new_with_signer(self.token_program.to_account_info(), cpi_accounts, signer))
+    }
+}
+
+// A single pool in a swap route
+pub struct RouteHop<'info> {
+    pub amm: Account<'info, Amm>,
+    pub vault_in: AccountInfo<'info>,
+    pub vault_out: AccountInfo<'info>,
+    pub mint_in: InterfaceAccount<'info, Mint>,
+    pub mint_out: InterfaceAccount<'info, Mint>,
+    pub program_in: AccountInfo<'info>,
+    pub program_out: AccountInfo<'info>,
+    pub oracle: Option<Account<'info, Oracle>>,
+    pub from_a_to_b: bool,
+}
+
+impl<'info> RouteHop<'info> {
+    pub fn output_mint(&self) -> Pubkey {
+        if self.from_a_to_b {
+            self.amm.token_b_mint
+        } else {
+            self.amm.token_a_mint
+        }
+    }
+}
+
+// Helper function to load and validate one [amm, token_a_vault, token_b_vault, token_a_mint, token_b_mint,
+// token_a_program, token_b_program, oracle] hop
+fn load_hop<'info>(
+    chunk: &'info [AccountInfo<'info>],
+    input_mint: Pubkey,
+    loaded: &[RouteHop<'info>],
+) -> Result<RouteHop<'info>> {
+    let amm: Account<'info, Amm> = Account::try_from(&chunk[0])?;
+
+    // Vaults and mints must belong to the pool, each token program must own its mint and each pool may
+    // only appear once
+    if chunk[1].key() != amm.token_a_vault
+        || chunk[2].key() != amm.token_b_vault
+        || chunk[3].key() != amm.token_a_mint
+        || chunk[4].key() != amm.token_b_mint
+        || *chunk[3].owner != chunk[5].key()
+        || *chunk[4].owner != chunk[6].key()
+    {
+        return Err(ErrorCode::InvalidRoute.into());
+    }
+    if loaded.iter().any(|hop| hop.amm.key() == amm.key()) {
+        return Err(ErrorCode::InvalidRoute.into());
+    }
+
+    // The pool's oracle, with this program's ID standing in for pools that have none
+    let oracle = if chunk[7].key() == crate::ID {
+        None
+    } else {
+        let oracle: Account<'info, Oracle> = Account::try_from(&chunk[7])?;
+        if oracle.amm != amm.key() {
+            return Err(ErrorCode::InvalidRoute.into());
+        }
+        Some(oracle)
+    };
+    if oracle.is_none() && amm.oracle_enabled {
+        return Err(ErrorCode::OracleRequired.into());
+    }
+
+    // Pools with long-term orders need their TWAMM state settled, which routes cannot pass
+    if amm.twamm_enabled {
+        return Err(ErrorCode::TwammStateRequired.into());
+    }
+
+    // Pools that screen their users cannot be routed through
+    if amm.is_restricted() {
+        return Err(ErrorCode::RestrictedPool.into());
+    }
+
+    // The token flowing in decides the direction of this hop
+    let from_a_to_b = if input_mint == amm.token_a_mint {
+        true
+    } else if input_mint == amm.token_b_mint {
+        false
+    } else {
+        return Err(ErrorCode::InvalidRoute.into());
+    };
+    let (vault_in, vault_out, program_in, program_out) = if from_a_to_b {
+        (chunk[1].clone(), chunk[2].clone(), chunk[5].clone(), chunk[6].clone())
+    } else {
+        (chunk[2].clone(), chunk[1].clone(), chunk[6].clone(), chunk[5].clone())
+    };
+    let token_a_mint: InterfaceAccount<'info, Mint> = InterfaceAccount::try_from(&chunk[3])?;
+    let token_b_mint: InterfaceAccount<'info, Mint> = InterfaceAccount::try_from(&chunk[4])?;
+    let (mint_in, mint_out) = if from_a_to_b {
+        (token_a_mint, token_b_mint)
+    } else {
+        (token_b_mint, token_a_mint)
+    };
+
+    Ok(RouteHop {
+        amm,
+        vault_in,
+        vault_out,
+        mint_in,
+        mint_out,
+        program_in,
+        program_out,
+        oracle,
+        from_a_to_b,
+    })
+}
+
+// Helper function to load a chained route where each hop consumes the previous hop's output
+fn load_route<'info>(accounts: &'info [AccountInfo<'info>], input_mint: Pubkey) -> Result<Vec<RouteHop<'info>>> {
+    let mut hops: Vec<RouteHop<'info>> = Vec::with_capacity(accounts.len() / ROUTE_ACCOUNTS_PER_HOP);
+    let mut mint = input_mint;
+    for chunk in accounts.chunks(ROUTE_ACCOUNTS_PER_HOP) {
+        let hop = load_hop(chunk, mint, &hops)?;
+        mint = hop.output_mint();
+        hops.push(hop);
+    }
+    Ok(hops)
+}
+
+// Instruction discriminator of the flash swap callback on the receiver program
+pub fn flash_swap_callback_discriminator() -> [u8; 8] {
+    let hash = anchor_lang::solana_program::hash::hash(FLASH_SWAP_CALLBACK_PREIMAGE);
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+// Instruction discriminator of the denylist check on a screening program
+pub fn denylist_check_discriminator() -> [u8; 8] {
+    let hash = anchor_lang::solana_program::hash::hash(DENYLIST_CHECK_PREIMAGE);
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+// Fixed-point log2 of a Q64.64 value, returned as Q32.32
+fn log2_q64(x: u128) -> i64 {
+    let msb = 127 - x.leading_zeros() as i64;
+    let mut result = (msb - 64) << 32;
+
+    // Normalize into [1, 2) as Q1.63 so squaring fits in a u128
+    let mut y = if msb >= 63 { x >> (msb - 63) } else { x << (63 - msb) };
+    for bit in (0..32).rev() {
+        y = (y * y) >> 63;
+        if y >= 1 << 64 {
+            y >>= 1;
+            result += 1 << bit;
+        }
+    }
+    result
+}
+
+// Global fee-tier table (one per program), managed by governance
+#[account]
+pub struct FeeTierTable {
+    pub authority: Pubkey, // Governance authority allowed to add tiers
+    pub fees: Vec<u64>,    // Fee in basis points, indexed by tier
+    pub bump: u8,
+}
+
+impl FeeTierTable {
+    pub const LEN: usize = 32 + (4 + 8 * MAX_FEE_TIERS) + 1;
+}
+
+// AMM struct
+#[account]
+pub struct Amm {
+    pub token_a_reserve: u64,
+    pub token_b_reserve: u64,
+    pub fee: u64,
+    pub total_shares: u64,
+    pub accumulated_fees_a: u64,
+    pub accumulated_fees_b: u64,
+    pub paused: bool, // Contract paused state
+    pub admin: Pubkey, // Pool admin (pause/unpause)
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub token_a_vault: Pubkey, // Token A vault owned by the AMM
+    pub token_b_vault: Pubkey, // Token B vault owned by the AMM
+    pub fee_tier: u8, // Index into the fee-tier table, part of the pool seeds
+    pub bump: u8,
+    pub price_a_cumulative: u128, // Sum of (price of A in B, Q64.64) * seconds
+    pub price_b_cumulative: u128, // Sum of (price of B in A, Q64.64) * seconds
+    pub log_price_cumulative: i128, // Sum of (log2 price of A in B, Q32.32) * seconds
+    pub last_oracle_timestamp: i64,
+    pub oracle_enabled: bool, // Every reserve change must record an observation once the oracle exists
+    pub locked: bool, // Reentrancy guard held while a flash loan is outstanding
+    pub flash_loan_amount: u64,
+    pub flash_loan_token_a: bool,
+    pub twamm_enabled: bool, // Long-term orders must be settled before the reserves change
+    pub long_term_balance_a: u64, // Vault tokens owned by long-term orders (unsold or unclaimed)
+    pub long_term_balance_b: u64,
+    pub max_price_move_bps: u16, // Circuit breaker: largest price move allowed within one slot (0 disables)
+    pub max_breaches: u8, // Consecutive breaching slots that auto-pause the pool (0 never pauses)
+    pub breaches: u8,
+    pub breach_slot: u64, // Last slot counted as a breach, so each slot counts at most once
+    pub reference_slot: u64, // Slot whose opening reserves the circuit breaker measures against
+    pub reference_reserve_a: u64,
+    pub reference_reserve_b: u64,
+    pub token_a_decimals: u8,
+    pub token_b_decimals: u8,
+    pub lp_mint: Pubkey, // Mint of the LP tokens representing pool shares
+    pub locked_shares: u64, // Shares held in liquidity locks, which earn trading fees directly
+    pub locked_fee_growth_a: u128, // Token A fees earned per locked share, Q64.64
+    pub locked_fee_growth_b: u128, // Token B fees earned per locked share, Q64.64
+    pub locked_fees_a: u64, // Vault tokens reserved for lock owners' unclaimed fees
+    pub locked_fees_b: u64,
+    pub position_shares: u64, // Shares held in positions, which earn trading fees in their own bucket
+    pub position_fee_growth_a: u128, // Token A fees earned per position share, Q64.64
+    pub position_fee_growth_b: u128, // Token B fees earned per position share, Q64.64
+    pub position_fees_a: u64, // Vault tokens reserved for position holders' uncollected fees
+    pub position_fees_b: u64,
+    pub allowlist_authority: Pubkey, // Manages allowlist entries; Pubkey::default() for a permissionless pool
+    pub allowlist_root: [u8; 32], // Merkle root of wallets that may claim an entry (zero for none)
+    pub denylist_program: Pubkey, // Screening program consulted before swaps and deposits; Pubkey::default() for none
+    pub swap_hook_program: Pubkey, // Hook program invoked before and after swaps; Pubkey::default() for none
+    pub max_referral_fee_bps: u16, // Largest share of the protocol fee paid to referrers, in basis points
+    pub fee_discounts: [FeeDiscountTier; MAX_FEE_DISCOUNT_TIERS], // Unused tiers have a zero discount
+}
+
+impl Amm {
+    pub const LEN: usize =
+        8 * 6 + 1 + 32 * 5 + 1 + 1 + 16 * 3 + 8 + 1 + 1 + 8 + 1 + 1 + 8 + 8 + 2 + 1 + 1 + 8 + 8 * 3 + 1 + 1 + 32 + 8 + 16 * 2 + 8 * 2 + 8 + 16 * 2 + 8 * 2 + 32 * 4 + 2 + FeeDiscountTier::LEN * MAX_FEE_DISCOUNT_TIERS;
+
+    // Whether the pool screens its users with an allowlist or a denylist program, or hooks its swaps.
+    // Only swap, add_liquidity and remove_liquidity run the screening and the hook, so every other
+    // entry point that trades or deposits refuses such pools, including fills of limit orders and DCA
+    // schedules created before the pool was restricted.
+    pub fn is_restricted(&self) -> bool {
+        self.allowlist_authority != Pubkey::default()
+            || self.denylist_program != Pubkey::default()
+            || self.swap_hook_program != Pubkey::default()
+    }
+
+    // Discount (basis points of the fee) for a trader with `volume` rolling volume and `staked` governance tokens
+    pub fn fee_discount_bps(&self, volume: u64, staked: u64) -> u16 {
+        self.fee_discounts
+            .iter()
+            .filter(|tier| {
+                (tier.min_volume > 0 && volume >= tier.min_volume) || (tier.min_staked > 0 && staked >= tier.min_staked)
+            })
+            .map(|tier| tier.discount_bps)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Remember the reserves at the start of each slot for the circuit breaker
+    pub fn roll_price_reference(&mut self, slot: u64) {
+        if slot != self.reference_slot {
+            self.reference_slot = slot;
+            self.reference_reserve_a = self.token_a_reserve;
+            self.reference_reserve_b = self.token_b_reserve;
+        }
+    }
+
+    // How far the price has moved since the start of the slot, in basis points
+    pub fn price_move_bps(&self) -> u64 {
+        calculate_price_impact_bps(
+            (self.reference_reserve_a, self.reference_reserve_b),
+            (self.token_a_reserve, self.token_b_reserve),
+        )
+    }
+
+    // Accumulate prices for the time elapsed since the last reserve change
+    pub fn update_price_accumulators(&mut self, now: i64) {
+        let elapsed = now - self.last_oracle_timestamp;
+        if elapsed <= 0 {
+            return;
+        }
+        if self.token_a_reserve > 0 && self.token_b_reserve > 0 {
+            let (price_a, price_b) = self.spot_prices();
+            let elapsed = elapsed as u128;
+            self.price_a_cumulative = self.price_a_cumulative.wrapping_add(price_a.wrapping_mul(elapsed));
+            self.price_b_cumulative = self.price_b_cumulative.wrapping_add(price_b.wrapping_mul(elapsed));
+            self.log_price_cumulative = self
+                .log_price_cumulative
+                .wrapping_add(log2_q64(price_a) as i128 * elapsed as i128);
+        }
+        self.last_oracle_timestamp = now;
+    }
+
+    // Circuit breaker, run after each trade: fail trades that leave the price more than
+    // max_price_move_bps from the slot's opening reserves. Executed trades that still leave it more
+    // than half the limit away count one breach per slot, so only capital actually traded can trip
+    // the breaker; max_breaches consecutive breaching slots pause the pool and a calmer trade resets
+    // the count.
+    pub fn check_price_move(&mut self) -> Result<()> {
+        if self.max_price_move_bps == 0 || self.reference_reserve_a == 0 || self.reference_reserve_b == 0 {
+            return Ok(());
+        }
+        let price_move_bps = self.price_move_bps();
+        if price_move_bps > self.max_price_move_bps as u64 {
+            return Err(ErrorCode::PriceMoveTooLarge.into());
+        }
+        self.record_price_move(price_move_bps)
+    }
+
+    // Count a slot whose price moved more than half the circuit breaker limit, pausing the pool
+    // after max_breaches consecutive ones
+    fn record_price_move(&mut self, price_move_bps: u64) -> Result<()> {
+        if price_move_bps * 2 <= self.max_price_move_bps as u64 {
+            self.breaches = 0;
+            return Ok(());
+        }
+        if self.breach_slot == self.reference_slot {
+            return Ok(());
+        }
+        self.breach_slot = self.reference_slot;
+        self.breaches = self.breaches.saturating_add(1);
+
+        let amm = Pubkey::create_program_address(&self.signer_seeds(), &crate::ID).map_err(|_| ErrorCode::InvalidInput)?;
+        emit!(CircuitBreakerBreachEvent {
+            amm,
+            slot: self.reference_slot,
+            price_move_bps,
+            breaches: self.breaches,
+        });
+        if self.max_breaches > 0 && self.breaches >= self.max_breaches && !self.paused {
+            self.paused = true;
+            emit!(CircuitBreakerTrippedEvent {
+                amm,
+                slot: self.reference_slot,
+                breaches: self.breaches,
+            });
+        }
+        Ok(())
+    }
+
+    // Run a constant product trade against the reserves; fees (in the input token) are set aside
+    pub fn apply_swap(&mut self, amount_in: u64, from_a_to_b: bool) -> Result<u64> {
+        self.apply_swap_with_fee(amount_in, from_a_to_b, self.fee)
+    }
+
+    // Run a constant product trade charging `fee` (basis points) instead of the pool fee, subject to
+    // the circuit breaker
+    pub fn apply_swap_with_fee(&mut self, amount_in: u64, from_a_to_b: bool, fee: u64) -> Result<u64> {
+        self.roll_price_reference(Clock::get()?.slot);
+        let amount_out = self.swap_reserves(amount_in, from_a_to_b, fee)?;
+        self.check_price_move()?;
+        Ok(amount_out)
+    }
+
+    // Run a long-term order trade during virtual execution. A large move counts as a breach but never
+    // fails, since settlement must succeed before anything else can touch the pool; the slot's
+    // reference then restarts after it so traders are only measured against their own moves.
+    pub fn apply_virtual_swap(&mut self, amount_in: u64, from_a_to_b: bool) -> Result<u64> {
+        self.roll_price_reference(Clock::get()?.slot);
+        self.reference_reserve_a = self.token_a_reserve;
+        self.reference_reserve_b = self.token_b_reserve;
+        let amount_out = self.swap_reserves(amount_in, from_a_to_b, self.fee)?;
+        if self.max_price_move_bps > 0 && self.reference_reserve_a > 0 && self.reference_reserve_b > 0 {
+            self.record_price_move(self.price_move_bps())?;
+        }
+        self.reference_reserve_a = self.token_a_reserve;
+        self.reference_reserve_b = self.token_b_reserve;
+        Ok(amount_out)
+    }
+
+    // Move the reserves for a constant product trade charging `fee` (basis points)
+    fn swap_reserves(&mut self, amount_in: u64, from_a_to_b: bool, fee: u64) -> Result<u64> {
+        let (reserve_in, reserve_out) = if from_a_to_b {
+            (self.token_a_reserve, self.token_b_reserve)
+        } else {
+            (self.token_b_reserve, self.token_a_reserve)
+        };
+        let amount_out = calculate_amount_out(amount_in, reserve_in, reserve_out, fee)?;
+
+        // Collect fees (denominated in the input token)
+        let fee_amount = (amount_in as u128 * fee as u128 / FEE_DENOMINATOR as u128) as u64;
+        let (reserve_in, reserve_out) = (
+            reserve_in.checked_add(amount_in - fee_amount).ok_or(ErrorCode::MathOverflow)?,
+            reserve_out.checked_sub(amount_out).ok_or(ErrorCode::InsufficientLiquidity)?,
+        );
+        if from_a_to_b {
+            self.collect_fees(fee_amount, 0);
+            self.token_a_reserve = reserve_in;
+            self.token_b_reserve = reserve_out;
+        } else {
+            self.collect_fees(0, fee_amount);
+            self.token_b_reserve = reserve_in;
+            self.token_a_reserve = reserve_out;
+        }
+        Ok(amount_out)
+    }
+
+    // Set aside trading fees pro rata by shares. The parts owed to locked liquidity and to positions
+    // are reserved in separate buckets for their owners to claim; the part owed to LP tokens is left
+    // for distribute_fees.
+    pub fn collect_fees(&mut self, fee_a: u64, fee_b: u64) {
+        let locked_a = accrue_fee_share(fee_a, self.locked_shares, self.total_shares, &mut self.locked_fee_growth_a);
+        let locked_b = accrue_fee_share(fee_b, self.locked_shares, self.total_shares, &mut self.locked_fee_growth_b);
+        let position_a = accrue_fee_share(fee_a, self.position_shares, self.total_shares, &mut self.position_fee_growth_a);
+        let position_b = accrue_fee_share(fee_b, self.position_shares, self.total_shares, &mut self.position_fee_growth_b);
+        self.locked_fees_a += locked_a;
+        self.locked_fees_b += locked_b;
+        self.position_fees_a += position_a;
+        self.position_fees_b += position_b;
+        self.accumulated_fees_a += fee_a - locked_a - position_a;
+        self.accumulated_fees_b += fee_b - locked_b - position_b;
+    }
+
+    // Price of A in B for the given amounts, normalized by mint decimals, as Q64.64
+    pub fn ui_price(&self, amount_a: u64, amount_b: u64) -> Result<u128> {
+        calculate_decimal_price(amount_a, self.token_a_decimals, amount_b, self.token_b_decimals)
+    }
+
+    // Execution price of a trade as the price of A in B, normalized by mint decimals, as Q64.64
+    pub fn execution_price(&self, amount_in: u64, amount_out: u64, from_a_to_b: bool) -> Result<u128> {
+        if from_a_to_b {
+            self.ui_price(amount_in, amount_out)
+        } else {
+            self.ui_price(amount_out, amount_in)
+        }
+    }
+
+    // Spot prices as Q64.64: (A in B, B in A)
+    pub fn spot_prices(&self) -> (u128, u128) {
+        let price_a = ((self.token_b_reserve as u128) << 64) / self.token_a_reserve as u128;
+        let price_b = ((self.token_a_reserve as u128) << 64) / self.token_b_reserve as u128;
+        (price_a, price_b)
+    }
+
+    // Seeds used by the AMM PDA to sign for its vaults
+    pub fn signer_seeds(&self) -> [&[u8]; 5] {
+        [
+            AMM_SEED,
+            self.token_a_mint.as_ref(),
+            self.token_b_mint.as_ref(),
+            std::slice::from_ref(&self.fee_tier),
+            std::slice::from_ref(&self.bump),
+        ]
+    }
+}
+
+// A single oracle observation
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Observation {
+    pub timestamp: i64,
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub log_price_cumulative: i128,
+}
+
+impl Observation {
+    pub const LEN: usize = 8 + 16 * 3;
+
+    // Snapshot of the pool's accumulators extrapolated to `now`
+    pub fn from_amm(amm: &Amm, now: i64) -> Self {
+        let mut amm = amm.clone();
+        amm.update_price_accumulators(now);
+        Observation {
+            timestamp: now,
+            price_a_cumulative: amm.price_a_cumulative,
+            price_b_cumulative: amm.price_b_cumulative,
+            log_price_cumulative: amm.log_price_cumulative,
+        }
+    }
+
+    // Linear interpolation between two observations
+    fn interpolate(&self, next: &Observation, timestamp: i64) -> Self {
+        let span = (next.timestamp - self.timestamp) as u128;
+        let offset = (timestamp - self.timestamp) as u128;
+        Observation {
+            timestamp,
+            price_a_cumulative: self.price_a_cumulative.wrapping_add(
+                next.price_a_cumulative.wrapping_sub(self.price_a_cumulative) / span * offset,
+            ),
+            price_b_cumulative: self.price_b_cumulative.wrapping_add(
+                next.price_b_cumulative.wrapping_sub(self.price_b_cumulative) / span * offset,
+            ),
+            log_price_cumulative: self.log_price_cumulative.wrapping_add(
+                next.log_price_cumulative.wrapping_sub(self.log_price_cumulative) / span as i128 * offset as i128,
+            ),
+        }
+    }
+}
+
+// Ring buffer of price observations for a single pool
+#[account]
+pub struct Oracle {
+    pub amm: Pubkey,
+    pub index: u16,       // Position of the most recent observation
+    pub cardinality: u16, // Configured number of observations to keep
+    pub observations: Vec<Observation>,
+    pub bump: u8,
+}
+
+impl Oracle {
+    pub fn space(cardinality: u16) -> usize {
+        8 + 32 + 2 + 2 + 4 + Observation::LEN * cardinality as usize + 1
+    }
+
+    // Record the pool's accumulators; at most one observation per timestamp
+    pub fn write(&mut self, amm: &Amm, now: i64) {
+        let last = self.observations[self.index as usize];
+        if last.timestamp == now {
+            return;
+        }
+        let observation = Observation::from_amm(amm, now);
+        let next = self.index as usize + 1;
+        if next == self.observations.len() && self.observations.len() < self.cardinality as usize {
+            self.observations.push(observation);
+            self.index = next as u16;
+        } else {
+            let next = next % self.observations.len();
+            self.observations[next] = observation;
+            self.index = next as u16;
+        }
+    }
+
+    // Accumulator values at `timestamp`, interpolated between stored observations
+    pub fn observe(&self, amm: &Amm, now: i64, timestamp: i64) -> Result<Observation> {
+        let latest = self.observations[self.index as usize];
+        if timestamp >= latest.timestamp {
+            let current = Observation::from_amm(amm, now);
+            if timestamp == now || current.timestamp == latest.timestamp {
+                return Ok(current);
+            }
+            return Ok(latest.interpolate(&current, timestamp));
+        }
+
+        // Walk the ring from the oldest observation forward
+        let len = self.observations.len();
+        let oldest = (self.index as usize + 1) % len;
+        if timestamp < self.observations[oldest].timestamp {
+            return Err(ErrorCode::OracleObservationTooOld.into());
+        }
+        for i in 0..len - 1 {
+            let before = self.observations[(oldest + i) % len];
+            let after = self.observations[(oldest + i + 1) % len];
+            if timestamp < after.timestamp {
+                return Ok(before.interpolate(&after, timestamp));
+            }
+        }
+        Ok(latest)
+    }
+}
+
+// Published callback layout for flash swap receivers.
+// Instruction data: flash_swap_callback_discriminator() followed by this struct (Borsh).
+// Accounts: [amm (readonly), token_a_vault (writable), token_b_vault (writable), sender (signer)],
+// followed by the remaining accounts passed to flash_swap, in order.
+// An Anchor receiver simply declares `pub fn flash_swap_callback(ctx, args: FlashSwapCallback)`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FlashSwapCallback {
+    pub amm: Pubkey,
+    pub sender: Pubkey,
+    pub amount_a_out: u64,
+    pub amount_b_out: u64,
+    pub data: Vec<u8>,
+}
+
+// Published layout of the denylist check invoked on a pool's screening program.
+// Instruction data: denylist_check_discriminator() followed by this struct (Borsh).
+// Accounts: [amm (readonly), user (readonly)], followed by the optional denylist account passed
+// to swap or add_liquidity. The program rejects the user by returning an error.
+// An Anchor screening program simply declares `pub fn check_denylist(ctx, args: DenylistCheck)`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct DenylistCheck {
+    pub amm: Pubkey,
+    pub user: Pubkey,
+}
+
+// One tier of a pool's fee discount schedule; a zero threshold does not qualify anyone
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct FeeDiscountTier {
+    pub min_volume: u64, // Rolling volume in token B
+    pub min_staked: u64, // Governance tokens staked
+    pub discount_bps: u16, // Share of the fee waived, in basis points
+}
+
+impl FeeDiscountTier {
+    pub const LEN: usize = 8 * 2 + 2;
+}
+
+// Sell rates that stop at a given slot, and the earnings snapshot taken when they did
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct TwammExpiry {
+    pub slot: u64,
+    pub rate_a: u64,
+    pub rate_b: u64,
+    pub earnings_per_rate_a: u128,
+    pub earnings_per_rate_b: u128,
+    pub processed: bool,
+    pub orders: u32, // Orders still referencing this entry
+}
+
+impl TwammExpiry {
+    pub const LEN: usize = 8 * 3 + 16 * 2 + 1 + 4;
+}
+
+// Per-pool long-term order (TWAMM) state. Both sides sell continuously at their aggregate rate;
+// virtual execution is settled lazily, one order interval at a time.
+#[account]
+pub struct TwammState {
+    pub amm: Pubkey,
+    pub sell_rate_a: u64, // Token A sold per slot across all active orders
+    pub sell_rate_b: u64, // Token B sold per slot across all active orders
+    pub earnings_per_rate_a: u128, // Token B earned per unit of A sell rate, Q64.64
+    pub earnings_per_rate_b: u128, // Token A earned per unit of B sell rate, Q64.64
+    pub last_virtual_order_slot: u64,
+    pub min_sell_rate_a: u64, // Smallest A sell rate a new order may have
+    pub min_sell_rate_b: u64, // Smallest B sell rate a new order may have
+    pub expiries: Vec<TwammExpiry>,
+    pub bump: u8,
+}
+
+impl TwammState {
+    pub const LEN: usize = 32 + 8 * 2 + 16 * 2 + 8 + 8 * 2 + (4 + TwammExpiry::LEN * MAX_TWAMM_EXPIRIES) + 1;
+
+    // Execute virtual orders up to `slot`, one order interval at a time so each interval trades at the
+    // price the previous one left, and drop the rates of each expiry reached. Stretches without active
+    // orders are skipped in one step. At most MAX_TWAMM_SETTLEMENT_INTERVALS intervals run per call;
+    // a pool further behind catches up over later calls (or execute_long_term_orders).
+    pub fn execute_virtual_orders(&mut self, amm: &mut Amm, slot: u64) -> Result<()> {
+        let mut intervals = 0;
+        while self.last_virtual_order_slot < slot && intervals < MAX_TWAMM_SETTLEMENT_INTERVALS {
+            let next_expiry = self
+                .expiries
+                .iter()
+                .filter(|entry| !entry.processed)
+                .map(|entry| entry.slot)
+                .min();
+            let period_end = if self.sell_rate_a == 0 && self.sell_rate_b == 0 {
+                next_expiry.map_or(slot, |expiry| expiry.min(slot))
+            } else {
+                intervals += 1;
+                let interval_end = (self.last_virtual_order_slot / LONG_TERM_ORDER_INTERVAL + 1) * LONG_TERM_ORDER_INTERVAL;
+                interval_end.min(slot)
+            };
+            self.execute_period(amm, period_end - self.last_virtual_order_slot)?;
+            self.last_virtual_order_slot = period_end;
+
+            if next_expiry == Some(period_end) {
+                let (earnings_a, earnings_b) = (self.earnings_per_rate_a, self.earnings_per_rate_b);
+                if let Some(entry) = self.expiries.iter_mut().find(|entry| entry.slot == period_end) {
+                    entry.processed = true;
+                    entry.earnings_per_rate_a = earnings_a;
+                    entry.earnings_per_rate_b = earnings_b;
+                    self.sell_rate_a -= entry.rate_a;
+                    self.sell_rate_b -= entry.rate_b;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Execute one period at constant sell rates: opposing flows are matched at the pool price and
+    // only the net imbalance is swapped through the curve (paying the pool fee)
+    fn execute_period(&mut self, amm: &mut Amm, slots: u64) -> Result<()> {
+        let sold_a = self.sell_rate_a * slots;
+        let sold_b = self.sell_rate_b * slots;
+        if sold_a == 0 && sold_b == 0 {
+            return Ok(());
+        }
+        if amm.token_a_reserve == 0 || amm.token_b_reserve == 0 {
+            return Err(ErrorCode::InsufficientLiquidity.into());
+        }
+
+        let (reserve_a, reserve_b) = (amm.token_a_reserve as u128, amm.token_b_reserve as u128);
+        let (proceeds_for_a_sellers, proceeds_for_b_sellers) = if sold_a as u128 * reserve_b >= sold_b as u128 * reserve_a {
+            let matched_a = (sold_b as u128 * reserve_a / reserve_b) as u64;
+            let net_a = sold_a - matched_a;
+            let out_b = if net_a > 0 { amm.apply_virtual_swap(net_a, true)? } else { 0 };
+            amm.long_term_balance_a -= net_a;
+            amm.long_term_balance_b += out_b;
+            (sold_b + out_b, matched_a)
+        } else {
+            let matched_b = (sold_a as u128 * reserve_b / reserve_a) as u64;
+            let net_b = sold_b - matched_b;
+            let out_a = if net_b > 0 { amm.apply_virtual_swap(net_b, false)? } else { 0 };
+            amm.long_term_balance_b -= net_b;
+            amm.long_term_balance_a += out_a;
+            (matched_b, sold_a + out_a)
+        };
+
+        if self.sell_rate_a > 0 {
+            let earned = ((proceeds_for_a_sellers as u128) << 64) / self.sell_rate_a as u128;
+            self.earnings_per_rate_a = self.earnings_per_rate_a.wrapping_add(earned);
+        }
+        if self.sell_rate_b > 0 {
+            let earned = ((proceeds_for_b_sellers as u128) << 64) / self.sell_rate_b as u128;
+            self.earnings_per_rate_b = self.earnings_per_rate_b.wrapping_add(earned);
+        }
+        Ok(())
+    }
+
+    // Find or create the schedule entry for an expiry slot
+    pub fn expiry_entry(&mut self, slot: u64) -> Result<&mut TwammExpiry> {
+        let position = match self.expiries.iter().position(|entry| entry.slot == slot) {
+            Some(position) => position,
+            None => {
+                if self.expiries.len() >= MAX_TWAMM_EXPIRIES {
+                    return Err(ErrorCode::TwammScheduleFull.into());
+                }
+                self.expiries.push(TwammExpiry {
+                    slot,
+                    ..TwammExpiry::default()
+                });
+                self.expiries.len() - 1
+            }
+        };
+        Ok(&mut self.expiries[position])
+    }
+
+    // Settle an expired order's final proceeds into the order and drop its reference to the expiry entry
+    pub fn release_order(&mut self, order: &mut LongTermOrder) -> Result<()> {
+        let proceeds = self.claim_proceeds(order, true)?;
+        order.pending_proceeds = order.pending_proceeds.checked_add(proceeds).ok_or(ErrorCode::MathOverflow)?;
+        order.expiry_released = true;
+        self.release_expiry(order.expiry_slot);
+        Ok(())
+    }
+
+    // Drop an order's reference to its expiry entry, removing entries nobody needs any more
+    pub fn release_expiry(&mut self, slot: u64) {
+        if let Some(entry) = self.expiries.iter_mut().find(|entry| entry.slot == slot) {
+            entry.orders -= 1;
+        }
+        self.expiries.retain(|entry| entry.orders > 0);
+    }
+
+    // Proceeds earned by an order since its last checkpoint; moves the checkpoint forward
+    pub fn claim_proceeds(&self, order: &mut LongTermOrder, expired: bool) -> Result<u64> {
+        let earnings_now = if expired {
+            let entry = self
+                .expiries
+                .iter()
+                .find(|entry| entry.slot == order.expiry_slot)
+                .ok_or(ErrorCode::InvalidInput)?;
+            if order.sell_token_a { entry.earnings_per_rate_a } else { entry.earnings_per_rate_b }
+        } else if order.sell_token_a {
+            self.earnings_per_rate_a
+        } else {
+            self.earnings_per_rate_b
+        };
+        let proceeds = (earnings_now.wrapping_sub(order.earnings_checkpoint) * order.sell_rate as u128) >> 64;
+        order.earnings_checkpoint = earnings_now;
+        u64::try_from(proceeds).map_err(|_| ErrorCode::MathOverflow.into())
+    }
+}
+
+// Long-term order selling at a constant rate per slot until its expiry
+#[account]
+pub struct LongTermOrder {
+    pub owner: Pubkey,
+    pub amm: Pubkey,
+    pub order_id: u64,
+    pub sell_token_a: bool,
+    pub sell_rate: u64,
+    pub expiry_slot: u64,
+    pub earnings_checkpoint: u128, // Earnings per rate already paid out
+    pub expiry_released: bool, // Final proceeds moved into pending_proceeds and the expiry entry freed
+    pub pending_proceeds: u64, // Proceeds owed to the owner after release
+    pub bump: u8,
+}
+
+impl LongTermOrder {
+    pub const LEN: usize = 32 * 2 + 8 + 1 + 8 + 8 + 16 + 1 + 8 + 1;
+}
+
+// Resting limit order: sell `amount` of one pool token for at least `min_amount_out` of the other
+#[account]
+pub struct LimitOrder {
+    pub owner: Pubkey,
+    pub amm: Pubkey,
+    pub escrow: Pubkey,      // Token account holding the tokens being sold
+    pub destination: Pubkey, // Owner's token account that receives the output
+    pub order_id: u64,
+    pub sell_token_a: bool,
+    pub amount: u64,
+    pub min_amount_out: u64,
+    pub bump: u8,
+}
+
+impl LimitOrder {
+    pub const LEN: usize = 32 * 4 + 8 + 1 + 8 + 8 + 1;
+}
+
+// Reward stream of a farm
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct FarmReward {
+    pub mint: Pubkey,
+    pub vault: Pubkey, // Token account holding the undistributed rewards, owned by the farm
+    pub emission_rate: u64, // Reward tokens per second, shared by all stakers
+    pub end_time: i64,
+    pub last_update_time: i64,
+    pub reward_per_share: u128, // Rewards earned per staked LP token, Q64.64
+}
+
+impl FarmReward {
+    pub const LEN: usize = 32 * 2 + 8 * 3 + 16;
+}
+
+// Liquidity-mining farm of a pool: LP tokens are staked into `stake_vault`
+#[account]
+pub struct Farm {
+    pub amm: Pubkey,
+    pub lp_mint: Pubkey,
+    pub stake_vault: Pubkey,
+    pub authority: Pubkey, // May add reward streams
+    pub total_staked: u64,
+    pub rewards: Vec<FarmReward>,
+    pub bump: u8,
+}
+
+impl Farm {
+    pub const LEN: usize = 32 * 4 + 8 + (4 + FarmReward::LEN * MAX_FARM_REWARDS) + 1;
+
+    // Accrue every stream's emissions since its last update into its reward-per-share accumulator.
+    // Emissions while nothing is staked are not distributed.
+    pub fn update_rewards(&mut self, now: i64) -> Result<()> {
+        for reward in self.rewards.iter_mut() {
+            let until = now.min(reward.end_time);
+            if until <= reward.last_update_time {
+                continue;
+            }
+            if self.total_staked > 0 {
+                let emitted = reward.emission_rate as u128 * (until - reward.last_update_time) as u128;
+                let earned = emitted.checked_mul(1 << 64).ok_or(ErrorCode::MathOverflow)?;
+                reward.reward_per_share = reward.reward_per_share.wrapping_add(earned / self.total_staked as u128);
+            }
+            reward.last_update_time = until;
+        }
+        Ok(())
+    }
+
+    // Seeds used by the farm PDA to sign for its vaults
+    pub fn signer_seeds(&self) -> [&[u8]; 3] {
+        [FARM_SEED, self.amm.as_ref(), std::slice::from_ref(&self.bump)]
+    }
+}
+
+// A staker's share of a farm
+#[account]
+pub struct StakePosition {
+    pub owner: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64, // LP tokens staked
+    pub reward_checkpoints: [u128; MAX_FARM_REWARDS], // Each stream's reward_per_share at the last settlement
+    pub pending_rewards: [u64; MAX_FARM_REWARDS], // Earned but not yet harvested
+    pub bump: u8,
+}
+
+impl StakePosition {
+    pub const LEN: usize = 32 * 2 + 8 + 16 * MAX_FARM_REWARDS + 8 * MAX_FARM_REWARDS + 1;
+
+    // Move rewards earned since the last checkpoints into pending; the farm must be updated first
+    pub fn settle(&mut self, farm: &Farm) -> Result<()> {
+        for (index, reward) in farm.rewards.iter().enumerate() {
+            let earned = reward
+                .reward_per_share
+                .wrapping_sub(self.reward_checkpoints[index])
+                .checked_mul(self.amount as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                >> 64;
+            let earned = u64::try_from(earned).map_err(|_| ErrorCode::MathOverflow)?;
+            self.pending_rewards[index] = self.pending_rewards[index].checked_add(earned).ok_or(ErrorCode::MathOverflow)?;
+            self.reward_checkpoints[index] = reward.reward_per_share;
+        }
+        Ok(())
+    }
+}
+
+// LP tokens locked until a timestamp or on a linear vesting schedule
+#[account]
+pub struct LiquidityLock {
+    pub owner: Pubkey,
+    pub amm: Pubkey,
+    pub escrow: Pubkey, // LP token account holding the locked shares, owned by the lock
+    pub lock_id: u64,
+    pub initial_shares: u64,
+    pub shares: u64, // Shares still held in the escrow
+    pub unlock_start: i64, // Nothing unlocks before this timestamp
+    pub unlock_end: i64, // Everything is unlocked at this timestamp
+    pub fee_checkpoint_a: u128, // Pool's locked fee growth at the last settlement
+    pub fee_checkpoint_b: u128,
+    pub fees_owed_a: u64, // Settled but not yet claimed
+    pub fees_owed_b: u64,
+    pub bump: u8,
+}
+
+impl LiquidityLock {
+    pub const LEN: usize = 32 * 3 + 8 * 5 + 16 * 2 + 8 * 2 + 1;
+
+    // Shares vested by `now` that have not been released yet
+    pub fn unlocked_shares(&self, now: i64) -> u64 {
+        let vested = if now >= self.unlock_end {
+            self.initial_shares
+        } else if now < self.unlock_start {
+            0
+        } else {
+            (self.initial_shares as u128 * (now - self.unlock_start) as u128 / (self.unlock_end - self.unlock_start) as u128) as u64
+        };
+        vested.saturating_sub(self.initial_shares - self.shares)
+    }
+
+    // Move fees earned since the last checkpoints into the owed balances
+    pub fn settle_fees(&mut self, amm: &Amm) {
+        let earned_a = (amm.locked_fee_growth_a.wrapping_sub(self.fee_checkpoint_a) * self.shares as u128) >> 64;
+        let earned_b = (amm.locked_fee_growth_b.wrapping_sub(self.fee_checkpoint_b) * self.shares as u128) >> 64;
+        self.fees_owed_a += earned_a as u64;
+        self.fees_owed_b += earned_b as u64;
+        self.fee_checkpoint_a = amm.locked_fee_growth_a;
+        self.fee_checkpoint_b = amm.locked_fee_growth_b;
+    }
+}
+
+// Liquidity position owned by whoever holds its NFT
+#[account]
+pub struct Position {
+    pub amm: Pubkey,
+    pub position_mint: Pubkey, // NFT mint; also seeds this account
+    pub shares: u64,
+    pub fee_checkpoint_a: u128, // Pool's position fee growth at the last settlement
+    pub fee_checkpoint_b: u128,
+    pub fees_owed_a: u64, // Settled but not yet collected
+    pub fees_owed_b: u64,
+    pub locked_until: i64, // Shares cannot be withdrawn before this timestamp
+    pub bump: u8,
+}
+
+impl Position {
+    pub const LEN: usize = 32 * 2 + 8 + 16 * 2 + 8 * 2 + 8 + 1;
+
+    // Move fees earned since the last checkpoints into the owed balances
+    pub fn settle_fees(&mut self, amm: &Amm) {
+        let earned_a = (amm.position_fee_growth_a.wrapping_sub(self.fee_checkpoint_a) * self.shares as u128) >> 64;
+        let earned_b = (amm.position_fee_growth_b.wrapping_sub(self.fee_checkpoint_b) * self.shares as u128) >> 64;
+        self.fees_owed_a += earned_a as u64;
+        self.fees_owed_b += earned_b as u64;
+        self.fee_checkpoint_a = amm.position_fee_growth_a;
+        self.fee_checkpoint_b = amm.position_fee_growth_b;
+    }
+}
+
+// A wallet allowed to use a permissioned pool
+#[account]
+pub struct AllowlistEntry {
+    pub amm: Pubkey,
+    pub wallet: Pubkey,
+    pub merkle_root: [u8; 32], // Root the entry was claimed against; zero when added by the authority
+    pub bump: u8,
+}
+
+impl AllowlistEntry {
+    pub const LEN: usize = 32 * 3 + 1;
+}
+
+// Cumulative stats of a referrer on one pool
+#[account]
+pub struct ReferrerStats {
+    pub amm: Pubkey,
+    pub referrer: Pubkey, // Owner of the token accounts that receive referral fees
+    pub fee_bps: u16, // Requested share of the protocol fee, capped by the pool
+    pub swaps: u64,
+    pub volume_a: u64, // Input volume referred, per token
+    pub volume_b: u64,
+    pub fees_earned_a: u64,
+    pub fees_earned_b: u64,
+    pub bump: u8,
+}
+
+impl ReferrerStats {
+    pub const LEN: usize = 32 * 2 + 2 + 8 * 5 + 1;
+}
+
+// A trader's volume on one pool over the rolling discount window
+#[account]
+pub struct TraderStats {
+    pub amm: Pubkey,
+    pub trader: Pubkey,
+    pub daily_volume: [u64; VOLUME_WINDOW_DAYS], // Volume in token B, indexed by day % VOLUME_WINDOW_DAYS
+    pub last_day: i64, // Day (unix time / SECONDS_PER_DAY) of the newest bucket
+    pub bump: u8,
+}
+
+impl TraderStats {
+    pub const LEN: usize = 32 * 2 + 8 * VOLUME_WINDOW_DAYS + 8 + 1;
+
+    // Clear the buckets of days that fell out of the window ending at `now`
+    pub fn roll(&mut self, now: i64) {
+        let day = now / SECONDS_PER_DAY;
+        if day <= self.last_day {
+            return;
+        }
+        let elapsed = (day - self.last_day).min(VOLUME_WINDOW_DAYS as i64);
+        for offset in 1..=elapsed {
+            self.daily_volume[((self.last_day + offset) % VOLUME_WINDOW_DAYS as i64) as usize] = 0;
+        }
+        self.last_day = day;
+    }
+
+    // Volume over the window; roll must be called first
+    pub fn rolling_volume(&self) -> u64 {
+        self.daily_volume.iter().fold(0, |total, volume| total.saturating_add(*volume))
+    }
+
+    // Add volume to the bucket of the current day
+    pub fn record(&mut self, now: i64, volume: u64) {
+        self.roll(now);
+        let bucket = &mut self.daily_volume[(self.last_day % VOLUME_WINDOW_DAYS as i64) as usize];
+        *bucket = bucket.saturating_add(volume);
+    }
+}
+
+// Governance staking for fee discounts (one per program); stakes are held in `vault`
+#[account]
+pub struct GovernanceStaking {
+    pub governance_mint: Pubkey,
+    pub vault: Pubkey,
+    pub lockup: i64, // Seconds each stake is locked for after it last grew
+    pub total_staked: u64,
+    pub bump: u8,
+}
+
+impl GovernanceStaking {
+    pub const LEN: usize = 32 * 2 + 8 * 2 + 1;
+}
+
+// A wallet's governance stake, counted toward fee discounts on every pool
+#[account]
+pub struct GovernanceStake {
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub unlock_time: i64, // The stake cannot be withdrawn before this timestamp
+    pub bump: u8,
+}
+
+impl GovernanceStake {
+    pub const LEN: usize = 32 + 8 * 2 + 1;
+}
+
+// Recurring swap: sell `amount_per_cycle` every `interval` seconds until no cycles remain
+#[account]
+pub struct DcaSchedule {
+    pub owner: Pubkey,
+    pub amm: Pubkey,
+    pub escrow: Pubkey,      // Token account holding the remaining cycles' input
+    pub destination: Pubkey, // Owner's token account that receives the output
+    pub schedule_id: u64,
+    pub sell_token_a: bool,
+    pub amount_per_cycle: u64,
+    pub min_amount_out_per_cycle: u64,
+    pub interval: i64, // Seconds between cycles
+    pub cycles_remaining: u64,
+    pub next_cycle_timestamp: i64,
+    pub bump: u8,
+}
+
+impl DcaSchedule {
+    pub const LEN: usize = 32 * 4 + 8 + 1 + 8 * 5 + 1;
+}
+
+// Optional swap expiry, either by slot or by unix timestamp
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum SwapDeadline {
+    Slot(u64),
+    UnixTimestamp(i64),
+}
+
+// Return value of the quote_swap view
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SwapQuote {
+    pub amount_out: u64, // Received by the trader, net of any transfer fee
+    pub fee_amount: u64, // Fee charged in the input token, after the trader's discount
+}
+
+// Return value of the quote_ui_price view; prices are decimal-normalized Q64.64
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UiPrice {
+    pub price_a: u128, // Price of A in B
+    pub price_b: u128, // Price of B in A
+    pub token_a_decimals: u8,
+    pub token_b_decimals: u8,
+}
+
+// Return value of the quote_add_liquidity and quote_remove_liquidity views
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LiquidityQuote {
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub shares: u64,
+}
+
+// Return value of the observe_twap view
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TwapResult {
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub price_a_twap: u128,   // Arithmetic mean price of A in B, Q64.64
+    pub price_b_twap: u128,   // Arithmetic mean price of B in A, Q64.64
+    pub log_price_twap: i64,  // Mean log2 price of A in B (geometric mean exponent), Q32.32
+}
+
+// Context for InitializeFeeTiers function
+#[derive(Accounts)]
+pub struct InitializeFeeTiers<'info> {
+    #[account(init, payer = authority, space = 8 + FeeTierTable::LEN, seeds = [FEE_TIERS_SEED], bump)]
+    pub fee_tiers: Account<'info, FeeTierTable>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for governance actions on the fee-tier table
+#[derive(Accounts)]
+pub struct ManageFeeTiers<'info> {
+    #[account(mut, seeds = [FEE_TIERS_SEED], bump = fee_tiers.bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub fee_tiers: Account<'info, FeeTierTable>,
+    pub authority: Signer<'info>,
+}
+
+// Context for Initialize function
+#[derive(Accounts)]
+#[instruction(fee_tier: u8)]
+pub struct Initialize<'info> {
+    #[account(seeds = [FEE_TIERS_SEED], bump = fee_tiers.bump)]
+    pub fee_tiers: Account<'info, FeeTierTable>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Amm::LEN,
+        seeds = [AMM_SEED, token_a_mint.key().as_ref(), token_b_mint.key().as_ref(), &[fee_tier]],
+        bump
+    )]
+    pub amm: Account<'info, Amm>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [VAULT_SEED, amm.key().as_ref(), token_a_mint.key().as_ref()],
+        bump,
+        token::mint = token_a_mint,
+        token::authority = amm,
+        token::token_program = token_a_program
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [VAULT_SEED, amm.key().as_ref(), token_b_mint.key().as_ref()],
+        bump,
+        token::mint = token_b_mint,
+        token::authority = amm,
+        token::token_program = token_b_program
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [LP_MINT_SEED, amm.key().as_ref()],
+        bump,
+        mint::decimals = LP_MINT_DECIMALS,
+        mint::authority = amm,
+        mint::token_program = token_program
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Token program of the LP mint
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for Swap function
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub from_account: Option<InterfaceAccount<'info, TokenAccount>>, // None to pay native SOL
+    #[account(mut)]
+    pub to_account: Option<InterfaceAccount<'info, TokenAccount>>, // None to receive native SOL
+    /// CHECK: temporary wSOL account, created and closed within the instruction
+    #[account(mut, seeds = [WSOL_SEED, user.key().as_ref()], bump)]
+    pub wsol_account: Option<UncheckedAccount<'info>>,
+    #[account(seeds = [ALLOWLIST_SEED, amm.key().as_ref(), user.key().as_ref()], bump = allowlist_entry.bump)]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>, // Required by permissioned pools
+    /// CHECK: the pool's screening program, invoked with the published DenylistCheck layout
+    #[account(executable, address = amm.denylist_program @ ErrorCode::DenylistProgramRequired)]
+    pub denylist_program: Option<UncheckedAccount<'info>>, // Required by screened pools
+    /// CHECK: screening state passed through to the denylist program
+    pub denylist_account: Option<UncheckedAccount<'info>>,
+    /// CHECK: the pool's swap hook, invoked with the swap_hook_interface layout; remaining accounts are passed through
+    #[account(executable, address = amm.swap_hook_program @ ErrorCode::SwapHookRequired)]
+    pub swap_hook_program: Option<UncheckedAccount<'info>>, // Required by hooked pools
+    #[account(mut)]
+    pub referrer_account: Option<InterfaceAccount<'info, TokenAccount>>, // Referrer's account for the input token
+    #[account(mut, seeds = [REFERRER_SEED, amm.key().as_ref(), referrer_stats.referrer.as_ref()], bump = referrer_stats.bump)]
+    pub referrer_stats: Option<Account<'info, ReferrerStats>>, // Required with a referrer account
+    #[account(mut, seeds = [TRADER_STATS_SEED, amm.key().as_ref(), user.key().as_ref()], bump = trader_stats.bump)]
+    pub trader_stats: Option<Account<'info, TraderStats>>, // Tracks volume for fee discounts
+    #[account(seeds = [GOVERNANCE_STAKE_SEED, user.key().as_ref()], bump = governance_stake.bump)]
+    pub governance_stake: Option<Account<'info, GovernanceStake>>, // Governance stake for fee discounts
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub system_program: Option<Program<'info, System>>,
+}
+
+// Context for AddLiquidity function
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, address = amm.token_a_vault)]
+    pub token_a_reserve_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = amm.token_b_vault)]
+    pub token_b_reserve_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_a: Option<InterfaceAccount<'info, TokenAccount>>, // None to pay native SOL
+    #[account(mut)]
+    pub user_b: Option<InterfaceAccount<'info, TokenAccount>>, // None to pay native SOL
+    #[account(mut, address = amm.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = lp_mint)]
+    pub user_lp: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: temporary wSOL account, created and closed within the instruction
+    #[account(mut, seeds = [WSOL_SEED, user.key().as_ref()], bump)]
+    pub wsol_account: Option<UncheckedAccount<'info>>,
+    #[account(seeds = [ALLOWLIST_SEED, amm.key().as_ref(), user.key().as_ref()], bump = allowlist_entry.bump)]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>, // Required by permissioned pools
+    /// CHECK: the pool's screening program, invoked with the published DenylistCheck layout
+    #[account(executable, address = amm.denylist_program @ ErrorCode::DenylistProgramRequired)]
+    pub denylist_program: Option<UncheckedAccount<'info>>, // Required by screened pools
+    /// CHECK: screening state passed through to the denylist program
+    pub denylist_account: Option<UncheckedAccount<'info>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Token program of the LP mint
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub system_program: Option<Program<'info, System>>,
+}
+
+// Context for RemoveLiquidity function
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, address = amm.token_a_vault)]
+    pub token_a_reserve_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = amm.token_b_vault)]
+    pub token_b_reserve_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = amm.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = lp_mint, token::authority = user)]
+    pub user_lp: InterfaceAccount<'info, TokenAccount>,
+    #[account(seeds = [ALLOWLIST_SEED, amm.key().as_ref(), user.key().as_ref()], bump = allowlist_entry.bump)]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>, // Required by permissioned pools
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Token program of the LP mint
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for DistributeFees function
+#[derive(Accounts)]
+pub struct DistributeFees<'info> {
+    #[account(
+        mut,
+        has_one = token_a_vault,
+        has_one = token_b_vault,
+        has_one = token_a_mint,
+        has_one = token_b_mint,
+        constraint = amm.admin == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub fee_receiver_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub fee_receiver_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for read-only quote views
+#[derive(Accounts)]
+pub struct Quote<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+}
+
+// Context for QuoteSwap function; the discount accounts are optional
+#[derive(Accounts)]
+pub struct QuoteSwap<'info> {
+    #[account(has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds = [TRADER_STATS_SEED, amm.key().as_ref(), trader_stats.trader.as_ref()], bump = trader_stats.bump)]
+    pub trader_stats: Option<Account<'info, TraderStats>>,
+    #[account(seeds = [GOVERNANCE_STAKE_SEED, governance_stake.owner.as_ref()], bump = governance_stake.bump)]
+    pub governance_stake: Option<Account<'info, GovernanceStake>>,
+}
+
+// Context for InitializeOracle function
+#[derive(Accounts)]
+#[instruction(cardinality: u16)]
+pub struct InitializeOracle<'info> {
+    #[account(mut, constraint = amm.admin == user.key() @ ErrorCode::Unauthorized)]
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = user,
+        space = Oracle::space(cardinality),
+        seeds = [ORACLE_SEED, amm.key().as_ref()],
+        bump
+    )]
+    pub oracle: Account<'info, Oracle>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for GrowOracle function
+#[derive(Accounts)]
+#[instruction(cardinality: u16)]
+pub struct GrowOracle<'info> {
+    #[account(
+        mut,
+        seeds = [ORACLE_SEED, oracle.amm.as_ref()],
+        bump = oracle.bump,
+        realloc = Oracle::space(cardinality),
+        realloc::payer = user,
+        realloc::zero = false
+    )]
+    pub oracle: Account<'info, Oracle>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for ObserveTwap view
+#[derive(Accounts)]
+pub struct ObserveTwap<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Account<'info, Oracle>,
+}
+
+// Context for Admin actions (pausing contract)
+#[derive(Accounts)]
+pub struct AdminAction<'info> {
+    #[account(mut, constraint = amm.admin == user.key() @ ErrorCode::Unauthorized)]
+    pub amm: Account<'info, Amm>,
+    pub user: Signer<'info>,
+}
+
+// Context for FlashBorrow function
+#[derive(Accounts)]
+pub struct FlashBorrow<'info> {
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub receiver: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    /// CHECK: address is constrained to the instructions sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for FlashRepay function (the AMM must stay the first account, see flash_borrow)
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub repayer: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for FlashSwap function
+#[derive(Accounts)]
+pub struct FlashSwap<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub receiver_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub receiver_b: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    /// CHECK: arbitrary receiver program, invoked with the published FlashSwapCallback layout
+    #[account(executable)]
+    pub callback_program: UncheckedAccount<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for SwapRoute and SwapSplit functions; pools are passed as remaining accounts
+#[derive(Accounts)]
+pub struct SwapRoute<'info> {
+    #[account(mut)]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+}
+
+// Context for DepositSingle function
+#[derive(Accounts)]
+pub struct DepositSingle<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = amm.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = lp_mint)]
+    pub user_lp: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Token program of the LP mint
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for WithdrawSingle function
+#[derive(Accounts)]
+pub struct WithdrawSingle<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = amm.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = lp_mint, token::authority = user)]
+    pub user_lp: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Token program of the LP mint
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for WithdrawImbalanced function
+#[derive(Accounts)]
+pub struct WithdrawImbalanced<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = amm.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = lp_mint, token::authority = user)]
+    pub user_lp: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Token program of the LP mint
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for PlaceLimitOrder function
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct PlaceLimitOrder<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + LimitOrder::LEN,
+        seeds = [LIMIT_ORDER_SEED, amm.key().as_ref(), owner.key().as_ref(), order_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order: Account<'info, LimitOrder>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [LIMIT_ORDER_ESCROW_SEED, order.key().as_ref()],
+        bump,
+        token::mint = sell_mint,
+        token::authority = order
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    pub sell_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = destination.owner == owner.key() @ ErrorCode::InvalidInput)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for CancelLimitOrder function
+#[derive(Accounts)]
+pub struct CancelLimitOrder<'info> {
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized, has_one = escrow, close = owner)]
+    pub order: Account<'info, LimitOrder>,
+    #[account(mut)]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = escrow.mint)]
+    pub sell_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Context for CrankLimitOrders function; orders are passed as remaining accounts
+#[derive(Accounts)]
+pub struct CrankLimitOrders<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub keeper_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub keeper_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub keeper: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for CreateDcaSchedule function
+#[derive(Accounts)]
+#[instruction(schedule_id: u64)]
+pub struct CreateDcaSchedule<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + DcaSchedule::LEN,
+        seeds = [DCA_SEED, amm.key().as_ref(), owner.key().as_ref(), schedule_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub dca: Account<'info, DcaSchedule>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [DCA_ESCROW_SEED, dca.key().as_ref()],
+        bump,
+        token::mint = sell_mint,
+        token::authority = dca
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    pub sell_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = destination.owner == owner.key() @ ErrorCode::InvalidInput)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for ExecuteDcaCycle function; any keeper may sign
+#[derive(Accounts)]
+pub struct ExecuteDcaCycle<'info> {
+    #[account(mut, has_one = amm, has_one = escrow, has_one = destination, has_one = owner)]
+    pub dca: Account<'info, DcaSchedule>,
+    #[account(mut)]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: the schedule's owner, receiving rent when the schedule completes
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    pub keeper: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for CancelDcaSchedule function
+#[derive(Accounts)]
+pub struct CancelDcaSchedule<'info> {
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized, has_one = escrow, close = owner)]
+    pub dca: Account<'info, DcaSchedule>,
+    #[account(mut)]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = escrow.mint)]
+    pub sell_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Context for InitializeTwamm function
+#[derive(Accounts)]
+pub struct InitializeTwamm<'info> {
+    #[account(mut, constraint = amm.admin == user.key() @ ErrorCode::Unauthorized)]
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + TwammState::LEN,
+        seeds = [TWAMM_SEED, amm.key().as_ref()],
+        bump
+    )]
+    pub twamm: Account<'info, TwammState>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for PlaceLongTermOrder function
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct PlaceLongTermOrder<'info> {
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Account<'info, TwammState>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + LongTermOrder::LEN,
+        seeds = [LONG_TERM_ORDER_SEED, amm.key().as_ref(), owner.key().as_ref(), order_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order: Account<'info, LongTermOrder>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for withdrawing from or cancelling a long-term order
+#[derive(Accounts)]
+pub struct LongTermOrderAction<'info> {
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Account<'info, TwammState>,
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized, has_one = amm)]
+    pub order: Account<'info, LongTermOrder>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_sell_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner_buy_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for ReleaseLongTermOrder function
+#[derive(Accounts)]
+pub struct ReleaseLongTermOrder<'info> {
+    #[account(mut)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Account<'info, TwammState>,
+    #[account(mut, has_one = amm)]
+    pub order: Account<'info, LongTermOrder>,
+}
+
+// Context for ExecuteLongTermOrders function
+#[derive(Accounts)]
+pub struct ExecuteLongTermOrders<'info> {
+    #[account(mut)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Account<'info, TwammState>,
+}
+
+// Context for InitializeFarm function
+#[derive(Accounts)]
+pub struct InitializeFarm<'info> {
+    #[account(has_one = lp_mint, constraint = amm.admin == user.key() @ ErrorCode::Unauthorized)]
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Farm::LEN,
+        seeds = [FARM_SEED, amm.key().as_ref()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [FARM_VAULT_SEED, farm.key().as_ref()],
+        bump,
+        token::mint = lp_mint,
+        token::authority = farm
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for AddFarmReward function
+#[derive(Accounts)]
+pub struct AddFarmReward<'info> {
+    #[account(mut, constraint = farm.authority == user.key() @ ErrorCode::Unauthorized)]
+    pub farm: Account<'info, Farm>,
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [FARM_REWARD_VAULT_SEED, farm.key().as_ref(), reward_mint.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = farm
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub funder_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for Stake function; the position is created on first stake
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(mut, has_one = stake_vault, has_one = lp_mint)]
+    pub farm: Account<'info, Farm>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + StakePosition::LEN,
+        seeds = [STAKE_SEED, farm.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, StakePosition>,
+    #[account(mut)]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_lp: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for Unstake function
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(mut, has_one = stake_vault, has_one = lp_mint)]
+    pub farm: Account<'info, Farm>,
+    #[account(
+        mut,
+        seeds = [STAKE_SEED, farm.key().as_ref(), owner.key().as_ref()],
+        bump = position.bump,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub position: Account<'info, StakePosition>,
+    #[account(mut)]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_lp: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Context for Harvest function; reward streams are passed in remaining accounts
+#[derive(Accounts)]
+pub struct Harvest<'info> {
+    #[account(mut)]
+    pub farm: Account<'info, Farm>,
+    #[account(
+        mut,
+        seeds = [STAKE_SEED, farm.key().as_ref(), owner.key().as_ref()],
+        bump = position.bump,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub position: Account<'info, StakePosition>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Context for LockLiquidity function
+#[derive(Accounts)]
+#[instruction(lock_id: u64)]
+pub struct LockLiquidity<'info> {
+    #[account(mut, has_one = lp_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + LiquidityLock::LEN,
+        seeds = [LOCK_SEED, amm.key().as_ref(), owner.key().as_ref(), lock_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub lock: Account<'info, LiquidityLock>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [LOCK_ESCROW_SEED, lock.key().as_ref()],
+        bump,
+        token::mint = lp_mint,
+        token::authority = lock
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_lp: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for UnlockLiquidity function
+#[derive(Accounts)]
+pub struct UnlockLiquidity<'info> {
+    #[account(mut, has_one = lp_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized, has_one = amm, has_one = escrow)]
+    pub lock: Account<'info, LiquidityLock>,
+    #[account(mut)]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_lp: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Context for ClaimLockedFees function
+#[derive(Accounts)]
+pub struct ClaimLockedFees<'info> {
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized, has_one = amm)]
+    pub lock: Account<'info, LiquidityLock>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner_b: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for OpenPosition function; the position mint is a fresh keypair
+#[derive(Accounts)]
+pub struct OpenPosition<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = owner,
+        mint::decimals = 0,
+        mint::authority = amm,
+        mint::freeze_authority = amm,
+        mint::token_program = nft_token_program
+    )]
+    pub position_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Position::LEN,
+        seeds = [POSITION_SEED, position_mint.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        init,
+        payer = owner,
+        associated_token::mint = position_mint,
+        associated_token::authority = owner,
+        associated_token::token_program = nft_token_program
+    )]
+    pub position_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: Metaplex metadata PDA of the position mint, created by the metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", metadata_program.key().as_ref(), position_mint.key().as_ref()],
+        seeds::program = metadata_program.key(),
+        bump
+    )]
+    pub metadata: UncheckedAccount<'info>,
+    /// CHECK: Metaplex master edition PDA of the position mint, created by the metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", metadata_program.key().as_ref(), position_mint.key().as_ref(), b"edition"],
+        seeds::program = metadata_program.key(),
+        bump
+    )]
+    pub master_edition: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub nft_token_program: Program<'info, Token>, // Metaplex editions require the original token program
+    pub metadata_program: Program<'info, Metadata>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for withdrawing from a position or collecting its fees; the signer must hold the NFT
+#[derive(Accounts)]
+pub struct PositionAction<'info> {
+    #[account(mut, seeds = [ORACLE_SEED, amm.key().as_ref()], bump = oracle.bump)]
+    pub oracle: Option<Account<'info, Oracle>>,
+    #[account(mut, seeds = [TWAMM_SEED, amm.key().as_ref()], bump = twamm.bump)]
+    pub twamm: Option<Account<'info, TwammState>>,
+    #[account(mut, has_one = token_a_vault, has_one = token_b_vault, has_one = token_a_mint, has_one = token_b_mint)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, seeds = [POSITION_SEED, position.position_mint.as_ref()], bump = position.bump, has_one = amm)]
+    pub position: Account<'info, Position>,
+    #[account(
+        constraint = position_token.mint == position.position_mint
+            && position_token.owner == owner.key()
+            && position_token.amount == 1 @ ErrorCode::Unauthorized
+    )]
+    pub position_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner_b: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+// Context for LockPosition function; the signer must hold the NFT
+#[derive(Accounts)]
+pub struct LockPosition<'info> {
+    #[account(mut, seeds = [POSITION_SEED, position.position_mint.as_ref()], bump = position.bump)]
+    pub position: Account<'info, Position>,
+    #[account(
+        constraint = position_token.mint == position.position_mint
+            && position_token.owner == owner.key()
+            && position_token.amount == 1 @ ErrorCode::Unauthorized
+    )]
+    pub position_token: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+}
+
+// Context for AddAllowlistEntry function
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddAllowlistEntry<'info> {
+    #[account(constraint = amm.allowlist_authority == authority.key() @ ErrorCode::Unauthorized)]
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + AllowlistEntry::LEN,
+        seeds = [ALLOWLIST_SEED, amm.key().as_ref(), wallet.as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for RemoveAllowlistEntry function; rent goes to the allowlist authority
+#[derive(Accounts)]
+pub struct RemoveAllowlistEntry<'info> {
+    #[account(constraint = amm.allowlist_authority == authority.key() @ ErrorCode::Unauthorized)]
+    pub amm: Account<'info, Amm>,
+    #[account(mut, has_one = amm, close = authority)]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+// Context for ClaimAllowlistEntry function
+#[derive(Accounts)]
+pub struct ClaimAllowlistEntry<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init_if_needed,
+        payer = wallet,
+        space = 8 + AllowlistEntry::LEN,
+        seeds = [ALLOWLIST_SEED, amm.key().as_ref(), wallet.key().as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+    #[account(mut)]
+    pub wallet: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for RegisterReferrer function
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        space = 8 + ReferrerStats::LEN,
+        seeds = [REFERRER_SEED, amm.key().as_ref(), referrer.key().as_ref()],
+        bump
+    )]
+    pub referrer_stats: Account<'info, ReferrerStats>,
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for InitializeTraderStats function
+#[derive(Accounts)]
+pub struct InitializeTraderStats<'info> {
+    pub amm: Account<'info, Amm>,
+    #[account(
+        init,
+        payer = trader,
+        space = 8 + TraderStats::LEN,
+        seeds = [TRADER_STATS_SEED, amm.key().as_ref(), trader.key().as_ref()],
+        bump
+    )]
+    pub trader_stats: Account<'info, TraderStats>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for InitializeGovernanceStaking function
+#[derive(Accounts)]
+pub struct InitializeGovernanceStaking<'info> {
+    #[account(seeds = [FEE_TIERS_SEED], bump = fee_tiers.bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub fee_tiers: Account<'info, FeeTierTable>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + GovernanceStaking::LEN,
+        seeds = [GOVERNANCE_STAKING_SEED],
+        bump
+    )]
+    pub governance_staking: Account<'info, GovernanceStaking>,
+    pub governance_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [GOVERNANCE_VAULT_SEED],
+        bump,
+        token::mint = governance_mint,
+        token::authority = governance_staking
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Context for StakeGovernance function; the stake is created on first use
+#[derive(Accounts)]
+pub struct StakeGovernance<'info> {
+    #[account(mut, seeds = [GOVERNANCE_STAKING_SEED], bump = governance_staking.bump, has_one = governance_mint, has_one = vault)]
+    pub governance_staking: Account<'info, GovernanceStaking>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + GovernanceStake::LEN,
+        seeds = [GOVERNANCE_STAKE_SEED, owner.key().as_ref()],
+        bump
+    )]
+    pub governance_stake: Account<'info, GovernanceStake>,
+    pub governance_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for UnstakeGovernance function
+#[derive(Accounts)]
+pub struct UnstakeGovernance<'info> {
+    #[account(mut, seeds = [GOVERNANCE_STAKING_SEED], bump = governance_staking.bump, has_one = governance_mint, has_one = vault)]
+    pub governance_staking: Account<'info, GovernanceStaking>,
+    #[account(
+        mut,
+        seeds = [GOVERNANCE_STAKE_SEED, owner.key().as_ref()],
+        bump = governance_stake.bump,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub governance_stake: Account<'info, GovernanceStake>,
+    pub governance_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner_token: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Custom error codes for better error handling
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Invalid input.")]
+    InvalidInput,
+    #[msg("Slippage exceeded the allowed limit.")]
+    SlippageExceeded,
+    #[msg("The contract is paused.")]
+    ContractPaused,
+    #[msg("The signer is not authorized to perform this action.")]
+    Unauthorized,
+    #[msg("The requested fee tier does not exist.")]
+    InvalidFeeTier,
+    #[msg("A fee tier with this fee already exists.")]
+    FeeTierAlreadyExists,
+    #[msg("The fee-tier table is full.")]
+    FeeTierTableFull,
+    #[msg("Token mints must be ordered so that token A's key is lower than token B's.")]
+    InvalidMintOrder,
+    #[msg("The pool does not have enough liquidity.")]
+    InsufficientLiquidity,
+    #[msg("Arithmetic overflow.")]
+    MathOverflow,
+    #[msg("The requested timestamp is older than the oldest oracle observation.")]
+    OracleObservationTooOld,
+    #[msg("The pool is locked by an outstanding flash loan.")]
+    PoolLocked,
+    #[msg("No matching flash_repay instruction follows this flash_borrow.")]
+    FlashLoanNotRepaid,
+    #[msg("There is no outstanding flash loan to repay.")]
+    NoFlashLoanOutstanding,
+    #[msg("No input was paid back to the pool.")]
+    InsufficientInputAmount,
+    #[msg("The constant product invariant does not hold after fees.")]
+    InvariantViolated,
+    #[msg("The swap route is invalid.")]
+    InvalidRoute,
+    #[msg("This pool has long-term orders; its TWAMM state account must be provided.")]
+    TwammStateRequired,
+    #[msg("The long-term order expiry schedule is full.")]
+    TwammScheduleFull,
+    #[msg("The long-term order has expired; withdraw its proceeds instead.")]
+    LongTermOrderExpired,
+    #[msg("The next DCA cycle is not due yet.")]
+    DcaCycleNotDue,
+    #[msg("The transaction deadline has passed.")]
+    Expired,
+    #[msg("The trade moves the price more than allowed.")]
+    PriceImpactTooHigh,
+    #[msg("The mint uses a Token-2022 extension this pool does not support.")]
+    UnsupportedTokenExtension,
+    #[msg("Native SOL needs the wSOL account and system program, and only works for the wrapped SOL side of a pool.")]
+    InvalidNativeSol,
+    #[msg("The farm already has the maximum number of reward streams.")]
+    FarmRewardsFull,
+    #[msg("No locked liquidity has vested yet.")]
+    LiquidityLocked,
+    #[msg("The wallet is not allowlisted for this pool.")]
+    NotAllowlisted,
+    #[msg("This pool screens its users or hooks its swaps and only supports swap, add_liquidity and remove_liquidity.")]
+    RestrictedPool,
+    #[msg("This pool screens its users; its denylist program must be provided.")]
+    DenylistProgramRequired,
+    #[msg("This pool has a swap hook; its hook program must be provided.")]
+    SwapHookRequired,
+    #[msg("The referrer account must be the registered referrer's account for the input token.")]
+    InvalidReferrer,
+    #[msg("This pool has a price oracle; its oracle account must be provided.")]
+    OracleRequired,
+    #[msg("The pool's lock was released during the flash swap callback.")]
+    FlashSwapReentered,
+    #[msg("The long-term order's sell rate is below the pool's minimum.")]
+    LongTermOrderTooSmall,
+    #[msg("The long-term order has not expired yet.")]
+    LongTermOrderNotExpired,
+    #[msg("Long-term orders are not settled up to the current slot; call execute_long_term_orders first.")]
+    TwammSettlementPending,
+    #[msg("The trade moves the price further than the circuit breaker allows within one slot.")]
+    PriceMoveTooLarge,
+    #[msg("The governance stake is still locked.")]
+    StakeLocked,
+    #[msg("Pools with long-term orders cannot screen their users or hook their swaps.")]
+    TwammPoolRestricted,
+}
+
+// Events
+#[event]
+pub struct SwapEvent {
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub from_a_to_b: bool,
+    pub execution_price: u128, // Price of A in B paid by this trade, decimal-normalized, Q64.64
+    pub reserve_a: u64, // Reserves after the trade
+    pub reserve_b: u64,
+    pub referrer: Option<Pubkey>,
+    pub referral_fee: u64, // Received by the referrer out of the protocol fee, net of any transfer fee, in the input token
+}
+
+#[event]
+pub struct AddLiquidityEvent {
+    pub user: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub shares: u64,
+}
+
+#[event]
 pub struct RemoveLiquidityEvent {
     pub user: Pubkey,
     pub amount_a: u64,
@@ -373,3 +6098,206 @@ pub struct FeeDistributedEvent {
     pub amount_a: u64,
     pub amount_b: u64,
 }
+
+#[event]
+pub struct FeeTierAddedEvent {
+    pub fee_tier: u8,
+    pub fee: u64,
+}
+
+#[event]
+pub struct PoolCreatedEvent {
+    pub amm: Pubkey,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub fee_tier: u8,
+    pub fee: u64,
+}
+
+#[event]
+pub struct FlashLoanEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub token_a: bool,
+}
+
+#[event]
+pub struct FlashSwapEvent {
+    pub user: Pubkey,
+    pub callback_program: Pubkey,
+    pub amount_a_in: u64,
+    pub amount_b_in: u64,
+    pub amount_a_out: u64,
+    pub amount_b_out: u64,
+}
+
+#[event]
+pub struct RouteSwapEvent {
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub hops: u8,
+}
+
+#[event]
+pub struct SplitSwapEvent {
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub legs: u8,
+}
+
+#[event]
+pub struct DepositSingleEvent {
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub token_a: bool,
+    pub swap_amount: u64, // Portion of the input swapped internally
+    pub shares: u64,
+}
+
+#[event]
+pub struct WithdrawSingleEvent {
+    pub user: Pubkey,
+    pub shares: u64,
+    pub token_a: bool,
+    pub amount_out: u64,
+}
+
+#[event]
+pub struct LimitOrderFilledEvent {
+    pub order: Pubkey,
+    pub owner: Pubkey,
+    pub keeper: Pubkey,
+    pub sell_token_a: bool,
+    pub amount_in: u64,
+    pub amount_out: u64, // Amount received by the owner, net of the keeper bounty
+    pub keeper_bounty: u64,
+}
+
+#[event]
+pub struct LongTermOrderPlacedEvent {
+    pub order: Pubkey,
+    pub owner: Pubkey,
+    pub sell_token_a: bool,
+    pub sell_rate: u64,
+    pub expiry_slot: u64,
+}
+
+#[event]
+pub struct LongTermOrderWithdrawnEvent {
+    pub order: Pubkey,
+    pub owner: Pubkey,
+    pub proceeds: u64,
+    pub refund: u64, // Unsold tokens returned on cancellation
+}
+
+#[event]
+pub struct DcaCycleExecutedEvent {
+    pub schedule: Pubkey,
+    pub owner: Pubkey,
+    pub keeper: Pubkey,
+    pub sell_token_a: bool,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub cycles_remaining: u64,
+}
+
+#[event]
+pub struct CircuitBreakerBreachEvent {
+    pub amm: Pubkey,
+    pub slot: u64,
+    pub price_move_bps: u64,
+    pub breaches: u8,
+}
+
+#[event]
+pub struct CircuitBreakerTrippedEvent {
+    pub amm: Pubkey,
+    pub slot: u64,
+    pub breaches: u8,
+}
+
+#[event]
+pub struct FarmRewardAddedEvent {
+    pub farm: Pubkey,
+    pub mint: Pubkey,
+    pub emission_rate: u64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct StakeEvent {
+    pub farm: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub total_staked: u64,
+}
+
+#[event]
+pub struct UnstakeEvent {
+    pub farm: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub total_staked: u64,
+}
+
+#[event]
+pub struct HarvestEvent {
+    pub farm: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct LiquidityLockedEvent {
+    pub lock: Pubkey,
+    pub owner: Pubkey,
+    pub amm: Pubkey,
+    pub shares: u64,
+    pub unlock_start: i64,
+    pub unlock_end: i64,
+}
+
+#[event]
+pub struct LiquidityUnlockedEvent {
+    pub lock: Pubkey,
+    pub owner: Pubkey,
+    pub shares: u64,
+    pub shares_remaining: u64,
+}
+
+#[event]
+pub struct LockedFeesClaimedEvent {
+    pub lock: Pubkey,
+    pub owner: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct PositionOpenedEvent {
+    pub position: Pubkey,
+    pub position_mint: Pubkey,
+    pub owner: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub shares: u64,
+}
+
+#[event]
+pub struct PositionFeesCollectedEvent {
+    pub position: Pubkey,
+    pub owner: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct GovernanceStakeEvent {
+    pub owner: Pubkey,
+    pub amount: u64, // Stake after the change
+    pub unlock_time: i64,
+}
@@ -0,0 +1,113 @@
+use anchor_lang::prelude::*;
+
+// Program ID
+declare_id!("4HmCyhnS8WL4Hc9HbUSzebenyA1ZQnaQF4Z8EUcwK9yT");
+
+// PDA seeds
+pub const DENYLIST_SEED: &[u8] = b"denylist";
+
+// Upper bound on the number of wallets one denylist can block
+pub const MAX_BLOCKED: usize = 32;
+
+// Sample screening program: keeps a list of blocked wallets per pool and fails the AMM's
+// check_denylist call for any of them. The denylist is passed to swap and add_liquidity as
+// their denylist account.
+#[program]
+pub mod sample_denylist {
+    use super::*;
+
+    // Create the denylist for a pool
+    pub fn initialize(ctx: Context<Initialize>, amm: Pubkey) -> Result<()> {
+        let denylist = &mut ctx.accounts.denylist;
+        denylist.authority = ctx.accounts.authority.key();
+        denylist.amm = amm;
+        denylist.blocked = Vec::new();
+        denylist.bump = ctx.bumps.denylist;
+        Ok(())
+    }
+
+    // Block a wallet (authority only)
+    pub fn block(ctx: Context<Update>, wallet: Pubkey) -> Result<()> {
+        let denylist = &mut ctx.accounts.denylist;
+        if denylist.blocked.contains(&wallet) {
+            return Ok(());
+        }
+        if denylist.blocked.len() >= MAX_BLOCKED {
+            return Err(ErrorCode::DenylistFull.into());
+        }
+        denylist.blocked.push(wallet);
+        Ok(())
+    }
+
+    // Unblock a wallet (authority only)
+    pub fn unblock(ctx: Context<Update>, wallet: Pubkey) -> Result<()> {
+        ctx.accounts.denylist.blocked.retain(|blocked| *blocked != wallet);
+        Ok(())
+    }
+
+    // Called by the AMM before swaps and deposits; arguments follow the DenylistCheck layout
+    pub fn check_denylist(ctx: Context<CheckDenylist>, amm: Pubkey, user: Pubkey) -> Result<()> {
+        let denylist = &ctx.accounts.denylist;
+        if denylist.amm != amm {
+            return Err(ErrorCode::WrongPool.into());
+        }
+        if denylist.blocked.contains(&user) {
+            return Err(ErrorCode::Denied.into());
+        }
+        Ok(())
+    }
+}
+
+// Blocked wallets of one pool
+#[account]
+pub struct Denylist {
+    pub authority: Pubkey,
+    pub amm: Pubkey,
+    pub blocked: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl Denylist {
+    pub const LEN: usize = 32 * 2 + (4 + 32 * MAX_BLOCKED) + 1;
+}
+
+// Context for Initialize function
+#[derive(Accounts)]
+#[instruction(amm: Pubkey)]
+pub struct Initialize<'info> {
+    #[account(init, payer = authority, space = 8 + Denylist::LEN, seeds = [DENYLIST_SEED, amm.as_ref()], bump)]
+    pub denylist: Account<'info, Denylist>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for Block and Unblock functions
+#[derive(Accounts)]
+pub struct Update<'info> {
+    #[account(mut, has_one = authority)]
+    pub denylist: Account<'info, Denylist>,
+    pub authority: Signer<'info>,
+}
+
+// Context for CheckDenylist function
+#[derive(Accounts)]
+pub struct CheckDenylist<'info> {
+    /// CHECK: the calling pool, matched against the denylist
+    pub amm: UncheckedAccount<'info>,
+    /// CHECK: the wallet being screened
+    pub user: UncheckedAccount<'info>,
+    #[account(seeds = [DENYLIST_SEED, amm.key().as_ref()], bump = denylist.bump)]
+    pub denylist: Account<'info, Denylist>,
+}
+
+// Error codes
+#[error_code]
+pub enum ErrorCode {
+    #[msg("The wallet is on the pool's denylist.")]
+    Denied,
+    #[msg("The denylist belongs to another pool.")]
+    WrongPool,
+    #[msg("The denylist cannot block any more wallets.")]
+    DenylistFull,
+}
@@ -0,0 +1,73 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+// Program ID
+declare_id!("BsK11DbxrfkWjQJb9k6Y4n7vAaSfEGCJ3fkHa2pS2Zr1");
+
+// Sample flash swap receiver: pays the pool back from the sender's token accounts. The callback
+// data carries the repayment as a Borsh-encoded Repayment; the sender's token accounts, both mints
+// and the token program are passed to flash_swap as its remaining accounts.
+#[program]
+pub mod sample_flash_swap_receiver {
+    use super::*;
+
+    // Called by the AMM after it sent the output; arguments follow the FlashSwapCallback layout
+    pub fn flash_swap_callback(
+        ctx: Context<FlashSwapCallback>,
+        _amm: Pubkey,
+        _sender: Pubkey,
+        _amount_a_out: u64,
+        _amount_b_out: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let repayment = Repayment::try_from_slice(&data)?;
+
+        // The sender signed the flash swap, so its signature carries over to these transfers
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        if repayment.amount_a > 0 {
+            let cpi_accounts_a = TransferChecked {
+                from: ctx.accounts.sender_a.to_account_info(),
+                mint: ctx.accounts.token_a_mint.to_account_info(),
+                to: ctx.accounts.token_a_vault.to_account_info(),
+                authority: ctx.accounts.sender.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new(cpi_program.clone(), cpi_accounts_a), repayment.amount_a, ctx.accounts.token_a_mint.decimals)?;
+        }
+        if repayment.amount_b > 0 {
+            let cpi_accounts_b = TransferChecked {
+                from: ctx.accounts.sender_b.to_account_info(),
+                mint: ctx.accounts.token_b_mint.to_account_info(),
+                to: ctx.accounts.token_b_vault.to_account_info(),
+                authority: ctx.accounts.sender.to_account_info(),
+            };
+            token_interface::transfer_checked(CpiContext::new(cpi_program, cpi_accounts_b), repayment.amount_b, ctx.accounts.token_b_mint.decimals)?;
+        }
+        Ok(())
+    }
+}
+
+// Amounts paid back into each vault
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Repayment {
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+// Context for FlashSwapCallback function
+#[derive(Accounts)]
+pub struct FlashSwapCallback<'info> {
+    /// CHECK: the calling pool
+    pub amm: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    pub sender: Signer<'info>,
+    #[account(mut)]
+    pub sender_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub sender_b: InterfaceAccount<'info, TokenAccount>,
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
@@ -0,0 +1,142 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::set_return_data;
+use swap_hook_interface::PreSwapResult;
+
+// Program ID
+declare_id!("9pSWtQh19cr6hGGAn2XAm1sNuoERJaXjWfqwwF7aMF9p");
+
+// PDA seeds
+pub const CONFIG_SEED: &[u8] = b"config";
+
+// Sample swap hook: caps the size of each trade, optionally overrides the pool fee and counts
+// loyalty points (trades and input volume) per pool. The hook config is passed to swap as its
+// first remaining account.
+#[program]
+pub mod sample_swap_hook {
+    use super::*;
+
+    // Create the hook config for a pool
+    pub fn initialize(ctx: Context<Initialize>, amm: Pubkey, max_amount_in: u64, fee_override: Option<u64>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.authority = ctx.accounts.authority.key();
+        config.amm = amm;
+        config.max_amount_in = max_amount_in;
+        config.fee_override = fee_override;
+        config.trades = 0;
+        config.volume = 0;
+        config.bump = ctx.bumps.config;
+        Ok(())
+    }
+
+    // Update the trade limit and fee override (authority only)
+    pub fn update(ctx: Context<Update>, max_amount_in: u64, fee_override: Option<u64>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.max_amount_in = max_amount_in;
+        config.fee_override = fee_override;
+        Ok(())
+    }
+
+    // Called by the AMM before the trade; arguments follow the SwapHookArgs layout
+    pub fn pre_swap(
+        ctx: Context<SwapHook>,
+        amm: Pubkey,
+        _user: Pubkey,
+        amount_in: u64,
+        _amount_out: u64,
+        _from_a_to_b: bool,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        if config.amm != amm {
+            return Err(ErrorCode::WrongPool.into());
+        }
+
+        // Trade limit
+        if config.max_amount_in > 0 && amount_in > config.max_amount_in {
+            return Err(ErrorCode::TradeTooLarge.into());
+        }
+
+        // Dynamic fee override
+        if config.fee_override.is_some() {
+            let result = PreSwapResult {
+                fee_override: config.fee_override,
+            };
+            set_return_data(&result.try_to_vec()?);
+        }
+        Ok(())
+    }
+
+    // Called by the AMM after the trade; arguments follow the SwapHookArgs layout
+    pub fn post_swap(
+        ctx: Context<SwapHook>,
+        amm: Pubkey,
+        _user: Pubkey,
+        amount_in: u64,
+        _amount_out: u64,
+        _from_a_to_b: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        if config.amm != amm {
+            return Err(ErrorCode::WrongPool.into());
+        }
+
+        // Loyalty points
+        config.trades += 1;
+        config.volume = config.volume.saturating_add(amount_in);
+        Ok(())
+    }
+}
+
+// Hook configuration for one pool
+#[account]
+pub struct HookConfig {
+    pub authority: Pubkey,
+    pub amm: Pubkey,
+    pub max_amount_in: u64, // Largest input accepted per trade (0 for no limit)
+    pub fee_override: Option<u64>, // Fee in basis points applied instead of the pool fee
+    pub trades: u64,
+    pub volume: u64, // Total input traded through the pool
+    pub bump: u8,
+}
+
+impl HookConfig {
+    pub const LEN: usize = 32 * 2 + 8 + (1 + 8) + 8 * 2 + 1;
+}
+
+// Context for Initialize function
+#[derive(Accounts)]
+#[instruction(amm: Pubkey)]
+pub struct Initialize<'info> {
+    #[account(init, payer = authority, space = 8 + HookConfig::LEN, seeds = [CONFIG_SEED, amm.as_ref()], bump)]
+    pub config: Account<'info, HookConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Context for Update function
+#[derive(Accounts)]
+pub struct Update<'info> {
+    #[account(mut, has_one = authority)]
+    pub config: Account<'info, HookConfig>,
+    pub authority: Signer<'info>,
+}
+
+// Context for PreSwap and PostSwap functions
+#[derive(Accounts)]
+pub struct SwapHook<'info> {
+    /// CHECK: the calling pool, matched against the config
+    pub amm: UncheckedAccount<'info>,
+    /// CHECK: the trader
+    pub user: UncheckedAccount<'info>,
+    #[account(mut, seeds = [CONFIG_SEED, amm.key().as_ref()], bump = config.bump)]
+    pub config: Account<'info, HookConfig>,
+}
+
+// Error codes
+#[error_code]
+pub enum ErrorCode {
+    #[msg("The trade exceeds the hook's size limit.")]
+    TradeTooLarge,
+    #[msg("The hook config belongs to another pool.")]
+    WrongPool,
+}
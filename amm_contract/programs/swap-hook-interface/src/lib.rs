@@ -0,0 +1,75 @@
+// Instruction interface between AMM pools and their swap hook programs.
+//
+// A pool with a registered hook invokes `pre_swap` before trading and `post_swap` after the
+// tokens have moved. Both instructions carry the same data: an 8-byte discriminator followed by
+// `SwapHookArgs` (Borsh), which matches an Anchor handler declared as
+// `pub fn pre_swap(ctx, amm: Pubkey, user: Pubkey, amount_in: u64, amount_out: u64, from_a_to_b: bool)`.
+//
+// Accounts: [amm (readonly), user (readonly)], followed by the remaining accounts passed to swap,
+// in order. A hook rejects a trade by failing the instruction. `pre_swap` may also set return data
+// to a Borsh-encoded `PreSwapResult` to override the pool fee for this trade.
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+
+// Sighash preimages of the hook instructions
+pub const PRE_SWAP_PREIMAGE: &[u8] = b"global:pre_swap";
+pub const POST_SWAP_PREIMAGE: &[u8] = b"global:post_swap";
+
+// Fixed context passed to both hooks
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapHookArgs {
+    pub amm: Pubkey,
+    pub user: Pubkey,
+    pub amount_in: u64,  // Input credited to the pool
+    pub amount_out: u64, // Quoted output at the pool fee in pre_swap, actual output in post_swap
+    pub from_a_to_b: bool,
+}
+
+// Optional return data of pre_swap
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PreSwapResult {
+    pub fee_override: Option<u64>, // Fee in basis points for this trade instead of the pool fee
+}
+
+// Instruction discriminator of pre_swap
+pub fn pre_swap_discriminator() -> [u8; 8] {
+    discriminator(PRE_SWAP_PREIMAGE)
+}
+
+// Instruction discriminator of post_swap
+pub fn post_swap_discriminator() -> [u8; 8] {
+    discriminator(POST_SWAP_PREIMAGE)
+}
+
+// Build a pre_swap instruction for `hook_program`; `extra_accounts` follow [amm, user]
+pub fn pre_swap_instruction(hook_program: Pubkey, args: &SwapHookArgs, extra_accounts: Vec<AccountMeta>) -> Result<Instruction> {
+    instruction(hook_program, pre_swap_discriminator(), args, extra_accounts)
+}
+
+// Build a post_swap instruction for `hook_program`; `extra_accounts` follow [amm, user]
+pub fn post_swap_instruction(hook_program: Pubkey, args: &SwapHookArgs, extra_accounts: Vec<AccountMeta>) -> Result<Instruction> {
+    instruction(hook_program, post_swap_discriminator(), args, extra_accounts)
+}
+
+fn discriminator(preimage: &[u8]) -> [u8; 8] {
+    let hash = anchor_lang::solana_program::hash::hash(preimage);
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+fn instruction(hook_program: Pubkey, discriminator: [u8; 8], args: &SwapHookArgs, extra_accounts: Vec<AccountMeta>) -> Result<Instruction> {
+    let mut data = discriminator.to_vec();
+    args.serialize(&mut data)?;
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(args.amm, false),
+        AccountMeta::new_readonly(args.user, false),
+    ];
+    accounts.extend(extra_accounts);
+    Ok(Instruction {
+        program_id: hook_program,
+        accounts,
+        data,
+    })
+}
@@ -3,14 +3,36 @@ import assert from "assert";
 import * as web3 from "@solana/web3.js";
 import * as anchor from "@coral-xyz/anchor";
 import type { AmmContract } from "../target/types/amm_contract";
+import type { SampleSwapHook } from "../target/types/sample_swap_hook";
+import type { SampleFlashSwapReceiver } from "../target/types/sample_flash_swap_receiver";
+import type { SampleDenylist } from "../target/types/sample_denylist";
+
+// Token-2022, for mints with extensions such as transfer fees
+const TOKEN_2022_PROGRAM_ID = new web3.PublicKey("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+// Wrapped SOL, traded as native lamports when a side has no token account
+const NATIVE_MINT = new web3.PublicKey("So11111111111111111111111111111111111111112");
 
 describe("AMM Contract Tests", () => {
   // Configure the client to use the local cluster
   anchor.setProvider(anchor.AnchorProvider.env());
 
   const program = anchor.workspace.AmmContract as anchor.Program<AmmContract>;
+  const hookProgram = anchor.workspace.SampleSwapHook as anchor.Program<SampleSwapHook>;
+  const receiverProgram = anchor.workspace.SampleFlashSwapReceiver as anchor.Program<SampleFlashSwapReceiver>;
+  const denylistProgram = anchor.workspace.SampleDenylist as anchor.Program<SampleDenylist>;
   
-  let ammAccount: web3.Keypair;
+  let feeTiers: web3.PublicKey;
+  let ammAccount: web3.PublicKey;
+  let tokenAVault: web3.PublicKey;
+  let tokenBVault: web3.PublicKey;
+  let oracle: web3.PublicKey;
+  let lpMint: web3.PublicKey;
+  let userLpAccount: web3.PublicKey;
+  let farm: web3.PublicKey;
+  let stakeVault: web3.PublicKey;
+  let rewardMint: web3.PublicKey;
+  let rewardVault: web3.PublicKey;
+  let userRewardAccount: web3.PublicKey;
   let mintA: web3.PublicKey;
   let mintB: web3.PublicKey;
   let userAAccount: web3.PublicKey;
@@ -20,7 +42,6 @@ describe("AMM Contract Tests", () => {
 
   before(async () => {
     // Initialize accounts and mints before running tests
-    ammAccount = new web3.Keypair();
     user = pg.wallet; // Use the default wallet in Solana Playground
     mintAuthority = new web3.Keypair();
 
@@ -31,6 +52,30 @@ describe("AMM Contract Tests", () => {
     mintA = await createMint(mintAuthority.publicKey);
     mintB = await createMint(mintAuthority.publicKey);
 
+    // Pools are keyed by the canonically ordered mint pair
+    if (Buffer.compare(mintA.toBuffer(), mintB.toBuffer()) > 0) {
+      [mintA, mintB] = [mintB, mintA];
+    }
+
+    // Derive the fee-tier table, pool and vault PDAs (0.3% tier)
+    [feeTiers] = web3.PublicKey.findProgramAddressSync([Buffer.from("fee_tiers")], program.programId);
+    [ammAccount] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("amm"), mintA.toBuffer(), mintB.toBuffer(), Buffer.from([2])],
+      program.programId
+    );
+    [tokenAVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), ammAccount.toBuffer(), mintA.toBuffer()],
+      program.programId
+    );
+    [tokenBVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), ammAccount.toBuffer(), mintB.toBuffer()],
+      program.programId
+    );
+    [oracle] = web3.PublicKey.findProgramAddressSync([Buffer.from("oracle"), ammAccount.toBuffer()], program.programId);
+    [lpMint] = web3.PublicKey.findProgramAddressSync([Buffer.from("lp_mint"), ammAccount.toBuffer()], program.programId);
+    [farm] = web3.PublicKey.findProgramAddressSync([Buffer.from("farm"), ammAccount.toBuffer()], program.programId);
+    [stakeVault] = web3.PublicKey.findProgramAddressSync([Buffer.from("farm_vault"), farm.toBuffer()], program.programId);
+
     // Create token accounts for user to hold Token A and Token B
     userAAccount = await createTokenAccount(mintA, user.publicKey);
     userBAccount = await createTokenAccount(mintB, user.publicKey);
@@ -40,29 +85,95 @@ describe("AMM Contract Tests", () => {
     await mintTokens(mintB, userBAccount, mintAuthority, 1000);
   });
 
+  it("Initializes the fee-tier table", async () => {
+    const txHash = await program.methods
+      .initializeFeeTiers()
+      .accounts({
+        feeTiers,
+        authority: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    console.log(`Fee-tier table initialized with transaction: ${txHash}`);
+
+    const table = await program.account.feeTierTable.fetch(feeTiers);
+    assert.deepEqual(table.fees.map((fee) => fee.toNumber()), [1, 5, 30, 100], "Default tiers should be seeded");
+  });
+
   it("Initializes the AMM", async () => {
-    const fee = new anchor.BN(30); // 0.3% fee
+    const feeTier = 2; // 0.3% fee
 
-    // Initialize the AMM with a fee of 30
+    // Initialize the AMM for the mint pair at the 0.3% tier
     const txHash = await program.methods
-      .initialize(fee)
+      .initialize(feeTier)
       .accounts({
-        amm: ammAccount.publicKey,
+        feeTiers,
+        amm: ammAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAVault,
+        tokenBVault,
+        lpMint,
         user: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
         systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
       })
-      .signers([ammAccount])
       .rpc();
 
     console.log(`AMM initialized with transaction: ${txHash}`);
 
+    // LP tokens are minted to this account when adding liquidity
+    userLpAccount = await createTokenAccount(lpMint, user.publicKey);
+
     // Fetch the AMM account to verify initialization
-    const amm = await program.account.amm.fetch(ammAccount.publicKey);
+    const amm = await program.account.amm.fetch(ammAccount);
     console.log("AMM state after initialization:", amm);
 
     assert.equal(amm.fee.toNumber(), 30, "AMM fee should be 0.3%");
     assert.equal(amm.tokenAReserve.toNumber(), 0, "Token A reserve should be 0");
     assert.equal(amm.tokenBReserve.toNumber(), 0, "Token B reserve should be 0");
+    assert.equal(amm.feeTier, feeTier, "AMM should record its fee tier");
+  });
+
+  it("Initializes the TWAP oracle", async () => {
+    const cardinality = 8;
+
+    // Creating the oracle makes every swap require it, so only the pool admin may do it
+    try {
+      await program.methods
+        .initializeOracle(cardinality)
+        .accounts({
+          amm: ammAccount,
+          oracle,
+          user: mintAuthority.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([mintAuthority])
+        .rpc();
+      assert.fail("A non-admin should not create the oracle");
+    } catch (err) {
+      assert(err.toString().includes("Unauthorized"), "Oracle creation by a non-admin should be rejected");
+    }
+
+    const txHash = await program.methods
+      .initializeOracle(cardinality)
+      .accounts({
+        amm: ammAccount,
+        oracle,
+        user: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    console.log(`Oracle initialized with transaction: ${txHash}`);
+
+    const oracleState = await program.account.oracle.fetch(oracle);
+    assert.equal(oracleState.cardinality, cardinality, "Oracle cardinality should match");
+    assert.equal(oracleState.observations.length, 1, "Oracle should start with one observation");
   });
 
   it("Adds liquidity", async () => {
@@ -73,78 +184,2344 @@ describe("AMM Contract Tests", () => {
     const txHash = await program.methods
       .addLiquidity(amountA, amountB)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
+        oracle,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAReserveAccount: tokenAVault,
+        tokenBReserveAccount: tokenBVault,
         user: user.publicKey,
         userA: userAAccount,
         userB: userBAccount,
+        lpMint,
+        userLp: userLpAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        denylistProgram: null,
+        denylistAccount: null,
+        wsolAccount: null,
+        systemProgram: null,
       })
       .rpc();
 
     console.log(`Liquidity added with transaction: ${txHash}`);
 
     // Fetch the AMM account to verify reserves
-    const amm = await program.account.amm.fetch(ammAccount.publicKey);
+    const amm = await program.account.amm.fetch(ammAccount);
     console.log("AMM state after adding liquidity:", amm);
 
     assert.equal(amm.tokenAReserve.toNumber(), 500, "Token A reserve should be 500");
     assert.equal(amm.tokenBReserve.toNumber(), 500, "Token B reserve should be 500");
+
+    const lpBalance = await program.provider.connection.getTokenAccountBalance(userLpAccount);
+    assert.equal(lpBalance.value.amount, "500", "LP tokens should be minted for the new shares");
+  });
+
+  it("Quotes a swap", async () => {
+    const quote = await program.methods
+      .quoteSwap(new anchor.BN(100), true)
+      .accounts({
+        amm: ammAccount,
+        twamm: null,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        traderStats: null,
+        governanceStake: null,
+      })
+      .view();
+
+    console.log("Swap quote:", quote);
+
+    // 100 in against 500/500 reserves at 0.3% fee
+    assert.equal(quote.amountOut.toNumber(), 83, "Quote should match the constant product formula");
   });
 
   it("Performs a swap", async () => {
     const amountIn = new anchor.BN(100);
-    const minimumOutput = new anchor.BN(90); // Slippage protection
+    const minimumOutput = new anchor.BN(80); // Slippage protection
     const fromAtoB = true; // Swap from Token A to Token B
 
     // Perform the swap
     const txHash = await program.methods
-      .swap(amountIn, fromAtoB, minimumOutput)
+      .swap(amountIn, fromAtoB, minimumOutput, null, 5000)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
+        oracle,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAVault,
+        tokenBVault,
         user: user.publicKey,
         fromAccount: userAAccount,
         toAccount: userBAccount,
-        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        denylistProgram: null,
+        denylistAccount: null,
+        swapHookProgram: null,
+        referrerAccount: null,
+        referrerStats: null,
+        traderStats: null,
+        governanceStake: null,
+        wsolAccount: null,
+        systemProgram: null,
       })
       .rpc();
 
     console.log(`Swapped Token A for Token B with transaction: ${txHash}`);
 
     // Fetch the AMM account to verify updated reserves
-    const amm = await program.account.amm.fetch(ammAccount.publicKey);
+    const amm = await program.account.amm.fetch(ammAccount);
     console.log("AMM state after swap:", amm);
 
     assert(amm.tokenAReserve.toNumber() > 500, "Token A reserve should increase");
     assert(amm.tokenBReserve.toNumber() < 500, "Token B reserve should decrease");
   });
 
+  it("Requires the pool's oracle on every swap once it exists", async () => {
+    try {
+      await program.methods
+        .swap(new anchor.BN(10), true, new anchor.BN(0), null, null)
+        .accounts(swapAccounts({ oracle: null }))
+        .rpc();
+      assert.fail("Swap without the oracle should fail");
+    } catch (err) {
+      assert(err.toString().includes("OracleRequired"), "Swap should require the oracle");
+    }
+
+    // A swap in a later second records a new observation
+    const before = await program.account.oracle.fetch(oracle);
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    await program.methods
+      .swap(new anchor.BN(10), true, new anchor.BN(0), null, null)
+      .accounts(swapAccounts())
+      .rpc();
+    const after = await program.account.oracle.fetch(oracle);
+    assert.notEqual(after.index, before.index, "Swap should write the oracle");
+  });
+
+  it("Borrows and repays a flash loan in one transaction", async () => {
+    const before = await program.account.amm.fetch(ammAccount);
+
+    const borrowIx = await program.methods
+      .flashBorrow(new anchor.BN(100), true)
+      .accounts({
+        amm: ammAccount,
+        tokenAVault,
+        tokenBVault,
+        receiver: userAAccount,
+        user: user.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+    const repayIx = await program.methods
+      .flashRepay()
+      .accounts({
+        amm: ammAccount,
+        tokenAVault,
+        tokenBVault,
+        repayer: userAAccount,
+        user: user.publicKey,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+
+    const txHash = await program.provider.sendAndConfirm(new web3.Transaction().add(borrowIx, repayIx));
+    console.log(`Flash loan executed with transaction: ${txHash}`);
+
+    // The fee (rounded up) is credited to the token A reserve
+    const after = await program.account.amm.fetch(ammAccount);
+    assert.equal(after.tokenAReserve.toNumber(), before.tokenAReserve.toNumber() + 1, "Flash loan fee should be credited to LPs");
+    assert.equal(after.locked, false, "Pool should be unlocked after repayment");
+  });
+
+  it("Flash swaps token B and repays in token A through a receiver program", async () => {
+    // The receiver pays 40 token A back from the user's account; k after fees must not drop
+    const repayment = Buffer.alloc(16);
+    repayment.writeBigUInt64LE(BigInt(40), 0);
+    repayment.writeBigUInt64LE(BigInt(0), 8);
+    const receiverAccounts = [
+      { pubkey: userAAccount, isWritable: true, isSigner: false },
+      { pubkey: userBAccount, isWritable: true, isSigner: false },
+      { pubkey: mintA, isWritable: false, isSigner: false },
+      { pubkey: mintB, isWritable: false, isSigner: false },
+      { pubkey: anchor.utils.token.TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+    ];
+    const flashSwap = (remainingAccounts: web3.AccountMeta[]) =>
+      program.methods
+        .flashSwap(new anchor.BN(0), new anchor.BN(20), repayment)
+        .accounts({
+          oracle,
+          twamm: null,
+          amm: ammAccount,
+          tokenAVault,
+          tokenBVault,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          receiverA: userAAccount,
+          receiverB: userBAccount,
+          user: user.publicKey,
+          callbackProgram: receiverProgram.programId,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+
+    const before = await program.account.amm.fetch(ammAccount);
+    await flashSwap(receiverAccounts);
+    const after = await program.account.amm.fetch(ammAccount);
+
+    assert.equal(before.tokenBReserve.sub(after.tokenBReserve).toNumber(), 20, "Token B should be sent out");
+    assert(after.tokenAReserve.gt(before.tokenAReserve), "Token A should be paid in");
+    assert(
+      after.tokenAReserve.mul(after.tokenBReserve).gte(before.tokenAReserve.mul(before.tokenBReserve)),
+      "k should not decrease"
+    );
+    assert.equal(after.locked, false, "Pool should be unlocked after the flash swap");
+
+    // Handing the pool to the receiver as a writable account would let it re-enter
+    try {
+      await flashSwap([{ pubkey: ammAccount, isWritable: true, isSigner: false }, ...receiverAccounts]);
+      assert.fail("Flash swap passing the pool to the receiver should fail");
+    } catch (err) {
+      assert(err.toString().includes("InvalidInput"), "Pool should not be passed to the receiver");
+    }
+  });
+
+  it("Rejects flash_repay without an outstanding flash loan", async () => {
+    try {
+      await program.methods
+        .flashRepay()
+        .accounts({
+          amm: ammAccount,
+          tokenAVault,
+          tokenBVault,
+          repayer: userAAccount,
+          user: user.publicKey,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      assert.fail("flash_repay without a loan should fail");
+    } catch (err) {
+      assert(err.toString().includes("NoFlashLoanOutstanding"), "There is no loan to repay");
+    }
+  });
+
   it("Removes liquidity", async () => {
-    const shares = new anchor.BN(500);
+    const shares = new anchor.BN(250);
 
     // Remove liquidity from the AMM
     const txHash = await program.methods
       .removeLiquidity(shares)
       .accounts({
-        amm: ammAccount.publicKey,
+        amm: ammAccount,
+        oracle,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAReserveAccount: tokenAVault,
+        tokenBReserveAccount: tokenBVault,
         user: user.publicKey,
         userA: userAAccount,
         userB: userBAccount,
+        lpMint,
+        userLp: userLpAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
       })
       .rpc();
 
     console.log(`Liquidity removed with transaction: ${txHash}`);
 
     // Fetch the AMM account to verify updated reserves
-    const amm = await program.account.amm.fetch(ammAccount.publicKey);
+    const amm = await program.account.amm.fetch(ammAccount);
     console.log("AMM state after removing liquidity:", amm);
 
     assert(amm.tokenAReserve.toNumber() < 500, "Token A reserve should decrease");
     assert(amm.tokenBReserve.toNumber() < 500, "Token B reserve should decrease");
   });
 
+  it("Rejects withdrawals by wallets that do not hold the LP tokens", async () => {
+    const attacker = new web3.Keypair();
+    const attackerA = await createTokenAccount(mintA, attacker.publicKey);
+    const attackerB = await createTokenAccount(mintB, attacker.publicKey);
+    const attackerLp = await createTokenAccount(lpMint, attacker.publicKey);
+    const before = await program.account.amm.fetch(ammAccount);
+
+    const removeLiquidity = (userLp: web3.PublicKey) =>
+      program.methods
+        .removeLiquidity(new anchor.BN(100))
+        .accounts({
+          amm: ammAccount,
+          oracle,
+          twamm: null,
+          allowlistEntry: null,
+          tokenAReserveAccount: tokenAVault,
+          tokenBReserveAccount: tokenBVault,
+          user: attacker.publicKey,
+          userA: attackerA,
+          userB: attackerB,
+          lpMint,
+          userLp,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .signers([attacker])
+        .rpc();
+
+    // Shares are burned from the signer's own LP account, so an empty account cannot withdraw
+    try {
+      await removeLiquidity(attackerLp);
+      assert.fail("Withdrawal without LP tokens should fail");
+    } catch (err) {
+      assert(err.toString().includes("0x1"), "Burn should fail with insufficient funds");
+    }
+
+    // Someone else's LP account is rejected outright
+    try {
+      await removeLiquidity(userLpAccount);
+      assert.fail("Withdrawal from another wallet's LP account should fail");
+    } catch (err) {
+      assert(err.toString().includes("ConstraintTokenOwner"), "LP account should belong to the signer");
+    }
+
+    const after = await program.account.amm.fetch(ammAccount);
+    assert(after.tokenAReserve.eq(before.tokenAReserve), "Reserves should be untouched");
+    assert(after.totalShares.eq(before.totalShares), "Shares should be untouched");
+  });
+
+  it("Creates a farm with a reward stream", async () => {
+    await program.methods
+      .initializeFarm()
+      .accounts({
+        amm: ammAccount,
+        farm,
+        lpMint,
+        stakeVault,
+        user: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    rewardMint = await createMint(mintAuthority.publicKey);
+    [rewardVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("farm_reward_vault"), farm.toBuffer(), rewardMint.toBuffer()],
+      program.programId
+    );
+    userRewardAccount = await createTokenAccount(rewardMint, user.publicKey);
+    await mintTokens(rewardMint, userRewardAccount, mintAuthority, 10000);
+
+    // 10 reward tokens per second for the next minute
+    const endTime = new anchor.BN(Math.floor(Date.now() / 1000) + 60);
+    const txHash = await program.methods
+      .addFarmReward(new anchor.BN(10), endTime)
+      .accounts({
+        farm,
+        rewardMint,
+        rewardVault,
+        funderToken: userRewardAccount,
+        user: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    console.log(`Farm reward added with transaction: ${txHash}`);
+
+    const farmState = await program.account.farm.fetch(farm);
+    assert.equal(farmState.rewards.length, 1, "Farm should have one reward stream");
+  });
+
+  it("Stakes, harvests and unstakes LP tokens", async () => {
+    const [position] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("stake"), farm.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .stake(new anchor.BN(100))
+      .accounts({
+        farm,
+        position,
+        stakeVault,
+        lpMint,
+        ownerLp: userLpAccount,
+        owner: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    // Let rewards accrue
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const before = await program.provider.connection.getTokenAccountBalance(userRewardAccount);
+    await program.methods
+      .harvest()
+      .accounts({
+        farm,
+        position,
+        owner: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: rewardVault, isSigner: false, isWritable: true },
+        { pubkey: rewardMint, isSigner: false, isWritable: false },
+        { pubkey: userRewardAccount, isSigner: false, isWritable: true },
+      ])
+      .rpc();
+    const after = await program.provider.connection.getTokenAccountBalance(userRewardAccount);
+    assert(Number(after.value.amount) > Number(before.value.amount), "Harvest should pay out rewards");
+
+    await program.methods
+      .unstake(new anchor.BN(100))
+      .accounts({
+        farm,
+        position,
+        stakeVault,
+        lpMint,
+        ownerLp: userLpAccount,
+        owner: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const positionState = await program.account.stakePosition.fetch(position);
+    assert.equal(positionState.amount.toNumber(), 0, "Position should be fully unstaked");
+  });
+
+  it("Locks liquidity on a vesting schedule and claims its fees", async () => {
+    const lockId = new anchor.BN(0);
+    const [lock] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("lock"), ammAccount.toBuffer(), user.publicKey.toBuffer(), lockId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [escrow] = web3.PublicKey.findProgramAddressSync([Buffer.from("lock_escrow"), lock.toBuffer()], program.programId);
+
+    const lockLiquidity = (unlockStart: number, unlockEnd: number) =>
+      program.methods
+        .lockLiquidity(lockId, new anchor.BN(100), new anchor.BN(unlockStart), new anchor.BN(unlockEnd))
+        .accounts({
+          amm: ammAccount,
+          lock,
+          escrow,
+          lpMint,
+          ownerLp: userLpAccount,
+          owner: user.publicKey,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          systemProgram: web3.SystemProgram.programId,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .rpc();
+
+    // Locked shares earn fees, so a lock that starts vesting within a week is refused
+    const now = Math.floor(Date.now() / 1000);
+    const day = 86_400;
+    try {
+      await lockLiquidity(now - 60, now + 60);
+      assert.fail("A lock that vests right away should fail");
+    } catch (err) {
+      assert(err.toString().includes("InvalidInput"), "Short lock should be rejected");
+    }
+
+    // Vest linearly over a day, starting eight days from now
+    await lockLiquidity(now + 8 * day, now + 9 * day);
+    let amm = await program.account.amm.fetch(ammAccount);
+    assert.equal(amm.lockedShares.toNumber(), 100, "Pool should track the locked shares");
+
+    try {
+      await program.methods
+        .unlockLiquidity()
+        .accounts({
+          amm: ammAccount,
+          lock,
+          escrow,
+          lpMint,
+          ownerLp: userLpAccount,
+          owner: user.publicKey,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      assert.fail("Unlocking before the schedule starts should fail");
+    } catch (err) {
+      assert(err.toString().includes("LiquidityLocked"), "Nothing should have vested yet");
+    }
+    const lockState = await program.account.liquidityLock.fetch(lock);
+    assert.equal(lockState.shares.toNumber(), 100, "Every share should stay locked");
+
+    await program.methods
+      .claimLockedFees()
+      .accounts({
+        amm: ammAccount,
+        lock,
+        tokenAVault,
+        tokenBVault,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        ownerA: userAAccount,
+        ownerB: userBAccount,
+        owner: user.publicKey,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    amm = await program.account.amm.fetch(ammAccount);
+    assert.equal(amm.lockedShares.toNumber(), lockState.shares.toNumber(), "Pool should track the remaining locked shares");
+  });
+
+  it("Opens, collects and withdraws an NFT position", async () => {
+    const metadataProgram = new web3.PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+    const positionMint = new web3.Keypair();
+    const [position] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("position"), positionMint.publicKey.toBuffer()],
+      program.programId
+    );
+    const [positionToken] = web3.PublicKey.findProgramAddressSync(
+      [user.publicKey.toBuffer(), anchor.utils.token.TOKEN_PROGRAM_ID.toBuffer(), positionMint.publicKey.toBuffer()],
+      anchor.utils.token.ASSOCIATED_PROGRAM_ID
+    );
+    const [metadata] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), metadataProgram.toBuffer(), positionMint.publicKey.toBuffer()],
+      metadataProgram
+    );
+    const [masterEdition] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), metadataProgram.toBuffer(), positionMint.publicKey.toBuffer(), Buffer.from("edition")],
+      metadataProgram
+    );
+
+    const beforeOpen = await program.account.amm.fetch(ammAccount);
+    const txHash = await program.methods
+      .openPosition(new anchor.BN(100), new anchor.BN(100), "")
+      .accounts({
+        oracle,
+        twamm: null,
+        amm: ammAccount,
+        tokenAVault,
+        tokenBVault,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        userA: userAAccount,
+        userB: userBAccount,
+        positionMint: positionMint.publicKey,
+        position,
+        positionToken,
+        metadata,
+        masterEdition,
+        owner: user.publicKey,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        nftTokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        metadataProgram,
+        associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([positionMint])
+      .rpc();
+
+    console.log(`Position opened with transaction: ${txHash}`);
+
+    const opened = await program.account.position.fetch(position);
+    assert(opened.shares.toNumber() > 0, "Position should hold the minted shares");
+
+    // Position shares earn fees in their own bucket, apart from locked liquidity
+    const afterOpen = await program.account.amm.fetch(ammAccount);
+    assert.equal(afterOpen.positionShares.sub(beforeOpen.positionShares).toString(), opened.shares.toString(), "Pool should track the position shares");
+    assert.equal(afterOpen.lockedShares.toString(), beforeOpen.lockedShares.toString(), "Positions should not count as locked liquidity");
+
+    const positionAccounts = {
+      oracle,
+      twamm: null,
+      amm: ammAccount,
+      position,
+      positionToken,
+      tokenAVault,
+      tokenBVault,
+      tokenAMint: mintA,
+      tokenBMint: mintB,
+      ownerA: userAAccount,
+      ownerB: userBAccount,
+      owner: user.publicKey,
+      tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+    await program.methods.collectPositionFees().accounts(positionAccounts).rpc();
+    await program.methods.removePositionLiquidity(opened.shares).accounts(positionAccounts).rpc();
+
+    const withdrawn = await program.account.position.fetch(position);
+    assert.equal(withdrawn.shares.toNumber(), 0, "Position should be fully withdrawn");
+  });
+
+  it("Restricts a permissioned pool to allowlisted wallets", async () => {
+    const [allowlistEntry] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("allowlist"), ammAccount.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .setAllowlist(user.publicKey, Array(32).fill(0))
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+
+    const swapAccounts = (entry: web3.PublicKey | null) => ({
+      amm: ammAccount,
+      oracle,
+      twamm: null,
+      allowlistEntry: entry,
+      tokenAVault,
+      tokenBVault,
+      user: user.publicKey,
+      fromAccount: userAAccount,
+      toAccount: userBAccount,
+      tokenAMint: mintA,
+      tokenBMint: mintB,
+      tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      denylistProgram: null,
+      denylistAccount: null,
+      swapHookProgram: null,
+      referrerAccount: null,
+      referrerStats: null,
+      traderStats: null,
+      governanceStake: null,
+      wsolAccount: null,
+      systemProgram: null,
+    });
+
+    try {
+      await program.methods.swap(new anchor.BN(10), true, new anchor.BN(0), null, null).accounts(swapAccounts(null)).rpc();
+      assert.fail("Swap without an allowlist entry should fail");
+    } catch (err) {
+      assert(err.toString().includes("NotAllowlisted"), "Swap should be rejected as not allowlisted");
+    }
+
+    await program.methods
+      .addAllowlistEntry(user.publicKey)
+      .accounts({
+        amm: ammAccount,
+        allowlistEntry,
+        authority: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+    await program.methods.swap(new anchor.BN(10), true, new anchor.BN(0), null, null).accounts(swapAccounts(allowlistEntry)).rpc();
+
+    // Make the pool permissionless again for the remaining tests
+    await program.methods
+      .setAllowlist(web3.PublicKey.default, Array(32).fill(0))
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+  });
+
+  it("Runs the pool's swap hook before and after each swap", async () => {
+    const [hookConfig] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("config"), ammAccount.toBuffer()],
+      hookProgram.programId
+    );
+
+    // Trades are capped at 50 units and pay no fee through the hook
+    await hookProgram.methods
+      .initialize(ammAccount, new anchor.BN(50), new anchor.BN(0))
+      .accounts({
+        config: hookConfig,
+        authority: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+    await program.methods
+      .setSwapHook(hookProgram.programId)
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+
+    const swap = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), true, new anchor.BN(0), null, null)
+        .accounts({
+          amm: ammAccount,
+          oracle,
+          twamm: null,
+          allowlistEntry: null,
+          tokenAVault,
+          tokenBVault,
+          user: user.publicKey,
+          fromAccount: userAAccount,
+          toAccount: userBAccount,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          denylistProgram: null,
+          denylistAccount: null,
+          swapHookProgram: hookProgram.programId,
+          referrerAccount: null,
+          referrerStats: null,
+          traderStats: null,
+          governanceStake: null,
+          wsolAccount: null,
+          systemProgram: null,
+        })
+        .remainingAccounts([{ pubkey: hookConfig, isWritable: true, isSigner: false }])
+        .rpc();
+
+    try {
+      await swap(100);
+      assert.fail("Swap above the hook's limit should fail");
+    } catch (err) {
+      assert(err.toString().includes("TradeTooLarge"), "Swap should be rejected by the hook");
+    }
+
+    const before = await program.account.amm.fetch(ammAccount);
+    await swap(20);
+    const after = await program.account.amm.fetch(ammAccount);
+
+    // The fee override applies: the whole input joins the reserves
+    assert.equal(after.tokenAReserve.sub(before.tokenAReserve).toNumber(), 20, "Hook fee override should apply");
+    assert(after.accumulatedFeesA.eq(before.accumulatedFeesA), "No fee should be collected");
+
+    const config = await hookProgram.account.hookConfig.fetch(hookConfig);
+    assert.equal(config.trades.toNumber(), 1, "Hook should count the trade");
+    assert.equal(config.volume.toNumber(), 20, "Hook should count the volume");
+
+    // Remove the hook for the remaining tests
+    await program.methods
+      .setSwapHook(web3.PublicKey.default)
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+  });
+
+  it("Pays referrers a share of the protocol fee", async () => {
+    const [referrerStats] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("referrer"), ammAccount.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const referrerAccount = await createTokenAccount(mintA, user.publicKey);
+
+    // The referrer asks for half of the protocol fee; the pool caps referrals at 20%
+    await program.methods
+      .setReferralFeeCap(2000)
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+    await program.methods
+      .registerReferrer(5000)
+      .accounts({
+        amm: ammAccount,
+        referrerStats,
+        referrer: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    await program.methods
+      .swap(new anchor.BN(100), true, new anchor.BN(0), null, null)
+      .accounts({
+        amm: ammAccount,
+        oracle,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAVault,
+        tokenBVault,
+        user: user.publicKey,
+        fromAccount: userAAccount,
+        toAccount: userBAccount,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        denylistProgram: null,
+        denylistAccount: null,
+        swapHookProgram: null,
+        referrerAccount,
+        referrerStats,
+        traderStats: null,
+        governanceStake: null,
+        wsolAccount: null,
+        systemProgram: null,
+      })
+      .rpc();
+
+    const stats = await program.account.referrerStats.fetch(referrerStats);
+    assert.equal(stats.swaps.toNumber(), 1, "Referred swap should be counted");
+    assert.equal(stats.volumeA.toNumber(), 100, "Referred volume should be counted");
+
+    const balance = await program.provider.connection.getTokenAccountBalance(referrerAccount);
+    assert.equal(balance.value.amount, stats.feesEarnedA.toString(), "Referral fee should be paid out");
+  });
+
+  it("Discounts the fee for high-volume traders", async () => {
+    const [traderStats] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("trader_stats"), ammAccount.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .initializeTraderStats()
+      .accounts({
+        amm: ammAccount,
+        traderStats,
+        trader: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    // Any volume in the window waives the whole fee
+    await program.methods
+      .setFeeDiscounts([{ minVolume: new anchor.BN(1), minStaked: new anchor.BN(0), discountBps: 10000 }])
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+
+    const swap = () =>
+      program.methods
+        .swap(new anchor.BN(50), true, new anchor.BN(0), null, null)
+        .accounts({
+          amm: ammAccount,
+          oracle,
+          twamm: null,
+          allowlistEntry: null,
+          tokenAVault,
+          tokenBVault,
+          user: user.publicKey,
+          fromAccount: userAAccount,
+          toAccount: userBAccount,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          denylistProgram: null,
+          denylistAccount: null,
+          swapHookProgram: null,
+          referrerAccount: null,
+          referrerStats: null,
+          traderStats,
+          governanceStake: null,
+          wsolAccount: null,
+          systemProgram: null,
+        })
+        .rpc();
+
+    // The first swap builds up volume at the full fee
+    await swap();
+    const stats = await program.account.traderStats.fetch(traderStats);
+    const volume = stats.dailyVolume.reduce((total, day) => total.add(day), new anchor.BN(0));
+    assert(volume.gtn(0), "Swap should be counted toward the rolling volume");
+
+    // The second swap qualifies for the discount
+    const before = await program.account.amm.fetch(ammAccount);
+    await swap();
+    const after = await program.account.amm.fetch(ammAccount);
+    assert.equal(after.tokenAReserve.sub(before.tokenAReserve).toNumber(), 50, "Discounted swap should pay no fee");
+
+    // Clear the schedule for the remaining tests
+    await program.methods
+      .setFeeDiscounts([])
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+  });
+
+  it("Discounts the fee for governance stakers and locks their stake", async () => {
+    const [governanceStaking] = web3.PublicKey.findProgramAddressSync([Buffer.from("governance_staking")], program.programId);
+    const [governanceVault] = web3.PublicKey.findProgramAddressSync([Buffer.from("governance_vault")], program.programId);
+    const [governanceStake] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("governance_stake"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const governanceMint = await createMint(mintAuthority.publicKey);
+    const userGovernance = await createTokenAccount(governanceMint, user.publicKey);
+    await mintTokens(governanceMint, userGovernance, mintAuthority, 10_000);
+
+    // The fee-tier governance authority opens staking with a one-hour lockup
+    await program.methods
+      .initializeGovernanceStaking(new anchor.BN(3600))
+      .accounts({
+        feeTiers,
+        governanceStaking,
+        governanceMint,
+        vault: governanceVault,
+        authority: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    // Staking 1,000 governance tokens waives the whole fee
+    await program.methods
+      .setFeeDiscounts([{ minVolume: new anchor.BN(0), minStaked: new anchor.BN(1000), discountBps: 10000 }])
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+    const stakeAccounts = {
+      governanceStaking,
+      governanceMint,
+      vault: governanceVault,
+      ownerToken: userGovernance,
+      owner: user.publicKey,
+      tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .stakeGovernance(new anchor.BN(1000))
+      .accounts({ ...stakeAccounts, governanceStake, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    const stake = await program.account.governanceStake.fetch(governanceStake);
+    assert.equal(stake.amount.toNumber(), 1000, "Stake should be recorded");
+
+    // The quote reflects the discount only when the trader's stake is passed
+    const quote = (governanceStake: web3.PublicKey | null) =>
+      program.methods
+        .quoteSwap(new anchor.BN(50), true)
+        .accounts({ amm: ammAccount, twamm: null, tokenAMint: mintA, tokenBMint: mintB, traderStats: null, governanceStake })
+        .view();
+    const before = await program.account.amm.fetch(ammAccount);
+    const discounted = await quote(governanceStake);
+    assert.equal(discounted.feeAmount.toNumber(), 0, "Staker should be quoted no fee");
+    assert.equal(
+      discounted.amountOut.toNumber(),
+      amountOut(new anchor.BN(50), before.tokenAReserve, before.tokenBReserve, new anchor.BN(0)).toNumber(),
+      "Quote should use the discounted fee"
+    );
+    assert((await quote(null)).amountOut.lt(discounted.amountOut), "Quote without the stake should charge the fee");
+
+    const userBBefore = new anchor.BN((await program.provider.connection.getTokenAccountBalance(userBAccount)).value.amount);
+    await program.methods
+      .swap(new anchor.BN(50), true, new anchor.BN(0), null, null)
+      .accounts(swapAccounts({ governanceStake }))
+      .rpc();
+    const after = await program.account.amm.fetch(ammAccount);
+    const userBAfter = new anchor.BN((await program.provider.connection.getTokenAccountBalance(userBAccount)).value.amount);
+    assert.equal(after.tokenAReserve.sub(before.tokenAReserve).toNumber(), 50, "Staker should pay no fee");
+    assert.equal(userBAfter.sub(userBBefore).toString(), discounted.amountOut.toString(), "Swap should match the quote");
+
+    // The stake stays locked, so it cannot be staked for one trade and withdrawn in the same transaction
+    try {
+      await program.methods
+        .unstakeGovernance(new anchor.BN(1000))
+        .accounts({ ...stakeAccounts, governanceStake })
+        .rpc();
+      assert.fail("Unstaking during the lockup should fail");
+    } catch (err) {
+      assert(err.toString().includes("StakeLocked"), "Locked stake should not be withdrawn");
+    }
+
+    // Clear the schedule for the remaining tests
+    await program.methods
+      .setFeeDiscounts([])
+      .accounts({ amm: ammAccount, user: user.publicKey })
+      .rpc();
+  });
+
+  it("Routes a swap through two pools", async () => {
+    // A second pair, B/C, chained after the main A/B pool
+    const mintC = await createMint(mintAuthority.publicKey);
+    const userCAccount = await createTokenAccount(mintC, user.publicKey);
+    await mintTokens(mintA, userAAccount, mintAuthority, 1000);
+    await mintTokens(mintB, userBAccount, mintAuthority, 1000);
+    await mintTokens(mintC, userCAccount, mintAuthority, 1000);
+    const poolBC = await createPool(mintB, userBAccount, mintC, userCAccount, 2, 500);
+    const poolAC = await createPool(mintA, userAAccount, mintC, userCAccount, 2, 200);
+    const mainPool = await program.account.amm.fetch(ammAccount);
+    const mainHop = routeHop(ammAccount, tokenAVault, tokenBVault, mintA, mintB, oracle);
+    const bcHop = routeHop(poolBC.amm, poolBC.tokenAVault, poolBC.tokenBVault, poolBC.tokenAMint, poolBC.tokenBMint);
+    const acHop = routeHop(poolAC.amm, poolAC.tokenAVault, poolAC.tokenBVault, poolAC.tokenAMint, poolAC.tokenBMint);
+
+    const route = (hops: web3.AccountMeta[][], destination: web3.PublicKey, minimumOutput: anchor.BN) =>
+      program.methods
+        .swapRoute(new anchor.BN(40), minimumOutput)
+        .accounts({
+          source: userAAccount,
+          destination,
+          user: user.publicKey,
+        })
+        .remainingAccounts(hops.flat())
+        .rpc();
+
+    // Each hop must take the token the previous hop produced
+    try {
+      await route([mainHop, acHop], userCAccount, new anchor.BN(0));
+      assert.fail("A hop that does not trade the previous output should fail");
+    } catch (err) {
+      assert(err.toString().includes("InvalidRoute"), "Broken mint chain should be rejected");
+    }
+    try {
+      await route([mainHop, bcHop], userBAccount, new anchor.BN(0));
+      assert.fail("A destination that does not hold the final mint should fail");
+    } catch (err) {
+      assert(err.toString().includes("InvalidRoute"), "Wrong destination mint should be rejected");
+    }
+
+    // The main pool keeps its oracle mandatory on routes too
+    try {
+      await route([routeHop(ammAccount, tokenAVault, tokenBVault, mintA, mintB), bcHop], userCAccount, new anchor.BN(0));
+      assert.fail("Routing through the main pool without its oracle should fail");
+    } catch (err) {
+      assert(err.toString().includes("OracleRequired"), "Missing oracle should be rejected");
+    }
+
+    // Expected output: A -> B on the main pool, then B -> C on the second pool
+    const bcFromB = poolBC.tokenAMint.equals(mintB);
+    const amountB = amountOut(new anchor.BN(40), mainPool.tokenAReserve, mainPool.tokenBReserve, mainPool.fee);
+    const amountC = bcFromB
+      ? amountOut(amountB, poolBC.state.tokenAReserve, poolBC.state.tokenBReserve, poolBC.state.fee)
+      : amountOut(amountB, poolBC.state.tokenBReserve, poolBC.state.tokenAReserve, poolBC.state.fee);
+
+    // The slippage check applies to the end-to-end output
+    try {
+      await route([mainHop, bcHop], userCAccount, amountC.addn(1));
+      assert.fail("Route below the minimum output should fail");
+    } catch (err) {
+      assert(err.toString().includes("SlippageExceeded"), "Route should be rejected for slippage");
+    }
+
+    const balanceBefore = await program.provider.connection.getTokenAccountBalance(userCAccount);
+    await route([mainHop, bcHop], userCAccount, amountC);
+    const balanceAfter = await program.provider.connection.getTokenAccountBalance(userCAccount);
+    assert.equal(
+      new anchor.BN(balanceAfter.value.amount).sub(new anchor.BN(balanceBefore.value.amount)).toString(),
+      amountC.toString(),
+      "User should receive the chained output"
+    );
+
+    // Both pools traded: the intermediate B joined the second pool's reserves net of its fee
+    const mainAfter = await program.account.amm.fetch(ammAccount);
+    const bcAfter = await program.account.amm.fetch(poolBC.amm);
+    assert.equal(mainAfter.tokenBReserve.toString(), mainPool.tokenBReserve.sub(amountB).toString(), "Main pool should pay out B");
+    const bcReserveB = bcFromB ? bcAfter.tokenAReserve : bcAfter.tokenBReserve;
+    const bcReserveBBefore = bcFromB ? poolBC.state.tokenAReserve : poolBC.state.tokenBReserve;
+    const bcFee = amountB.mul(poolBC.state.fee).divn(10000);
+    assert.equal(bcReserveB.sub(bcReserveBBefore).toString(), amountB.sub(bcFee).toString(), "Second pool should receive the intermediate B");
+  });
+
+  it("Splits a swap across two fee tiers of the same pair", async () => {
+    // A 0.05% pool for the same pair next to the main 0.3% pool
+    await mintTokens(mintA, userAAccount, mintAuthority, 1000);
+    await mintTokens(mintB, userBAccount, mintAuthority, 1000);
+    const lowFeePool = await createPool(mintA, userAAccount, mintB, userBAccount, 1, 400);
+    const mainPool = await program.account.amm.fetch(ammAccount);
+    const legs = [
+      routeHop(ammAccount, tokenAVault, tokenBVault, mintA, mintB, oracle),
+      routeHop(lowFeePool.amm, lowFeePool.tokenAVault, lowFeePool.tokenBVault, mintA, mintB),
+    ];
+
+    const split = (allocations: number[], minimumOutput: anchor.BN) =>
+      program.methods
+        .swapSplit(new anchor.BN(50), allocations, minimumOutput)
+        .accounts({
+          source: userAAccount,
+          destination: userBAccount,
+          user: user.publicKey,
+        })
+        .remainingAccounts(legs.flat())
+        .rpc();
+
+    // Allocations must cover the whole order
+    try {
+      await split([6000, 3000], new anchor.BN(0));
+      assert.fail("Allocations that do not add up to 100% should fail");
+    } catch (err) {
+      assert(err.toString().includes("InvalidInput"), "Partial allocations should be rejected");
+    }
+
+    // 60% (30 A) trades on the main pool and 40% (20 A) on the low-fee pool
+    const mainOut = amountOut(new anchor.BN(30), mainPool.tokenAReserve, mainPool.tokenBReserve, mainPool.fee);
+    const lowFeeOut = amountOut(new anchor.BN(20), lowFeePool.state.tokenAReserve, lowFeePool.state.tokenBReserve, lowFeePool.state.fee);
+    const totalOut = mainOut.add(lowFeeOut);
+
+    // The minimum output applies to the sum of the legs
+    try {
+      await split([6000, 4000], totalOut.addn(1));
+      assert.fail("Split below the minimum output should fail");
+    } catch (err) {
+      assert(err.toString().includes("SlippageExceeded"), "Split should be rejected for slippage");
+    }
+
+    const balanceBefore = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    await split([6000, 4000], totalOut);
+    const balanceAfter = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    assert.equal(
+      new anchor.BN(balanceAfter.value.amount).sub(new anchor.BN(balanceBefore.value.amount)).toString(),
+      totalOut.toString(),
+      "User should receive the output of both legs"
+    );
+
+    // Each pool traded its share of the order
+    const mainAfter = await program.account.amm.fetch(ammAccount);
+    const lowFeeAfter = await program.account.amm.fetch(lowFeePool.amm);
+    assert.equal(mainAfter.tokenBReserve.toString(), mainPool.tokenBReserve.sub(mainOut).toString(), "Main pool should fill 60%");
+    assert.equal(
+      lowFeeAfter.tokenBReserve.toString(),
+      lowFeePool.state.tokenBReserve.sub(lowFeeOut).toString(),
+      "Low-fee pool should fill 40%"
+    );
+  });
+
+  it("Zaps a single token into the pool", async () => {
+    // A fresh 1% pool so the single-sided math starts from known reserves
+    await mintTokens(mintA, userAAccount, mintAuthority, 20000);
+    await mintTokens(mintB, userBAccount, mintAuthority, 20000);
+    const pool = await createPool(mintA, userAAccount, mintB, userBAccount, 3, 10000);
+    const { tokenAReserve, tokenBReserve, totalShares, fee } = pool.state;
+
+    // Expected split: swap part of the A for B, then deposit the rest alongside the swap output
+    const amountIn = new anchor.BN(1000);
+    const swapAmount = zapSwapAmount(amountIn, tokenAReserve, tokenBReserve, fee);
+    const swapOut = amountOut(swapAmount, tokenAReserve, tokenBReserve, fee);
+    const swapFee = swapAmount.mul(fee).divn(10000);
+    const reserveA = tokenAReserve.add(swapAmount).sub(swapFee);
+    const reserveB = tokenBReserve.sub(swapOut);
+    const sharesA = amountIn.sub(swapAmount).mul(totalShares).div(reserveA);
+    const sharesB = swapOut.mul(totalShares).div(reserveB);
+    const shares = BN.min(sharesA, sharesB);
+    assert(swapAmount.gtn(0) && swapAmount.lt(amountIn.divn(2)), "Less than half of the input should be swapped");
+
+    const deposit = (minSharesOut: anchor.BN) =>
+      program.methods
+        .depositSingle(amountIn, true, minSharesOut)
+        .accounts({
+          oracle: null,
+          twamm: null,
+          amm: pool.amm,
+          tokenAVault: pool.tokenAVault,
+          tokenBVault: pool.tokenBVault,
+          tokenAMint: pool.tokenAMint,
+          tokenBMint: pool.tokenBMint,
+          userToken: userAAccount,
+          lpMint: pool.lpMint,
+          userLp: pool.userLp,
+          user: user.publicKey,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    try {
+      await deposit(shares.addn(1));
+      assert.fail("Deposit below the minimum shares should fail");
+    } catch (err) {
+      assert(err.toString().includes("SlippageExceeded"), "Deposit should be rejected for slippage");
+    }
+
+    const lpBefore = await program.provider.connection.getTokenAccountBalance(pool.userLp);
+    const aBefore = await program.provider.connection.getTokenAccountBalance(userAAccount);
+    const bBefore = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    await deposit(shares);
+    const lpAfter = await program.provider.connection.getTokenAccountBalance(pool.userLp);
+    const aAfter = await program.provider.connection.getTokenAccountBalance(userAAccount);
+    const bAfter = await program.provider.connection.getTokenAccountBalance(userBAccount);
+
+    // Only token A leaves the wallet and the shares match the zap math
+    assert.equal(Number(aBefore.value.amount) - Number(aAfter.value.amount), 1000, "The whole input should be deposited");
+    assert.equal(bAfter.value.amount, bBefore.value.amount, "No token B should be needed");
+    assert.equal(
+      new anchor.BN(lpAfter.value.amount).sub(new anchor.BN(lpBefore.value.amount)).toString(),
+      shares.toString(),
+      "LP tokens should be minted for the zapped shares"
+    );
+
+    // The swapped B never leaves the vault, so only A's reserve grows (net of the swap fee)
+    const after = await program.account.amm.fetch(pool.amm);
+    assert.equal(after.tokenAReserve.toString(), tokenAReserve.add(amountIn).sub(swapFee).toString(), "Reserve A should take the input");
+    assert.equal(after.tokenBReserve.toString(), tokenBReserve.toString(), "Reserve B should be unchanged");
+    assert.equal(after.totalShares.toString(), totalShares.add(shares).toString(), "Total shares should grow by the minted shares");
+  });
+
+  it("Withdraws into a single token and in imbalanced amounts", async () => {
+    // A fresh 0.01% pool deep enough for the imbalance fee to round above zero
+    await mintTokens(mintA, userAAccount, mintAuthority, 1_000_000);
+    await mintTokens(mintB, userBAccount, mintAuthority, 1_000_000);
+    const pool = await createPool(mintA, userAAccount, mintB, userBAccount, 0, 1_000_000);
+    const poolAccounts = {
+      oracle: null,
+      twamm: null,
+      amm: pool.amm,
+      tokenAVault: pool.tokenAVault,
+      tokenBVault: pool.tokenBVault,
+      tokenAMint: pool.tokenAMint,
+      tokenBMint: pool.tokenBMint,
+      lpMint: pool.lpMint,
+      userLp: pool.userLp,
+      user: user.publicKey,
+      tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+
+    // Single token: burn 10% of the shares for both sides, then swap the B share back into A
+    let state = pool.state;
+    const shares = state.totalShares.divn(10);
+    const keptA = shares.mul(state.tokenAReserve).div(state.totalShares);
+    const unwantedB = shares.mul(state.tokenBReserve).div(state.totalShares);
+    const swappedA = amountOut(unwantedB, state.tokenBReserve.sub(unwantedB), state.tokenAReserve.sub(keptA), state.fee);
+    const singleOut = keptA.add(swappedA);
+    assert(swappedA.lt(unwantedB), "Swapping the unwanted side back should cost price impact and the fee");
+
+    const withdrawSingle = (minOut: anchor.BN) =>
+      program.methods
+        .withdrawSingle(shares, true, minOut)
+        .accounts({ ...poolAccounts, userToken: userAAccount })
+        .rpc();
+
+    try {
+      await withdrawSingle(singleOut.addn(1));
+      assert.fail("Withdrawal below the minimum output should fail");
+    } catch (err) {
+      assert(err.toString().includes("SlippageExceeded"), "Withdrawal should be rejected for slippage");
+    }
+
+    let aBefore = await program.provider.connection.getTokenAccountBalance(userAAccount);
+    let bBefore = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    await withdrawSingle(singleOut);
+    let aAfter = await program.provider.connection.getTokenAccountBalance(userAAccount);
+    let bAfter = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    assert.equal(
+      new anchor.BN(aAfter.value.amount).sub(new anchor.BN(aBefore.value.amount)).toString(),
+      singleOut.toString(),
+      "User should receive the kept and swapped A"
+    );
+    assert.equal(bAfter.value.amount, bBefore.value.amount, "No token B should be paid out");
+
+    // Imbalanced: take A only; each side's deviation from a proportional withdrawal pays half the fee
+    state = await program.account.amm.fetch(pool.amm);
+    const amountA = new anchor.BN(200_000);
+    const newA = state.tokenAReserve.sub(amountA);
+    const newB = state.tokenBReserve;
+    const d0 = isqrt(state.tokenAReserve.mul(state.tokenBReserve));
+    const d1 = isqrt(newA.mul(newB));
+    const idealA = d1.mul(state.tokenAReserve).div(d0);
+    const idealB = d1.mul(state.tokenBReserve).div(d0);
+    const feeA = idealA.sub(newA).abs().mul(state.fee).divn(20000);
+    const feeB = idealB.sub(newB).abs().mul(state.fee).divn(20000);
+    const d2 = isqrt(newA.sub(feeA).mul(newB.sub(feeB)));
+    const burned = state.totalShares.mul(d0.sub(d2)).add(d0.subn(1)).div(d0);
+    assert(feeA.gtn(0) && feeB.gtn(0), "Both sides should pay an imbalance fee");
+
+    const withdrawImbalanced = (maxSharesBurned: anchor.BN) =>
+      program.methods
+        .withdrawImbalanced(amountA, new anchor.BN(0), maxSharesBurned)
+        .accounts({ ...poolAccounts, userA: userAAccount, userB: userBAccount })
+        .rpc();
+
+    try {
+      await withdrawImbalanced(burned.subn(1));
+      assert.fail("Withdrawal burning more than the maximum shares should fail");
+    } catch (err) {
+      assert(err.toString().includes("SlippageExceeded"), "Withdrawal should be rejected for slippage");
+    }
+
+    const lpBefore = await program.provider.connection.getTokenAccountBalance(pool.userLp);
+    aBefore = await program.provider.connection.getTokenAccountBalance(userAAccount);
+    bBefore = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    await withdrawImbalanced(burned);
+    const lpAfter = await program.provider.connection.getTokenAccountBalance(pool.userLp);
+    aAfter = await program.provider.connection.getTokenAccountBalance(userAAccount);
+    bAfter = await program.provider.connection.getTokenAccountBalance(userBAccount);
+    assert.equal(Number(aAfter.value.amount) - Number(aBefore.value.amount), 200_000, "User should receive exactly the requested A");
+    assert.equal(bAfter.value.amount, bBefore.value.amount, "No token B should be paid out");
+    assert.equal(
+      new anchor.BN(lpBefore.value.amount).sub(new anchor.BN(lpAfter.value.amount)).toString(),
+      burned.toString(),
+      "Shares should be burned by the drop in sqrt(k)"
+    );
+
+    // The imbalance fees leave the reserves alongside the withdrawal
+    const after = await program.account.amm.fetch(pool.amm);
+    assert.equal(after.tokenAReserve.toString(), newA.sub(feeA).toString(), "Reserve A should drop by the amount and its fee");
+    assert.equal(after.tokenBReserve.toString(), newB.sub(feeB).toString(), "Reserve B should drop by its fee");
+  });
+
+  it("Places, cancels and cranks limit orders, sweeping donated dust", async () => {
+    const orderAccounts = (orderId: number) => {
+      const [order] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("limit_order"), ammAccount.toBuffer(), user.publicKey.toBuffer(), new anchor.BN(orderId).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const [escrow] = web3.PublicKey.findProgramAddressSync([Buffer.from("limit_order_escrow"), order.toBuffer()], program.programId);
+      return { order, escrow };
+    };
+    const place = (orderId: number, minAmountOut: number) => {
+      const { order, escrow } = orderAccounts(orderId);
+      return program.methods
+        .placeLimitOrder(new anchor.BN(orderId), true, new anchor.BN(20), new anchor.BN(minAmountOut))
+        .accounts({
+          amm: ammAccount,
+          order,
+          escrow,
+          sellMint: mintA,
+          ownerToken: userAAccount,
+          destination: userBAccount,
+          owner: user.publicKey,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          systemProgram: web3.SystemProgram.programId,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .rpc();
+    };
+    const crank = (orderId: number) => {
+      const { order, escrow } = orderAccounts(orderId);
+      return program.methods
+        .crankLimitOrders()
+        .accounts({
+          oracle,
+          twamm: null,
+          amm: ammAccount,
+          tokenAVault,
+          tokenBVault,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          keeperTokenA: userAAccount,
+          keeperTokenB: userBAccount,
+          keeper: user.publicKey,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts([
+          { pubkey: order, isWritable: true, isSigner: false },
+          { pubkey: escrow, isWritable: true, isSigner: false },
+          { pubkey: userBAccount, isWritable: true, isSigner: false },
+          { pubkey: user.publicKey, isWritable: true, isSigner: false },
+        ])
+        .rpc();
+    };
+    const balance = async (account: web3.PublicKey) =>
+      Number((await program.provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    // An order the pool cannot fill rests in its escrow through a crank
+    const resting = orderAccounts(1);
+    const aBeforePlace = await balance(userAAccount);
+    await place(1, 1_000_000);
+    assert.equal(aBeforePlace - (await balance(userAAccount)), 20, "Order amount should be escrowed");
+    await crank(1);
+    assert.equal(await balance(resting.escrow), 20, "Uncrossed order should stay in escrow");
+
+    // Once the pool is restricted, resting orders no longer fill; their owners can still cancel them
+    await program.methods.setAllowlist(user.publicKey, Array(32).fill(0)).accounts({ amm: ammAccount, user: user.publicKey }).rpc();
+    try {
+      await crank(1);
+      assert.fail("Cranking a restricted pool should fail");
+    } catch (err) {
+      assert(err.toString().includes("RestrictedPool"), "Restricted pool should refuse to fill limit orders");
+    }
+
+    // Dust sent to the escrow is refunded with the order and does not block its close
+    await mintTokens(mintA, resting.escrow, mintAuthority, 3);
+    const aBeforeCancel = await balance(userAAccount);
+    await program.methods
+      .cancelLimitOrder()
+      .accounts({
+        order: resting.order,
+        escrow: resting.escrow,
+        sellMint: mintA,
+        ownerToken: userAAccount,
+        owner: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    assert.equal((await balance(userAAccount)) - aBeforeCancel, 23, "Cancel should refund the order and the dust");
+    assert.equal(await program.provider.connection.getAccountInfo(resting.order), null, "Order should be closed");
+    assert.equal(await program.provider.connection.getAccountInfo(resting.escrow), null, "Escrow should be closed");
+    await program.methods.setAllowlist(web3.PublicKey.default, Array(32).fill(0)).accounts({ amm: ammAccount, user: user.publicKey }).rpc();
+
+    // A crossable order fills on the next crank even with dust in its escrow
+    const filled = orderAccounts(2);
+    await place(2, 1);
+    await mintTokens(mintA, filled.escrow, mintAuthority, 2);
+    const before = await program.account.amm.fetch(ammAccount);
+    const expectedOut = amountOut(new anchor.BN(20), before.tokenAReserve, before.tokenBReserve, before.fee);
+    const bBeforeCrank = await balance(userBAccount);
+    await crank(2);
+    const after = await program.account.amm.fetch(ammAccount);
+
+    // The user is both owner and keeper here, so the fill and the bounty land in the same account
+    assert.equal((await balance(userBAccount)) - bBeforeCrank, expectedOut.toNumber(), "Owner and keeper should receive the fill");
+    assert.equal(
+      after.tokenAReserve.sub(before.tokenAReserve).toNumber(),
+      20 - Math.floor((20 * before.fee.toNumber()) / 10000),
+      "Pool should book only the order amount"
+    );
+    assert.equal(await program.provider.connection.getAccountInfo(filled.order), null, "Filled order should be closed");
+    assert.equal(await program.provider.connection.getAccountInfo(filled.escrow), null, "Filled escrow should be closed");
+  });
+
+  it("Executes DCA cycles on schedule and closes the schedule after the last one", async () => {
+    const [dca] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("dca"), ammAccount.toBuffer(), user.publicKey.toBuffer(), new anchor.BN(1).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    const [escrow] = web3.PublicKey.findProgramAddressSync([Buffer.from("dca_escrow"), dca.toBuffer()], program.programId);
+    const balance = async (account: web3.PublicKey) =>
+      Number((await program.provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    // Two cycles of 20 A, two seconds apart
+    const aBefore = await balance(userAAccount);
+    await program.methods
+      .createDcaSchedule(new anchor.BN(1), true, new anchor.BN(20), new anchor.BN(2), new anchor.BN(2), new anchor.BN(1))
+      .accounts({
+        amm: ammAccount,
+        dca,
+        escrow,
+        sellMint: mintA,
+        ownerToken: userAAccount,
+        destination: userBAccount,
+        owner: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+    assert.equal(aBefore - (await balance(userAAccount)), 40, "Every cycle's input should be escrowed up front");
+
+    const executeCycle = () =>
+      program.methods
+        .executeDcaCycle()
+        .accounts({
+          dca,
+          escrow,
+          destination: userBAccount,
+          owner: user.publicKey,
+          amm: ammAccount,
+          oracle,
+          twamm: null,
+          tokenAVault,
+          tokenBVault,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          keeper: user.publicKey,
+          tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    // The first cycle is due immediately
+    let pool = await program.account.amm.fetch(ammAccount);
+    let expectedOut = amountOut(new anchor.BN(20), pool.tokenAReserve, pool.tokenBReserve, pool.fee);
+    let bBefore = await balance(userBAccount);
+    await executeCycle();
+    assert.equal((await balance(userBAccount)) - bBefore, expectedOut.toNumber(), "First cycle should pay the swap output");
+    const schedule = await program.account.dcaSchedule.fetch(dca);
+    assert.equal(schedule.cyclesRemaining.toNumber(), 1, "One cycle should remain");
+    assert.equal(await balance(escrow), 20, "Escrow should hold the last cycle");
+
+    // The next one waits for the interval
+    try {
+      await executeCycle();
+      assert.fail("A cycle before its interval should fail");
+    } catch (err) {
+      assert(err.toString().includes("DcaCycleNotDue"), "Early cycle should be rejected");
+    }
+
+    // A restricted pool runs no cycles, even for schedules created before the restriction
+    await program.methods.setAllowlist(user.publicKey, Array(32).fill(0)).accounts({ amm: ammAccount, user: user.publicKey }).rpc();
+    try {
+      await executeCycle();
+      assert.fail("A cycle on a restricted pool should fail");
+    } catch (err) {
+      assert(err.toString().includes("RestrictedPool"), "Restricted pool should refuse DCA cycles");
+    }
+    await program.methods.setAllowlist(web3.PublicKey.default, Array(32).fill(0)).accounts({ amm: ammAccount, user: user.publicKey }).rpc();
+
+    // Dust sent to the escrow does not block the final cycle from closing it
+    await mintTokens(mintA, escrow, mintAuthority, 3);
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+    pool = await program.account.amm.fetch(ammAccount);
+    expectedOut = amountOut(new anchor.BN(20), pool.tokenAReserve, pool.tokenBReserve, pool.fee);
+    bBefore = await balance(userBAccount);
+    await executeCycle();
+    const after = await program.account.amm.fetch(ammAccount);
+    assert.equal((await balance(userBAccount)) - bBefore, expectedOut.toNumber(), "Final cycle should pay the swap output");
+    assert.equal(
+      after.tokenAReserve.sub(pool.tokenAReserve).toNumber(),
+      20 - Math.floor((20 * pool.fee.toNumber()) / 10000),
+      "Pool should book only the cycle amount"
+    );
+    assert.equal(await program.provider.connection.getAccountInfo(dca), null, "Completed schedule should be closed");
+    assert.equal(await program.provider.connection.getAccountInfo(escrow), null, "Completed escrow should be closed");
+  });
+
+  it("Executes long-term orders across intervals, then cancels, withdraws and releases them", async () => {
+    // A fresh pool with long-term orders enabled and a minimum sell rate of 10 per slot on each side
+    const mintX = await createMint(mintAuthority.publicKey);
+    const mintY = await createMint(mintAuthority.publicKey);
+    const userX = await createTokenAccount(mintX, user.publicKey);
+    const userY = await createTokenAccount(mintY, user.publicKey);
+    await mintTokens(mintX, userX, mintAuthority, 1_000_000);
+    await mintTokens(mintY, userY, mintAuthority, 1_000_000);
+    const pool = await createPool(mintX, userX, mintY, userY, 2, 500_000);
+    const [twamm] = web3.PublicKey.findProgramAddressSync([Buffer.from("twamm"), pool.amm.toBuffer()], program.programId);
+    await program.methods
+      .initializeTwamm(new anchor.BN(10), new anchor.BN(10))
+      .accounts({ amm: pool.amm, twamm, user: user.publicKey, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+
+    // Settlement trades for order owners without screening or hooks, so the pool cannot be restricted
+    try {
+      await program.methods.setSwapHook(hookProgram.programId).accounts({ amm: pool.amm, user: user.publicKey }).rpc();
+      assert.fail("Hooking a pool with long-term orders should fail");
+    } catch (err) {
+      assert(err.toString().includes("TwammPoolRestricted"), "Restricting a TWAMM pool should be rejected");
+    }
+
+    // Long-term orders do not execute against a paused pool
+    await program.methods.pauseContract(true).accounts({ amm: pool.amm, user: user.publicKey }).rpc();
+    try {
+      await program.methods.executeLongTermOrders().accounts({ amm: pool.amm, oracle: null, twamm }).rpc();
+      assert.fail("Executing long-term orders on a paused pool should fail");
+    } catch (err) {
+      assert(err.toString().includes("ContractPaused"), "Paused pool should refuse to execute long-term orders");
+    }
+    await program.methods.pauseContract(false).accounts({ amm: pool.amm, user: user.publicKey }).rpc();
+
+    const orderAddress = (orderId: number) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("long_term_order"), pool.amm.toBuffer(), user.publicKey.toBuffer(), new anchor.BN(orderId).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0];
+    const vaultAccounts = {
+      amm: pool.amm,
+      oracle: null,
+      twamm,
+      tokenAVault: pool.tokenAVault,
+      tokenBVault: pool.tokenBVault,
+      tokenAMint: pool.tokenAMint,
+      tokenBMint: pool.tokenBMint,
+      tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+    };
+    const place = (orderId: number, sellTokenA: boolean, amount: number, intervals: number) =>
+      program.methods
+        .placeLongTermOrder(new anchor.BN(orderId), sellTokenA, new anchor.BN(amount), new anchor.BN(intervals))
+        .accounts({
+          ...vaultAccounts,
+          order: orderAddress(orderId),
+          ownerToken: sellTokenA ? pool.userA : pool.userB,
+          owner: user.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+    const orderAction = (orderId: number, sellTokenA: boolean) => ({
+      ...vaultAccounts,
+      order: orderAddress(orderId),
+      ownerSellToken: sellTokenA ? pool.userA : pool.userB,
+      ownerBuyToken: sellTokenA ? pool.userB : pool.userA,
+      owner: user.publicKey,
+    });
+    const balance = async (account: web3.PublicKey) =>
+      Number((await program.provider.connection.getTokenAccountBalance(account)).value.amount);
+    const waitForSlot = async (target: number) => {
+      while ((await program.provider.connection.getSlot()) < target) {
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+      }
+    };
+
+    // Orders below the minimum sell rate are rejected
+    try {
+      await place(0, true, 100, 1);
+      assert.fail("An order below the minimum sell rate should fail");
+    } catch (err) {
+      assert(err.toString().includes("LongTermOrderTooSmall"), "Dust order should be rejected");
+    }
+
+    // Order 1 sells A over two intervals, order 2 sells B over three
+    const aBeforePlace = await balance(pool.userA);
+    await place(1, true, 60_000, 2);
+    await place(2, false, 30_000, 3);
+    const order1 = await program.account.longTermOrder.fetch(orderAddress(1));
+    const order2 = await program.account.longTermOrder.fetch(orderAddress(2));
+    let state = await program.account.twammState.fetch(twamm);
+    assert.equal(state.sellRateA.toString(), order1.sellRate.toString(), "Order 1 should set the A sell rate");
+    assert.equal(state.sellRateB.toString(), order2.sellRate.toString(), "Order 2 should set the B sell rate");
+    const deposit1 = aBeforePlace - (await balance(pool.userA));
+    assert(deposit1 > 0 && deposit1 <= 60_000, "Order 1 should escrow a whole number of slots");
+    let amm = await program.account.amm.fetch(pool.amm);
+    assert.equal(amm.longTermBalanceA.toNumber(), deposit1, "Deposit should sit outside the reserves");
+
+    // Let execution cross an interval boundary, then withdraw order 1's proceeds so far
+    const firstBoundary = Math.ceil(state.lastVirtualOrderSlot.toNumber() / 150) * 150;
+    await waitForSlot(firstBoundary + 10);
+    const reservesBefore = amm;
+    let bBefore = await balance(pool.userB);
+    await program.methods.withdrawLongTermProceeds().accounts(orderAction(1, true)).rpc();
+    const partialProceeds = (await balance(pool.userB)) - bBefore;
+    state = await program.account.twammState.fetch(twamm);
+    amm = await program.account.amm.fetch(pool.amm);
+    assert(partialProceeds > 0, "Order 1 should have earned B so far");
+    assert(state.lastVirtualOrderSlot.toNumber() > firstBoundary, "Settlement should run past the interval boundary");
+    assert(!amm.tokenAReserve.eq(reservesBefore.tokenAReserve), "The net flow should trade against the pool");
+    assert(await program.provider.connection.getAccountInfo(orderAddress(1)), "A live order stays open after a withdrawal");
+
+    // Cancelling order 2 refunds the unsold B and pays the A it earned
+    const aBeforeCancel = await balance(pool.userA);
+    bBefore = await balance(pool.userB);
+    await program.methods.cancelLongTermOrder().accounts(orderAction(2, false)).rpc();
+    state = await program.account.twammState.fetch(twamm);
+    assert((await balance(pool.userB)) - bBefore > 0, "Unsold B should be refunded");
+    assert((await balance(pool.userA)) - aBeforeCancel > 0, "Earned A should be paid out");
+    assert.equal(state.sellRateB.toNumber(), 0, "Cancelled order should stop selling");
+    assert.equal(await program.provider.connection.getAccountInfo(orderAddress(2)), null, "Cancelled order should be closed");
+
+    // Once order 1 expires, anyone can release its expiry entry; the proceeds wait in the order
+    try {
+      await program.methods.releaseLongTermOrder().accounts({ amm: pool.amm, oracle: null, twamm, order: orderAddress(1) }).rpc();
+      assert.fail("Releasing a live order should fail");
+    } catch (err) {
+      assert(err.toString().includes("LongTermOrderNotExpired"), "Live order should not be released");
+    }
+    await waitForSlot(order1.expirySlot.toNumber() + 1);
+    await program.methods.releaseLongTermOrder().accounts({ amm: pool.amm, oracle: null, twamm, order: orderAddress(1) }).rpc();
+    const released = await program.account.longTermOrder.fetch(orderAddress(1));
+    state = await program.account.twammState.fetch(twamm);
+    assert(released.expiryReleased, "Order should be marked released");
+    assert(released.pendingProceeds.gtn(0), "Final proceeds should be held in the order");
+    assert(!state.expiries.some((entry) => entry.slot.eq(order1.expirySlot)), "Expiry entry should be freed");
+    assert.equal(state.sellRateA.toNumber(), 0, "Expired order should stop selling");
+
+    // The owner then withdraws the held proceeds and the order closes
+    bBefore = await balance(pool.userB);
+    await program.methods.withdrawLongTermProceeds().accounts(orderAction(1, true)).rpc();
+    assert.equal((await balance(pool.userB)) - bBefore, released.pendingProceeds.toNumber(), "Held proceeds should be paid out");
+    assert.equal(await program.provider.connection.getAccountInfo(orderAddress(1)), null, "Expired order should be closed");
+  }).timeout(400_000);
+
+  it("Rejects swaps past their deadline or above their price impact cap", async () => {
+    const before = await program.account.amm.fetch(ammAccount);
+    const slot = await program.provider.connection.getSlot();
+    const now = Math.floor(Date.now() / 1000);
+    const swap = (deadline: object | null, maxPriceImpactBps: number | null) =>
+      program.methods
+        .swap(new anchor.BN(50), true, new anchor.BN(0), deadline, maxPriceImpactBps)
+        .accounts(swapAccounts())
+        .rpc();
+
+    // Stale transactions are refused whether the deadline is a slot or a timestamp
+    try {
+      await swap({ slot: { 0: new anchor.BN(slot - 1) } }, null);
+      assert.fail("Swap past its slot deadline should fail");
+    } catch (err) {
+      assert(err.toString().includes("Expired"), "Swap should be rejected past its slot deadline");
+    }
+    try {
+      await swap({ unixTimestamp: { 0: new anchor.BN(now - 600) } }, null);
+      assert.fail("Swap past its timestamp deadline should fail");
+    } catch (err) {
+      assert(err.toString().includes("Expired"), "Swap should be rejected past its timestamp deadline");
+    }
+
+    // A 50 token trade moves the price by far more than 0.01%
+    try {
+      await swap(null, 1);
+      assert.fail("Swap above the price impact cap should fail");
+    } catch (err) {
+      assert(err.toString().includes("PriceImpactTooHigh"), "Swap should be rejected for price impact");
+    }
+
+    const unchanged = await program.account.amm.fetch(ammAccount);
+    assert(unchanged.tokenAReserve.eq(before.tokenAReserve), "Rejected swaps should not trade");
+    assert(unchanged.tokenBReserve.eq(before.tokenBReserve), "Rejected swaps should not trade");
+
+    // The same trade goes through with a live deadline and a cap it respects
+    await swap({ unixTimestamp: { 0: new anchor.BN(now + 600) } }, 5000);
+    const after = await program.account.amm.fetch(ammAccount);
+    assert(after.tokenAReserve.gt(before.tokenAReserve), "Swap within its limits should trade");
+  });
+
+  it("Refuses trades past the circuit breaker and pauses after consecutive breaching slots", async () => {
+    // A fresh pool so the price moves are measured from known reserves
+    const mintX = await createMint(mintAuthority.publicKey);
+    const mintY = await createMint(mintAuthority.publicKey);
+    const userX = await createTokenAccount(mintX, user.publicKey);
+    const userY = await createTokenAccount(mintY, user.publicKey);
+    await mintTokens(mintX, userX, mintAuthority, 200_000);
+    await mintTokens(mintY, userY, mintAuthority, 200_000);
+    const pool = await createPool(mintX, userX, mintY, userY, 2, 100_000);
+
+    // At most a 1% move per slot; moves above 0.5% count, and two breaching slots in a row pause the pool
+    await program.methods.setCircuitBreaker(100, 2).accounts({ amm: pool.amm, user: user.publicKey }).rpc();
+
+    const swap = (amountIn: number, fromAtoB: boolean) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), fromAtoB, new anchor.BN(0), null, null)
+        .accounts(
+          swapAccounts({
+            amm: pool.amm,
+            oracle: null,
+            tokenAVault: pool.tokenAVault,
+            tokenBVault: pool.tokenBVault,
+            fromAccount: fromAtoB ? pool.userA : pool.userB,
+            toAccount: fromAtoB ? pool.userB : pool.userA,
+            tokenAMint: pool.tokenAMint,
+            tokenBMint: pool.tokenBMint,
+          })
+        )
+        .rpc();
+    const nextSlot = () => new Promise((resolve) => setTimeout(resolve, 1000));
+
+    // A trade moving the price by about 10% is refused outright and counts nothing
+    try {
+      await swap(5_000, true);
+      assert.fail("A trade past the circuit breaker should fail");
+    } catch (err) {
+      assert(err.toString().includes("PriceMoveTooLarge"), "Trade should be refused by the circuit breaker");
+    }
+    let state = await program.account.amm.fetch(pool.amm);
+    assert(state.tokenAReserve.eq(pool.state.tokenAReserve), "Refused trade should not change the reserves");
+    assert.equal(state.breaches, 0, "Refused trade should not count as a breach");
+
+    // A trade moving it by about 0.7% executes and counts one breach; a calm slot resets the count
+    await swap(350, true);
+    state = await program.account.amm.fetch(pool.amm);
+    assert.equal(state.breaches, 1, "Executed trade above half the limit should count");
+    await nextSlot();
+    await swap(10, true);
+    state = await program.account.amm.fetch(pool.amm);
+    assert.equal(state.breaches, 0, "Calm trade should reset the count");
+
+    // Two breaching slots in a row trip the breaker
+    await swap(350, true);
+    await nextSlot();
+    await swap(350, false);
+    state = await program.account.amm.fetch(pool.amm);
+    assert.equal(state.breaches, 2, "Both slots should count");
+    assert(state.paused, "Pool should pause after consecutive breaching slots");
+    try {
+      await swap(10, true);
+      assert.fail("Swap on a tripped pool should fail");
+    } catch (err) {
+      assert(err.toString().includes("ContractPaused"), "Tripped pool should refuse swaps");
+    }
+
+    // Unpausing clears the trip
+    await program.methods.pauseContract(false).accounts({ amm: pool.amm, user: user.publicKey }).rpc();
+    state = await program.account.amm.fetch(pool.amm);
+    assert(!state.paused && state.breaches === 0, "Unpausing should clear the breaker");
+    await swap(10, true);
+  });
+
+  it("Credits only what the vault receives from a Token-2022 transfer-fee mint", async () => {
+    // A mixed pair: a Token-2022 mint withholding 1% of every transfer against a classic SPL Token mint
+    const feeMint = await createTransferFeeMint(mintAuthority.publicKey, 100);
+    const plainMint = await createMint(mintAuthority.publicKey);
+    const userFee = await createTokenAccount(feeMint, user.publicKey, TOKEN_2022_PROGRAM_ID);
+    const userPlain = await createTokenAccount(plainMint, user.publicKey);
+    await mintTokens(feeMint, userFee, mintAuthority, 1_000_000, TOKEN_2022_PROGRAM_ID);
+    await mintTokens(plainMint, userPlain, mintAuthority, 1_000_000);
+    const pool = await createPool(feeMint, userFee, plainMint, userPlain, 2, 100_000, TOKEN_2022_PROGRAM_ID);
+    const feeIsA = pool.tokenAMint.equals(feeMint);
+    const transferFee = (amount: number) => Math.ceil((amount * 100) / 10000);
+    const balance = async (account: web3.PublicKey) =>
+      Number((await program.provider.connection.getTokenAccountBalance(account)).value.amount);
+    type Reserves = { tokenAReserve: anchor.BN; tokenBReserve: anchor.BN };
+    const feeReserve = (state: Reserves) => (feeIsA ? state.tokenAReserve : state.tokenBReserve).toNumber();
+    const plainReserve = (state: Reserves) => (feeIsA ? state.tokenBReserve : state.tokenAReserve).toNumber();
+    const feeVault = feeIsA ? pool.tokenAVault : pool.tokenBVault;
+
+    // The deposit lost 1% on the way in and the reserve books only what arrived
+    assert.equal(feeReserve(pool.state), 100_000 - transferFee(100_000), "Reserve should exclude the transfer fee");
+    assert.equal(await balance(feeVault), feeReserve(pool.state), "Reserve should match the vault");
+    assert.equal(plainReserve(pool.state), 100_000, "The classic side should be credited in full");
+
+    const swap = (amountIn: number, sellFee: boolean, accounts: object = {}) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), sellFee === feeIsA, new anchor.BN(0), null, null)
+        .accounts(
+          swapAccounts({
+            amm: pool.amm,
+            oracle: null,
+            tokenAVault: pool.tokenAVault,
+            tokenBVault: pool.tokenBVault,
+            fromAccount: sellFee ? userFee : userPlain,
+            toAccount: sellFee ? userPlain : userFee,
+            tokenAMint: pool.tokenAMint,
+            tokenBMint: pool.tokenBMint,
+            tokenAProgram: pool.tokenAProgram,
+            tokenBProgram: pool.tokenBProgram,
+            ...accounts,
+          })
+        )
+        .rpc();
+
+    // Each side must come with the program that owns its mint
+    try {
+      await swap(10_000, true, { tokenAProgram: pool.tokenBProgram, tokenBProgram: pool.tokenAProgram });
+      assert.fail("A swap with the token programs swapped should fail");
+    } catch (err) {
+      assert(err.toString().includes("ConstraintMintTokenProgram"), "Mismatched token program should be rejected");
+    }
+
+    // Selling the fee token: the pool trades the 99% that reaches the vault
+    let state = await program.account.amm.fetch(pool.amm);
+    const received = 10_000 - transferFee(10_000);
+    const expectedOut = amountOut(new anchor.BN(received), new anchor.BN(feeReserve(state)), new anchor.BN(plainReserve(state)), state.fee);
+    const quote = (amountIn: number, sellFee: boolean) =>
+      program.methods
+        .quoteSwap(new anchor.BN(amountIn), sellFee === feeIsA)
+        .accounts({
+          amm: pool.amm,
+          twamm: null,
+          tokenAMint: pool.tokenAMint,
+          tokenBMint: pool.tokenBMint,
+          traderStats: null,
+          governanceStake: null,
+        })
+        .view();
+    assert.equal((await quote(10_000, true)).amountOut.toNumber(), expectedOut.toNumber(), "Quote should trade the amount received");
+    let feeVaultBefore = await balance(feeVault);
+    const plainBefore = await balance(userPlain);
+    await swap(10_000, true);
+    assert.equal((await balance(feeVault)) - feeVaultBefore, received, "Vault should receive the amount net of the transfer fee");
+    assert.equal((await balance(userPlain)) - plainBefore, expectedOut.toNumber(), "Output should be quoted on the amount received");
+
+    // Buying the fee token: the pool pays its quote and the user receives it net of the transfer fee
+    state = await program.account.amm.fetch(pool.amm);
+    const paidOut = amountOut(new anchor.BN(10_000), new anchor.BN(plainReserve(state)), new anchor.BN(feeReserve(state)), state.fee).toNumber();
+    assert.equal((await quote(10_000, false)).amountOut.toNumber(), paidOut - transferFee(paidOut), "Quote should be net of the output transfer fee");
+    feeVaultBefore = await balance(feeVault);
+    const feeBefore = await balance(userFee);
+    await swap(10_000, false);
+    assert.equal(feeVaultBefore - (await balance(feeVault)), paidOut, "Vault should pay out the quoted amount");
+    assert.equal((await balance(userFee)) - feeBefore, paidOut - transferFee(paidOut), "User should receive the output net of the transfer fee");
+  });
+
+  it("Grosses up a referral paid in a transfer-fee mint so the referrer nets its share", async () => {
+    const feeMint = await createTransferFeeMint(mintAuthority.publicKey, 100);
+    const plainMint = await createMint(mintAuthority.publicKey);
+    const userFee = await createTokenAccount(feeMint, user.publicKey, TOKEN_2022_PROGRAM_ID);
+    const userPlain = await createTokenAccount(plainMint, user.publicKey);
+    await mintTokens(feeMint, userFee, mintAuthority, 1_000_000, TOKEN_2022_PROGRAM_ID);
+    await mintTokens(plainMint, userPlain, mintAuthority, 1_000_000);
+    const pool = await createPool(feeMint, userFee, plainMint, userPlain, 2, 100_000, TOKEN_2022_PROGRAM_ID);
+    const feeIsA = pool.tokenAMint.equals(feeMint);
+    const feeVault = feeIsA ? pool.tokenAVault : pool.tokenBVault;
+    const transferFee = (amount: number) => Math.ceil((amount * 100) / 10000);
+    const balance = async (account: web3.PublicKey) =>
+      Number((await program.provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    const [referrerStats] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("referrer"), pool.amm.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const referrerAccount = await createTokenAccount(feeMint, user.publicKey, TOKEN_2022_PROGRAM_ID);
+    await program.methods
+      .setReferralFeeCap(2000)
+      .accounts({ amm: pool.amm, user: user.publicKey })
+      .rpc();
+    await program.methods
+      .registerReferrer(2000)
+      .accounts({
+        amm: pool.amm,
+        referrerStats,
+        referrer: user.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const feesBefore = pool.state[feeIsA ? "accumulatedFeesA" : "accumulatedFeesB"].toNumber();
+    const vaultBefore = await balance(feeVault);
+    const signature = await program.methods
+      .swap(new anchor.BN(100_000), feeIsA, new anchor.BN(0), null, null)
+      .accounts(
+        swapAccounts({
+          amm: pool.amm,
+          oracle: null,
+          tokenAVault: pool.tokenAVault,
+          tokenBVault: pool.tokenBVault,
+          fromAccount: userFee,
+          toAccount: userPlain,
+          tokenAMint: pool.tokenAMint,
+          tokenBMint: pool.tokenBMint,
+          tokenAProgram: pool.tokenAProgram,
+          tokenBProgram: pool.tokenBProgram,
+          referrerAccount,
+          referrerStats,
+        })
+      )
+      .rpc({ commitment: "confirmed" });
+
+    // The vault sent the grossed-up referral, which the pool deducted from its protocol fee
+    const state = await program.account.amm.fetch(pool.amm);
+    const received = 100_000 - transferFee(100_000);
+    const referralSent = received - ((await balance(feeVault)) - vaultBefore);
+    const protocolFee = state[feeIsA ? "accumulatedFeesA" : "accumulatedFeesB"].toNumber() - feesBefore + referralSent;
+    const share = Math.floor((protocolFee * 2000) / 10000);
+    assert(share > 0, "The swap should earn a referral");
+    assert.equal(referralSent - transferFee(referralSent), share, "The transfer should be grossed up by its fee");
+
+    // The referrer receives its full share, and the stats and event report that amount
+    assert.equal(await balance(referrerAccount), share, "Referrer should net its full share");
+    const stats = await program.account.referrerStats.fetch(referrerStats);
+    assert.equal(stats[feeIsA ? "feesEarnedA" : "feesEarnedB"].toNumber(), share, "Stats should record the amount received");
+    const tx = await program.provider.connection.getTransaction(signature, { commitment: "confirmed", maxSupportedTransactionVersion: 0 });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = [...parser.parseLogs(tx.meta.logMessages)].filter((event) => event.name === "SwapEvent");
+    assert.equal((events[0].data as any).referralFee.toNumber(), share, "Event should report the amount received");
+  });
+
+  it("Wraps native SOL into a pool and unwraps swap output back to lamports", async () => {
+    // A SOL pair seeded straight from the wallet's lamports
+    const otherMint = await createMint(mintAuthority.publicKey);
+    const userOther = await createTokenAccount(otherMint, user.publicKey);
+    await mintTokens(otherMint, userOther, mintAuthority, 1_000_000);
+    const pool = await createPool(NATIVE_MINT, null, otherMint, userOther, 2, 100_000);
+    const solIsA = pool.tokenAMint.equals(NATIVE_MINT);
+    const [solVault, solReserve, otherReserve] = solIsA
+      ? [pool.tokenAVault, pool.state.tokenAReserve, pool.state.tokenBReserve]
+      : [pool.tokenBVault, pool.state.tokenBReserve, pool.state.tokenAReserve];
+    const connection = program.provider.connection;
+    const tokenBalance = async (account: web3.PublicKey) => new anchor.BN((await connection.getTokenAccountBalance(account)).value.amount);
+    assert.equal(solReserve.toNumber(), 100_000, "Wrapped lamports should be credited to the reserve");
+    assert.equal((await tokenBalance(solVault)).toNumber(), 100_000, "Vault should hold the wrapped lamports");
+    assert.equal(await connection.getAccountInfo(wsolAccount), null, "Temporary wSOL account should be closed");
+
+    const swap = (amountIn: number, sellSol: boolean, accounts: object) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), sellSol === solIsA, new anchor.BN(0), null, null)
+        .accounts(
+          swapAccounts({
+            amm: pool.amm,
+            oracle: null,
+            tokenAVault: pool.tokenAVault,
+            tokenBVault: pool.tokenBVault,
+            tokenAMint: pool.tokenAMint,
+            tokenBMint: pool.tokenBMint,
+            wsolAccount,
+            systemProgram: web3.SystemProgram.programId,
+            ...accounts,
+          })
+        )
+        .rpc();
+    const feePaid = async (signature: string) =>
+      (await connection.getTransaction(signature, { commitment: "confirmed", maxSupportedTransactionVersion: 0 })).meta.fee;
+
+    // A side without a token account needs the wSOL account to go through
+    try {
+      await swap(10_000, true, { fromAccount: null, toAccount: userOther, wsolAccount: null, systemProgram: null });
+      assert.fail("A native SOL swap without the wSOL account should fail");
+    } catch (err) {
+      assert(err.toString().includes("InvalidNativeSol"), "Missing wSOL account should be rejected");
+    }
+
+    // SOL in: exactly the input leaves the wallet (besides the transaction fee) and the rent comes back
+    let state = await program.account.amm.fetch(pool.amm);
+    const solOut = amountOut(new anchor.BN(10_000), solReserve, otherReserve, state.fee);
+    let lamportsBefore = await connection.getBalance(user.publicKey, "confirmed");
+    const otherBefore = await tokenBalance(userOther);
+    let signature = await swap(10_000, true, { fromAccount: null, toAccount: userOther });
+    let lamportsAfter = await connection.getBalance(user.publicKey, "confirmed");
+    assert.equal(lamportsBefore - lamportsAfter, 10_000 + (await feePaid(signature)), "Wallet should pay the input in lamports");
+    assert.equal((await tokenBalance(userOther)).sub(otherBefore).toString(), solOut.toString(), "User should receive the quoted output");
+    assert.equal(await connection.getAccountInfo(wsolAccount), null, "Temporary wSOL account should be closed");
+
+    // SOL out: the output is unwrapped straight into the wallet
+    state = await program.account.amm.fetch(pool.amm);
+    const [solNow, otherNow] = solIsA ? [state.tokenAReserve, state.tokenBReserve] : [state.tokenBReserve, state.tokenAReserve];
+    const lamportsOut = amountOut(new anchor.BN(5_000), otherNow, solNow, state.fee).toNumber();
+    lamportsBefore = await connection.getBalance(user.publicKey, "confirmed");
+    signature = await swap(5_000, false, { fromAccount: userOther, toAccount: null });
+    lamportsAfter = await connection.getBalance(user.publicKey, "confirmed");
+    assert.equal(lamportsAfter - lamportsBefore, lamportsOut - (await feePaid(signature)), "Wallet should receive the output in lamports");
+    assert.equal(await connection.getAccountInfo(wsolAccount), null, "Temporary wSOL account should be closed");
+  });
+
+  it("Prices a 6/9-decimal pair in UI units and reports execution price and reserves in SwapEvent", async () => {
+    // One whole token of a 6-decimal mint against one thousandth of a whole token of a 9-decimal mint
+    const mintSix = await createMint(mintAuthority.publicKey, 6);
+    const mintNine = await createMint(mintAuthority.publicKey, 9);
+    const userSix = await createTokenAccount(mintSix, user.publicKey);
+    const userNine = await createTokenAccount(mintNine, user.publicKey);
+    await mintTokens(mintSix, userSix, mintAuthority, 2_000_000);
+    await mintTokens(mintNine, userNine, mintAuthority, 2_000_000);
+    const pool = await createPool(mintSix, userSix, mintNine, userNine, 2, 1_000_000);
+    const sixIsA = pool.tokenAMint.equals(mintSix);
+    const [decimalsA, decimalsB] = sixIsA ? [6, 9] : [9, 6];
+
+    // The pool stores both mints' decimals, and the initial shares are the geometric mean of the raw amounts
+    let state = pool.state;
+    assert.equal(state.tokenADecimals, decimalsA, "Pool should store token A's decimals");
+    assert.equal(state.tokenBDecimals, decimalsB, "Pool should store token B's decimals");
+    assert.equal(state.totalShares.toNumber(), 1_000_000, "Initial shares should not depend on the decimals");
+
+    // Equal raw reserves are 1 six-decimal token per 0.001 nine-decimal token
+    const uiPrice = await program.methods.quoteUiPrice().accounts({ amm: pool.amm, twamm: null }).view();
+    assert.equal(uiPrice.tokenADecimals, decimalsA, "View should report token A's decimals");
+    assert.equal(uiPrice.tokenBDecimals, decimalsB, "View should report token B's decimals");
+    assert.equal(
+      uiPrice.priceA.toString(),
+      decimalPrice(state.tokenAReserve, decimalsA, state.tokenBReserve, decimalsB).toString(),
+      "Price of A should be normalized by the decimals"
+    );
+    assert.equal(
+      uiPrice.priceB.toString(),
+      decimalPrice(state.tokenBReserve, decimalsB, state.tokenAReserve, decimalsA).toString(),
+      "Price of B should be normalized by the decimals"
+    );
+    const sixInNine = sixIsA ? uiPrice.priceA : uiPrice.priceB;
+    assert.equal(sixInNine.toString(), new anchor.BN(1).shln(64).divn(1000).toString(), "One six-decimal token should cost 0.001");
+
+    // A swap's event carries the amounts, the decimal-normalized execution price and the post-trade reserves
+    const amountIn = new anchor.BN(10_000);
+    const expectedOut = amountOut(amountIn, state.tokenAReserve, state.tokenBReserve, state.fee);
+    const signature = await program.methods
+      .swap(amountIn, true, new anchor.BN(0), null, null)
+      .accounts(
+        swapAccounts({
+          amm: pool.amm,
+          oracle: null,
+          tokenAVault: pool.tokenAVault,
+          tokenBVault: pool.tokenBVault,
+          fromAccount: pool.userA,
+          toAccount: pool.userB,
+          tokenAMint: pool.tokenAMint,
+          tokenBMint: pool.tokenBMint,
+        })
+      )
+      .rpc({ commitment: "confirmed" });
+    const tx = await program.provider.connection.getTransaction(signature, { commitment: "confirmed", maxSupportedTransactionVersion: 0 });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = [...parser.parseLogs(tx.meta.logMessages)].filter((event) => event.name === "SwapEvent");
+    assert.equal(events.length, 1, "Swap should emit one SwapEvent");
+    const event = events[0].data as any;
+    state = await program.account.amm.fetch(pool.amm);
+    assert(event.user.equals(user.publicKey), "Event should name the trader");
+    assert.equal(event.amountIn.toString(), amountIn.toString(), "Event should report the input");
+    assert.equal(event.amountOut.toString(), expectedOut.toString(), "Event should report the output");
+    assert.equal(event.fromAToB, true, "Event should report the direction");
+    assert.equal(
+      event.executionPrice.toString(),
+      decimalPrice(amountIn, decimalsA, expectedOut, decimalsB).toString(),
+      "Execution price should be A in B, normalized by the decimals"
+    );
+    assert.equal(event.reserveA.toString(), state.tokenAReserve.toString(), "Event should carry the post-trade A reserve");
+    assert.equal(event.reserveB.toString(), state.tokenBReserve.toString(), "Event should carry the post-trade B reserve");
+  });
+
+  it("Screens swaps and deposits through the pool's denylist program", async () => {
+    // A fresh pool screened by the sample denylist
+    const mintX = await createMint(mintAuthority.publicKey);
+    const mintY = await createMint(mintAuthority.publicKey);
+    const userX = await createTokenAccount(mintX, user.publicKey);
+    const userY = await createTokenAccount(mintY, user.publicKey);
+    await mintTokens(mintX, userX, mintAuthority, 100_000);
+    await mintTokens(mintY, userY, mintAuthority, 100_000);
+    const pool = await createPool(mintX, userX, mintY, userY, 2, 10_000);
+    const [denylist] = web3.PublicKey.findProgramAddressSync([Buffer.from("denylist"), pool.amm.toBuffer()], denylistProgram.programId);
+    await denylistProgram.methods
+      .initialize(pool.amm)
+      .accounts({ denylist, authority: user.publicKey, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    await program.methods.setDenylistProgram(denylistProgram.programId).accounts({ amm: pool.amm, user: user.publicKey }).rpc();
+
+    const swap = (screening: object) =>
+      program.methods
+        .swap(new anchor.BN(100), true, new anchor.BN(0), null, null)
+        .accounts(
+          swapAccounts({
+            amm: pool.amm,
+            oracle: null,
+            tokenAVault: pool.tokenAVault,
+            tokenBVault: pool.tokenBVault,
+            fromAccount: pool.userA,
+            toAccount: pool.userB,
+            tokenAMint: pool.tokenAMint,
+            tokenBMint: pool.tokenBMint,
+            ...screening,
+          })
+        )
+        .rpc();
+    const deposit = () =>
+      program.methods
+        .addLiquidity(new anchor.BN(100), new anchor.BN(100))
+        .accounts({
+          amm: pool.amm,
+          oracle: null,
+          twamm: null,
+          allowlistEntry: null,
+          tokenAReserveAccount: pool.tokenAVault,
+          tokenBReserveAccount: pool.tokenBVault,
+          user: user.publicKey,
+          userA: pool.userA,
+          userB: pool.userB,
+          lpMint: pool.lpMint,
+          userLp: pool.userLp,
+          tokenAMint: pool.tokenAMint,
+          tokenBMint: pool.tokenBMint,
+          tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          tokenAProgram: pool.tokenAProgram,
+          tokenBProgram: pool.tokenBProgram,
+          denylistProgram: denylistProgram.programId,
+          denylistAccount: denylist,
+          wsolAccount: null,
+          systemProgram: null,
+        })
+        .rpc();
+    const screened = { denylistProgram: denylistProgram.programId, denylistAccount: denylist };
+
+    // The screening program cannot be skipped
+    try {
+      await swap({});
+      assert.fail("A swap without the denylist program should fail");
+    } catch (err) {
+      assert(err.toString().includes("DenylistProgramRequired"), "Swap should require the screening program");
+    }
+
+    // Wallets not on the list trade and deposit as usual
+    await swap(screened);
+    await deposit();
+
+    // A blocked wallet can neither trade nor deposit
+    await denylistProgram.methods.block(user.publicKey).accounts({ denylist, authority: user.publicKey }).rpc();
+    const before = await program.account.amm.fetch(pool.amm);
+    try {
+      await swap(screened);
+      assert.fail("A blocked wallet's swap should fail");
+    } catch (err) {
+      assert(err.toString().includes("Denied"), "Swap should be rejected by the denylist");
+    }
+    try {
+      await deposit();
+      assert.fail("A blocked wallet's deposit should fail");
+    } catch (err) {
+      assert(err.toString().includes("Denied"), "Deposit should be rejected by the denylist");
+    }
+    const after = await program.account.amm.fetch(pool.amm);
+    assert(after.tokenAReserve.eq(before.tokenAReserve), "Rejected calls should not change the reserves");
+    assert(after.totalShares.eq(before.totalShares), "Rejected deposit should not mint shares");
+
+    // Unblocking restores access
+    await denylistProgram.methods.unblock(user.publicKey).accounts({ denylist, authority: user.publicKey }).rpc();
+    await swap(screened);
+  });
+
+  it("Reports a TWAP after the reserves change", async () => {
+    // Let some time pass so the window covers the post-swap reserves
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const twap = await program.methods
+      .observeTwap(1, 0)
+      .accounts({
+        amm: ammAccount,
+        oracle,
+      })
+      .view();
+
+    console.log("TWAP over the last second:", twap);
+    assert(twap.priceATwap.gt(new anchor.BN(0)), "TWAP price should be positive");
+  });
+
+  // Accounts for a plain swap from the user's token A account on the main pool; tests override what they exercise
+  function swapAccounts(overrides: object = {}) {
+    return {
+      amm: ammAccount,
+      oracle,
+      twamm: null,
+      allowlistEntry: null,
+      tokenAVault,
+      tokenBVault,
+      user: user.publicKey,
+      fromAccount: userAAccount,
+      toAccount: userBAccount,
+      tokenAMint: mintA,
+      tokenBMint: mintB,
+      tokenAProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      tokenBProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+      denylistProgram: null,
+      denylistAccount: null,
+      swapHookProgram: null,
+      referrerAccount: null,
+      referrerStats: null,
+      traderStats: null,
+      governanceStake: null,
+      wsolAccount: null,
+      systemProgram: null,
+      ...overrides,
+    };
+  }
+
+  // Create a pool for a mint pair at a fee tier and seed it with `liquidity` of each token from the user's accounts;
+  // a side without an account is paid in native SOL
+  async function createPool(
+    mintX: web3.PublicKey,
+    userX: web3.PublicKey | null,
+    mintY: web3.PublicKey,
+    userY: web3.PublicKey | null,
+    feeTier: number,
+    liquidity: number,
+    programX: web3.PublicKey = anchor.utils.token.TOKEN_PROGRAM_ID,
+    programY: web3.PublicKey = anchor.utils.token.TOKEN_PROGRAM_ID
+  ) {
+    const ordered = Buffer.compare(mintX.toBuffer(), mintY.toBuffer()) < 0;
+    const [tokenAMint, tokenBMint] = ordered ? [mintX, mintY] : [mintY, mintX];
+    const [userA, userB] = ordered ? [userX, userY] : [userY, userX];
+    const [tokenAProgram, tokenBProgram] = ordered ? [programX, programY] : [programY, programX];
+    const [amm] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("amm"), tokenAMint.toBuffer(), tokenBMint.toBuffer(), Buffer.from([feeTier])],
+      program.programId
+    );
+    const [poolAVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), amm.toBuffer(), tokenAMint.toBuffer()],
+      program.programId
+    );
+    const [poolBVault] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), amm.toBuffer(), tokenBMint.toBuffer()],
+      program.programId
+    );
+    const [poolLpMint] = web3.PublicKey.findProgramAddressSync([Buffer.from("lp_mint"), amm.toBuffer()], program.programId);
+
+    await program.methods
+      .initialize(feeTier)
+      .accounts({
+        feeTiers,
+        amm,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault: poolAVault,
+        tokenBVault: poolBVault,
+        lpMint: poolLpMint,
+        user: user.publicKey,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram,
+        tokenBProgram,
+        systemProgram: web3.SystemProgram.programId,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+    const userLp = await createTokenAccount(poolLpMint, user.publicKey);
+    await program.methods
+      .addLiquidity(new anchor.BN(liquidity), new anchor.BN(liquidity))
+      .accounts({
+        amm,
+        oracle: null,
+        twamm: null,
+        allowlistEntry: null,
+        tokenAReserveAccount: poolAVault,
+        tokenBReserveAccount: poolBVault,
+        user: user.publicKey,
+        userA,
+        userB,
+        lpMint: poolLpMint,
+        userLp,
+        tokenAMint,
+        tokenBMint,
+        tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+        tokenAProgram,
+        tokenBProgram,
+        denylistProgram: null,
+        denylistAccount: null,
+        wsolAccount: userA && userB ? null : wsolAccount,
+        systemProgram: userA && userB ? null : web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const state = await program.account.amm.fetch(amm);
+    return {
+      amm,
+      tokenAMint,
+      tokenBMint,
+      tokenAVault: poolAVault,
+      tokenBVault: poolBVault,
+      tokenAProgram,
+      tokenBProgram,
+      lpMint: poolLpMint,
+      userA,
+      userB,
+      userLp,
+      state,
+    };
+  }
+
+  // The [amm, token_a_vault, token_b_vault, token_a_mint, token_b_mint, token_a_program, token_b_program, oracle]
+  // accounts of one route hop or split leg
+  function routeHop(
+    amm: web3.PublicKey,
+    vaultA: web3.PublicKey,
+    vaultB: web3.PublicKey,
+    tokenAMint: web3.PublicKey,
+    tokenBMint: web3.PublicKey,
+    hopOracle: web3.PublicKey | null = null,
+    tokenAProgram: web3.PublicKey = anchor.utils.token.TOKEN_PROGRAM_ID,
+    tokenBProgram: web3.PublicKey = anchor.utils.token.TOKEN_PROGRAM_ID
+  ): web3.AccountMeta[] {
+    return [
+      { pubkey: amm, isWritable: true, isSigner: false },
+      { pubkey: vaultA, isWritable: true, isSigner: false },
+      { pubkey: vaultB, isWritable: true, isSigner: false },
+      { pubkey: tokenAMint, isWritable: false, isSigner: false },
+      { pubkey: tokenBMint, isWritable: false, isSigner: false },
+      { pubkey: tokenAProgram, isWritable: false, isSigner: false },
+      { pubkey: tokenBProgram, isWritable: false, isSigner: false },
+      // Pools without an oracle take the program ID in its place
+      { pubkey: hopOracle ?? program.programId, isWritable: hopOracle !== null, isSigner: false },
+    ];
+  }
+
+  // Constant-product output after the fee (basis points), rounded down like the program
+  function amountOut(amountIn: anchor.BN, reserveIn: anchor.BN, reserveOut: anchor.BN, fee: anchor.BN): anchor.BN {
+    const amountInWithFee = amountIn.mul(new anchor.BN(10000).sub(fee));
+    return amountInWithFee.mul(reserveOut).div(reserveIn.muln(10000).add(amountInWithFee));
+  }
+
+  // Price of `base` in `quote` as Q64.64 after normalizing both by their mint decimals, rounded like the program
+  function decimalPrice(base: anchor.BN, baseDecimals: number, quote: anchor.BN, quoteDecimals: number): anchor.BN {
+    const raw = quote.shln(64).div(base);
+    const scale = new anchor.BN(10).pow(new anchor.BN(Math.abs(baseDecimals - quoteDecimals)));
+    return baseDecimals >= quoteDecimals ? raw.mul(scale) : raw.div(scale);
+  }
+
+  // Largest swap amount whose leftover input still covers the post-swap ratio, searched like the program's zap
+  function zapSwapAmount(amountIn: anchor.BN, reserveIn: anchor.BN, reserveOut: anchor.BN, fee: anchor.BN): anchor.BN {
+    let low = new anchor.BN(0);
+    let high = amountIn;
+    while (low.lt(high)) {
+      const mid = low.add(high.sub(low).addn(1).divn(2));
+      const out = amountOut(mid, reserveIn, reserveOut, fee);
+      const newReserveIn = reserveIn.add(mid).sub(mid.mul(fee).divn(10000));
+      const newReserveOut = reserveOut.sub(out);
+      if (amountIn.sub(mid).mul(newReserveOut).gte(out.mul(newReserveIn))) {
+        low = mid;
+      } else {
+        high = mid.subn(1);
+      }
+    }
+    return low;
+  }
+
+  // Integer square root (floor), matching the program's sqrt_u128
+  function isqrt(x: anchor.BN): anchor.BN {
+    if (x.ltn(2)) {
+      return x;
+    }
+    let estimate = new anchor.BN(1).shln(Math.ceil(x.bitLength() / 2));
+    for (;;) {
+      const next = estimate.add(x.div(estimate)).divn(2);
+      if (next.gte(estimate)) {
+        return estimate;
+      }
+      estimate = next;
+    }
+  }
+
+  // Temporary wSOL account the program wraps and unwraps the user's native SOL through
+  const wsolAccount = web3.PublicKey.findProgramAddressSync([Buffer.from("wsol"), pg.wallet.publicKey.toBuffer()], program.programId)[0];
+
   // Helper functions for mints and token accounts
-  async function createMint(mintAuthority: web3.PublicKey): Promise<web3.PublicKey> {
+  async function createMint(mintAuthority: web3.PublicKey, decimals: number = 6): Promise<web3.PublicKey> {
     const mint = new web3.Keypair();
     const tx = new web3.Transaction();
     tx.add(
@@ -157,7 +2534,7 @@ describe("AMM Contract Tests", () => {
       }),
       anchor.utils.token.createInitializeMintInstruction(
         mint.publicKey,
-        6,
+        decimals,
         mintAuthority,
         null,
         anchor.utils.token.TOKEN_PROGRAM_ID
@@ -168,41 +2545,72 @@ describe("AMM Contract Tests", () => {
     return mint.publicKey;
   }
 
-  async function createTokenAccount(mint: web3.PublicKey, owner: web3.PublicKey): Promise<web3.PublicKey> {
+  // A Token-2022 mint whose every transfer withholds `feeBasisPoints` of the amount (uncapped)
+  async function createTransferFeeMint(mintAuthority: web3.PublicKey, feeBasisPoints: number): Promise<web3.PublicKey> {
+    const mint = new web3.Keypair();
+    // Base mint padded to the account size, the account type byte, then the TransferFeeConfig TLV entry
+    const space = 165 + 1 + 4 + 108;
+    const initializeTransferFeeConfig = Buffer.alloc(2 + 1 + 1 + 2 + 8);
+    initializeTransferFeeConfig.writeUInt8(26, 0); // TransferFeeExtension
+    initializeTransferFeeConfig.writeUInt8(0, 1); // InitializeTransferFeeConfig
+    initializeTransferFeeConfig.writeUInt8(0, 2); // No fee config authority
+    initializeTransferFeeConfig.writeUInt8(0, 3); // No withdraw withheld authority
+    initializeTransferFeeConfig.writeUInt16LE(feeBasisPoints, 4);
+    initializeTransferFeeConfig.writeBigUInt64LE(BigInt("18446744073709551615"), 6);
+    const tx = new web3.Transaction();
+    tx.add(
+      web3.SystemProgram.createAccount({
+        fromPubkey: program.provider.publicKey,
+        newAccountPubkey: mint.publicKey,
+        space,
+        lamports: await program.provider.connection.getMinimumBalanceForRentExemption(space),
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      new web3.TransactionInstruction({
+        programId: TOKEN_2022_PROGRAM_ID,
+        keys: [{ pubkey: mint.publicKey, isWritable: true, isSigner: false }],
+        data: initializeTransferFeeConfig,
+      }),
+      anchor.utils.token.createInitializeMintInstruction(mint.publicKey, 6, mintAuthority, null, TOKEN_2022_PROGRAM_ID)
+    );
+
+    await pg.provider.sendAndConfirm(tx, [mint]);
+    return mint.publicKey;
+  }
+
+  async function createTokenAccount(
+    mint: web3.PublicKey,
+    owner: web3.PublicKey,
+    tokenProgram: web3.PublicKey = anchor.utils.token.TOKEN_PROGRAM_ID
+  ): Promise<web3.PublicKey> {
     const tokenAccount = new web3.Keypair();
+    // Token-2022 accounts of a transfer-fee mint carry the account type byte and a TransferFeeAmount TLV entry
+    const space = tokenProgram.equals(TOKEN_2022_PROGRAM_ID) ? 165 + 1 + 4 + 8 : anchor.utils.token.AccountLayout.span;
     const tx = new web3.Transaction();
     tx.add(
       web3.SystemProgram.createAccount({
         fromPubkey: program.provider.publicKey,
         newAccountPubkey: tokenAccount.publicKey,
-        space: anchor.utils.token.AccountLayout.span,
-        lamports: await anchor.utils.token.getMinBalanceRentForExemptAccount(program.provider.connection),
-        programId: anchor.utils.token.TOKEN_PROGRAM_ID,
+        space,
+        lamports: await program.provider.connection.getMinimumBalanceForRentExemption(space),
+        programId: tokenProgram,
       }),
-      anchor.utils.token.createInitializeAccountInstruction(
-        tokenAccount.publicKey,
-        mint,
-        owner,
-        anchor.utils.token.TOKEN_PROGRAM_ID
-      )
+      anchor.utils.token.createInitializeAccountInstruction(tokenAccount.publicKey, mint, owner, tokenProgram)
     );
 
     await pg.provider.sendAndConfirm(tx, [tokenAccount]);
     return tokenAccount.publicKey;
   }
 
-  async function mintTokens(mint: web3.PublicKey, destination: web3.PublicKey, mintAuthority: web3.Keypair, amount: number) {
+  async function mintTokens(
+    mint: web3.PublicKey,
+    destination: web3.PublicKey,
+    mintAuthority: web3.Keypair,
+    amount: number,
+    tokenProgram: web3.PublicKey = anchor.utils.token.TOKEN_PROGRAM_ID
+  ) {
     const tx = new web3.Transaction();
-    tx.add(
-      anchor.utils.token.createMintToInstruction(
-        mint,
-        destination,
-        mintAuthority.publicKey,
-        amount,
-        [],
-        anchor.utils.token.TOKEN_PROGRAM_ID
-      )
-    );
+    tx.add(anchor.utils.token.createMintToInstruction(mint, destination, mintAuthority.publicKey, amount, [], tokenProgram));
 
     await pg.provider.sendAndConfirm(tx, [mintAuthority]);
   }